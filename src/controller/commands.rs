@@ -1,42 +1,222 @@
 use controller::input::*;
+use controller::keymap::{Binding, parse_keys};
+use controller::operators::Verb;
+use data::editor_state::{Mode, TextObject, VISUAL_MODES};
 
-use termion::event::{Event, Key};
+use termion::event::Key;
 
-pub fn event_to_fn_alias(event: &Event) -> FnAlias {
-    match event {
-        &Event::Key(Key::Char('f')) => FnAlias::FindNext,
-        _ => FnAlias::NoOp,
-    }
-}
+// The built-in key bindings of each mode, which make up the default Keymap.
+pub fn default_bindings() -> Vec<(Mode, Vec<Key>, Binding)> {
+    // Motions, which move the cursor in Navigate mode and move one end of the
+    // selection in the Visual modes
+    let motions = vec![
+        // Functions, which take the next key as their argument
+        ("f", Binding::Function(FnAlias::FindNext)),
+        ("F", Binding::Function(FnAlias::FindPrev)),
+        ("t", Binding::Function(FnAlias::TillNext)),
+        ("T", Binding::Function(FnAlias::TillPrev)),
+
+        // Sequences of more than one key
+        ("gj", Binding::Operator(Action::DisplayRowDown)),
+        ("gk", Binding::Operator(Action::DisplayRowUp)),
+        ("]c", Binding::Operator(Action::NextHunk)),
+        ("[c", Binding::Operator(Action::PrevHunk)),
 
-pub fn build_op_from_event(event: &Event) -> Option<ExecutableExpr> {
-    match *event {
         // Basic, directional navigation
-        Event::Key(Key::Char('h')) | Event::Key(Key::Left) => 
-            Option::from(ExecutableExpr::Operator(Action::Left)),
-        Event::Key(Key::Char('l')) | Event::Key(Key::Right) => 
-            Option::from(ExecutableExpr::Operator(Action::Right)),
-        Event::Key(Key::Char('j')) | Event::Key(Key::Down) => 
-            Option::from(ExecutableExpr::Operator(Action::Down)),
-        Event::Key(Key::Char('k')) | Event::Key(Key::Up) => 
-            Option::from(ExecutableExpr::Operator(Action::Up)),
+        ("h", Binding::Operator(Action::Left)),
+        ("<Left>", Binding::Operator(Action::Left)),
+        ("l", Binding::Operator(Action::Right)),
+        ("<Right>", Binding::Operator(Action::Right)),
+        ("j", Binding::Operator(Action::Down)),
+        ("<Down>", Binding::Operator(Action::Down)),
+        ("k", Binding::Operator(Action::Up)),
+        ("<Up>", Binding::Operator(Action::Up)),
 
         // Content-aware navigation
-        Event::Key(Key::Char('w')) => 
-            Option::from(ExecutableExpr::Operator(Action::StartNextWord)),
-        Event::Key(Key::Char('b')) => 
-            Option::from(ExecutableExpr::Operator(Action::StartPrevWord)),
-        Event::Key(Key::Char('^')) => 
-            Option::from(ExecutableExpr::Operator(Action::StartOfLine)),
-        Event::Key(Key::Char('$')) => 
-            Option::from(ExecutableExpr::Operator(Action::EndOfLine)),
-
-        Event::Key(Key::Char(';')) => 
-            Option::from(ExecutableExpr::Operator(Action::ToCommandMode)),
-        Event::Key(Key::Char('q')) => 
-            Option::from(ExecutableExpr::Operator(Action::ExitEditor)),
-
-
-        _ => None,
+        ("w", Binding::Operator(Action::StartNextWord)),
+        ("b", Binding::Operator(Action::StartPrevWord)),
+        ("e", Binding::Operator(Action::EndOfWord)),
+        ("ge", Binding::Operator(Action::EndOfPrevWord)),
+        ("W", Binding::Operator(Action::StartNextBigWord)),
+        ("B", Binding::Operator(Action::StartPrevBigWord)),
+        ("E", Binding::Operator(Action::EndOfBigWord)),
+        ("gE", Binding::Operator(Action::EndOfPrevBigWord)),
+        ("0", Binding::Operator(Action::StartOfLine)),
+        ("<Home>", Binding::Operator(Action::StartOfLine)),
+        ("^", Binding::Operator(Action::FirstNonBlank)),
+        ("$", Binding::Operator(Action::EndOfLine)),
+        ("<End>", Binding::Operator(Action::EndOfLine)),
+        ("|", Binding::Operator(Action::Column)),
+        (")", Binding::Operator(Action::NextSentence)),
+        ("(", Binding::Operator(Action::PrevSentence)),
+        ("}", Binding::Operator(Action::NextParagraph)),
+        ("{", Binding::Operator(Action::PrevParagraph)),
+        ("%", Binding::Operator(Action::MatchingBracket)),
+
+        // Whole lines, by number or relative to the screen
+        ("gg", Binding::Operator(Action::FirstLine)),
+        ("G", Binding::Operator(Action::LastLine)),
+        ("H", Binding::Operator(Action::ScreenTop)),
+        ("M", Binding::Operator(Action::ScreenMiddle)),
+        ("L", Binding::Operator(Action::ScreenBottom)),
+
+        // Repeating the last f, F, t or T
+        (";", Binding::Operator(Action::RepeatFind)),
+        (",", Binding::Operator(Action::RepeatFindReverse)),
+
+        // Marks, set with `m`
+        ("`", Binding::Function(FnAlias::JumpToMark)),
+        ("'", Binding::Function(FnAlias::JumpToMarkLine)),
+    ];
+
+    let navigate = vec![
+        // Verbs, which act on the text covered by the motion typed after them. Typing
+        // the last key of a verb again (`dd`, `guu`) makes it act on whole lines.
+        ("d", Binding::Verb(Verb::Delete)),
+        ("c", Binding::Verb(Verb::Change)),
+        ("y", Binding::Verb(Verb::Yank)),
+        (">", Binding::Verb(Verb::ShiftRight)),
+        ("<lt>", Binding::Verb(Verb::ShiftLeft)),
+        ("gu", Binding::Verb(Verb::Lowercase)),
+        ("gU", Binding::Verb(Verb::Uppercase)),
+        ("g~", Binding::Verb(Verb::ToggleCase)),
+        ("=", Binding::Verb(Verb::Reindent)),
+
+        // Registers, which a command can name with `"x` before it
+        ("\"", Binding::Function(FnAlias::SelectRegister)),
+        ("p", Binding::Operator(Action::PutAfter)),
+        ("P", Binding::Operator(Action::PutBefore)),
+        ("gp", Binding::Operator(Action::PutAfterMovingPast)),
+        ("gP", Binding::Operator(Action::PutBeforeMovingPast)),
+
+        // Short forms of a verb with its motion
+        ("x", Binding::Edit(Verb::Delete, Action::Right)),
+        ("s", Binding::Edit(Verb::Change, Action::Right)),
+
+        (".", Binding::Operator(Action::RepeatChange)),
+
+        // Marks and the jump list. Terminals send Ctrl-i as Tab.
+        ("m", Binding::Function(FnAlias::SetMark)),
+        ("<C-o>", Binding::Operator(Action::JumpOlder)),
+        ("<C-i>", Binding::Operator(Action::JumpNewer)),
+        ("<Tab>", Binding::Operator(Action::JumpNewer)),
+
+        ("v", Binding::Operator(Action::ToVisualMode)),
+        ("V", Binding::Operator(Action::ToVisualLineMode)),
+        ("<C-v>", Binding::Operator(Action::ToVisualBlockMode)),
+        ("gv", Binding::Operator(Action::Reselect)),
+        // Macros, recorded into a register with `qx` ... `q` and played with `@x`
+        ("q", Binding::Function(FnAlias::RecordMacro)),
+        ("@", Binding::Function(FnAlias::PlayMacro)),
+
+        (":", Binding::Operator(Action::ToCommandMode)),
+        ("i", Binding::Operator(Action::ToInsertMode)),
+        ("a", Binding::Operator(Action::Append)),
+        ("A", Binding::Operator(Action::AppendToLine)),
+        ("I", Binding::Operator(Action::InsertAtFirstNonBlank)),
+        ("o", Binding::Operator(Action::OpenLineBelow)),
+        ("O", Binding::Operator(Action::OpenLineAbove)),
+        ("ZQ", Binding::Operator(Action::ExitEditor)),
+    ];
+
+    // In the Visual modes, verbs act on the selection as soon as they're typed
+    let visual = vec![
+        ("d", Binding::Verb(Verb::Delete)),
+        ("x", Binding::Verb(Verb::Delete)),
+        ("c", Binding::Verb(Verb::Change)),
+        ("s", Binding::Verb(Verb::Change)),
+        ("y", Binding::Verb(Verb::Yank)),
+        (">", Binding::Verb(Verb::ShiftRight)),
+        ("<lt>", Binding::Verb(Verb::ShiftLeft)),
+        ("u", Binding::Verb(Verb::Lowercase)),
+        ("gu", Binding::Verb(Verb::Lowercase)),
+        ("U", Binding::Verb(Verb::Uppercase)),
+        ("gU", Binding::Verb(Verb::Uppercase)),
+        ("~", Binding::Verb(Verb::ToggleCase)),
+        ("g~", Binding::Verb(Verb::ToggleCase)),
+        ("=", Binding::Verb(Verb::Reindent)),
+        ("\"", Binding::Function(FnAlias::SelectRegister)),
+
+        ("o", Binding::Operator(Action::SwapSelectionEnds)),
+        ("v", Binding::Operator(Action::ToVisualMode)),
+        ("V", Binding::Operator(Action::ToVisualLineMode)),
+        ("<C-v>", Binding::Operator(Action::ToVisualBlockMode)),
+        ("<Esc>", Binding::Operator(Action::ToNavigateMode)),
+    ];
+
+    // Keys with a meaning of their own while typing text or a command line. Any
+    // other key is typed as it is.
+    let insert = vec![
+        ("<Esc>", Binding::Operator(Action::ToNavigateMode)),
+        ("<BS>", Binding::Operator(Action::DeleteCharBefore)),
+        ("<Tab>", Binding::Operator(Action::InsertTab)),
+        ("<Left>", Binding::Operator(Action::Left)),
+        ("<Right>", Binding::Operator(Action::Right)),
+    ];
+
+    let command = vec![
+        ("<CR>", Binding::Operator(Action::RunCommandLine)),
+        ("<Esc>", Binding::Operator(Action::ToNavigateMode)),
+        ("<BS>", Binding::Operator(Action::DeleteCharBefore)),
+    ];
+
+    // Typing into every line of the block, before or after it
+    let visual_block = vec![
+        ("I", Binding::Operator(Action::BlockInsert)),
+        ("A", Binding::Operator(Action::BlockAppend)),
+    ];
+
+    let parse = |keys: &str| parse_keys(keys, "").unwrap();
+    let mut bindings: Vec<(Mode, Vec<Key>, Binding)> = vec![];
+    for &(keys, ref binding) in motions.iter().chain(navigate.iter()) {
+        bindings.push((Mode::Navigate, parse(keys), binding.clone()));
+    }
+    for &mode in VISUAL_MODES {
+        for &(keys, ref binding) in motions.iter().chain(visual.iter()) {
+            bindings.push((mode, parse(keys), binding.clone()));
+        }
+        // Text objects select what they cover
+        for (keys, object) in default_text_objects() {
+            bindings.push((mode, keys, Binding::TextObject(object)));
+        }
+    }
+    for &(keys, ref binding) in visual_block.iter() {
+        bindings.push((Mode::VisualBlock, parse(keys), binding.clone()));
+    }
+    for &(keys, ref binding) in insert.iter() {
+        bindings.push((Mode::Insert, parse(keys), binding.clone()));
+    }
+    for &(keys, ref binding) in command.iter() {
+        bindings.push((Mode::Command, parse(keys), binding.clone()));
+    }
+    bindings
+}
+
+// The text objects which can follow a verb, e.g. the `iw` of `ciw`.
+pub fn default_text_objects() -> Vec<(Vec<Key>, TextObject)> {
+    let mut objects = vec![];
+    for &(prefix, around) in [('i', false), ('a', true)].iter() {
+        let mut add = |keys: &str, object: TextObject| {
+            objects.push((parse_keys(&format!("{}{}", prefix, keys), "").unwrap(), object));
+        };
+        add("w", TextObject::Word { around, big: false });
+        add("W", TextObject::Word { around, big: true });
+        add("s", TextObject::Sentence { around });
+        add("p", TextObject::Paragraph { around });
+        add("t", TextObject::Tag { around });
+        add("i", TextObject::IndentBlock { around });
+
+        for &quote in ['"', '\'', '`'].iter() {
+            add(&quote.to_string(), TextObject::Quoted { quote, around });
+        }
+
+        // Each pair of brackets can be given by either bracket, and some by a letter
+        let brackets = [('(', ')', vec!["(", ")", "b"]), ('[', ']', vec!["[", "]"]), ('{', '}', vec!["{", "}", "B"]), ('<', '>', vec!["<lt>", ">"])];
+        for &(open, close, ref keys) in brackets.iter() {
+            for keys in keys.iter() {
+                add(keys, TextObject::Bracketed { open, close, around });
+            }
+        }
     }
+    objects
 }
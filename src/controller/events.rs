@@ -1,20 +1,35 @@
-use std::collections::HashMap;
-use std::io::{stdin, stdout};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, stdout};
+use std::path::PathBuf;
+use std::sync::mpsc::channel;
+use std::time::{Duration, SystemTime};
 
 use termion::raw::IntoRawMode;
-use termion::input::TermRead;
-use termion::event::Event;
+use termion::screen::AlternateScreen;
+use termion::event::{Event, Key};
 
-use syntect::easy::HighlightLines;
+use syntect::highlighting::Theme;
+use syntect::parsing::SyntaxDefinition;
 
-use data::editor_state::{StateApi, EditorState, Mode};
+use data::editor_state::{StateApi, EditorState, Mode, VISUAL_MODES};
+use data::io::write_file;
 use controller::input::{ModeInputHandler, NavigateModeInputHandler, CommandModeInputHandler, InsertModeInputHandler};
+use controller::keymap::format_keys;
+use controller::keytrie::Lookup;
+use controller::macros::{Typeahead, type_copies};
+use controller::sources::*;
+use view::backend::{TermionBackend, restore_terminal_on_panic};
 use view::terminal::*;
 
 pub struct InputModeMultiplexer {
-    mapping: HashMap<Mode, Box<ModeInputHandler>>
+    mapping: HashMap<Mode, Box<ModeInputHandler>>,
+    pending_keys: Vec<Key>,  // Typed keys which are the start of a key mapping
 }
 
+// Key mappings which expand to themselves, directly or not, are given up on after
+// this many expansions.
+const MAX_MAPPING_EXPANSIONS: usize = 1000;
+
 impl InputModeMultiplexer {
     pub fn new() -> InputModeMultiplexer {
         let mut mode_mappings: HashMap<Mode, Box<ModeInputHandler>> = HashMap::new();
@@ -22,9 +37,14 @@ impl InputModeMultiplexer {
         mode_mappings.insert(Mode::Navigate, Box::new(NavigateModeInputHandler::new()));
         mode_mappings.insert(Mode::Command, Box::new(CommandModeInputHandler::new()));
         mode_mappings.insert(Mode::Insert, Box::new(InsertModeInputHandler::new()));
+        // The Visual modes share Navigate mode's motions, each with bindings of its own
+        for &mode in VISUAL_MODES {
+            mode_mappings.insert(mode, Box::new(NavigateModeInputHandler::new()));
+        }
 
         InputModeMultiplexer {
-            mapping: mode_mappings
+            mapping: mode_mappings,
+            pending_keys: vec![],
         }
     }
 
@@ -36,32 +56,257 @@ impl InputModeMultiplexer {
     }
 
     pub fn do_action_for_input(&mut self, event: Event, state: &mut EditorState) -> &Vec<char> {
-        // We get the correct handler for the node, and forward the input character on to that.
-        // The handler deals with internal state management, command composition etc.
+        // Every key typed goes into the macro being recorded, whatever the mode
+        if let (Event::Key(key), Some(&mut (_, ref mut keys))) = (&event, state.recording.as_mut()) {
+            keys.push(*key);
+        }
+
+        // A key press while the output panel is open just dismisses it
+        if state.get_panel().is_some() {
+            state.close_panel();
+            return self.get_command_buffer(state);
+        }
+
+        match event {
+            Event::Key(key) => self.resolve_mappings(key, state),
+            event => {
+                let mode_handler = self.mapping.get_mut(&state.get_mode()).unwrap();
+                mode_handler.handle_input(event, state);
+            }
+        }
+        self.play_typeahead(state);
+        self.get_command_buffer(state)
+    }
+
+    // Types the keys queued up by a macro, as though they'd been typed one by one.
+    // Macros played by these keys put theirs at the front of the queue.
+    pub fn play_typeahead(&mut self, state: &mut EditorState) {
+        while let Some(entry) = state.typeahead.pop_front() {
+            match entry {
+                Typeahead::Key(key, remap) => {
+                    let mut queue = VecDeque::new();
+                    queue.push_back((key, remap));
+                    self.process_queue(queue, state);
+                },
+                Typeahead::Copies(keys, count) => type_copies(keys, count, state),
+            }
+        }
+    }
+
+    // Applies the user's key mappings to the typed key, passing the resulting keys
+    // on to the handler for the current mode. Keys which might be the start of a
+    // mapping are held back until the next key, or the timeout, decides it.
+    fn resolve_mappings(&mut self, key: Key, state: &mut EditorState) {
+        let mut queue: VecDeque<(Key, bool)> = VecDeque::new();  // Keys and whether they may be remapped
+        queue.push_back((key, true));
+        self.process_queue(queue, state);
+    }
+
+    fn process_queue(&mut self, mut queue: VecDeque<(Key, bool)>, state: &mut EditorState) {
+        let mut expansions = 0;
+
+        while let Some((key, remap)) = queue.pop_front() {
+            let mode = *state.get_mode();
+            let literal = self.mapping.get(&mode).unwrap().awaiting_argument();
+            if !remap || (literal && self.pending_keys.is_empty()) {
+                self.send_key(key, state);
+                continue;
+            }
+
+            self.pending_keys.push(key);
+            let complete = match state.keymap.mapping(mode, &self.pending_keys) {
+                Lookup::Prefix | Lookup::Ambiguous(_) => false,
+                Lookup::Match(_) | Lookup::NoMatch => true,
+            };
+            if !complete {
+                continue;
+            }
+
+            if self.expand_pending(&mut queue, state) {
+                expansions += 1;
+                if expansions > MAX_MAPPING_EXPANSIONS {
+                    state.set_status_message(String::from("Recursive mapping"));
+                    self.pending_keys.clear();
+                    return;
+                }
+            }
+        }
+    }
+
+    // Replaces the longest mapping at the start of the pending keys with its rhs, at
+    // the front of the queue, followed by whatever came after it to be looked at
+    // again. Without one, the first key is sent as typed. Returns true if a
+    // mapping was expanded.
+    fn expand_pending(&mut self, queue: &mut VecDeque<(Key, bool)>, state: &EditorState) -> bool {
+        let mode = *state.get_mode();
+        let pending: Vec<Key> = self.pending_keys.drain(..).collect();
+        let longest_match = state.keymap.longest_mapping(mode, &pending)
+                                         .map(|(length, mapping)| (length, mapping.clone()));
+
+        let (consumed, replacement, expanded) = match longest_match {
+            Some((length, mapping)) => {
+                let remap_rhs = !mapping.noremap;
+                (length, mapping.rhs.into_iter().map(|key| (key, remap_rhs)).collect(), true)
+            },
+            None => (1, vec![(pending[0], false)], false),
+        };
+        for &key in pending[consumed..].iter().rev() {
+            queue.push_front((key, true));
+        }
+        for entry in replacement.into_iter().rev() {
+            queue.push_front(entry);
+        }
+        expanded
+    }
+
+    // Whether the keys typed so far could go either way, depending on whether
+    // anything else is typed within `timeoutlen`.
+    pub fn awaiting_timeout(&self, state: &EditorState) -> bool {
+        !self.pending_keys.is_empty() || self.mapping.get(state.get_mode()).unwrap().awaiting_timeout()
+    }
+
+    // Nothing was typed in time, so the keys held back are taken as they are.
+    pub fn timeout(&mut self, state: &mut EditorState) {
+        while !self.pending_keys.is_empty() {
+            let mut queue = VecDeque::new();
+            self.expand_pending(&mut queue, state);
+            self.process_queue(queue, state);
+        }
+        let mode_handler = self.mapping.get_mut(state.get_mode()).unwrap();
+        mode_handler.timeout(state);
+    }
+
+    // The keys of a command or mapping which is still being typed
+    pub fn pending_keys(&self, state: &EditorState) -> Vec<Key> {
+        let mut keys = self.mapping.get(state.get_mode()).unwrap().pending_keys();
+        keys.extend(self.pending_keys.iter().cloned());
+        keys
+    }
+
+    fn send_key(&mut self, key: Key, state: &mut EditorState) {
         let mode_handler = self.mapping.get_mut(&state.get_mode()).unwrap();
-        mode_handler.handle_input(event, state)
+        mode_handler.handle_input(Event::Key(key), state);
     }
 
 }
 
-pub fn event_loop(highlighter: &mut HighlightLines, state: &mut EditorState) {
+// Everything the event loop can be woken up by. Any subsystem holding a
+// Sender<EditorEvent> can post to the loop without blocking it.
+pub enum EditorEvent {
+    Input(Event),
+    Resize,
+    FileChanged(PathBuf, Option<SystemTime>),  // The file's new modification time, if it's still there
+    Timer(TimerId, TimerKind),
+    JobFinished { name: String, result: Result<String, String> },
+}
+
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(4);
+const FILE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Runs until the editor is quit, or the terminal can no longer be drawn on.
+pub fn event_loop(syntax: &SyntaxDefinition, theme: &Theme, state: &mut EditorState) -> io::Result<()> {
+
+    restore_terminal_on_panic();
+
+    // Dropping the backend leaves the alternate screen and restores cooked mode,
+    // so returning from this function is all that's needed to exit cleanly.
+    let mut backend = TermionBackend::new(AlternateScreen::from(stdout().into_raw_mode().unwrap()));
+    let mut renderer = Renderer::new(syntax, theme);
+    renderer.clear_screen(&mut backend)?;
+    renderer.render(&mut backend, state)?;
 
-    
-    let mut out = stdout().into_raw_mode().unwrap();
-    clear_screen(&mut out);
-    render(&mut out, highlighter, state);
+    let (sender, receiver) = channel();
+    spawn_input_reader(sender.clone());
+    spawn_resize_listener(sender.clone());
+    spawn_file_watcher(sender.clone(), PathBuf::from(state.get_active_file_name()), FILE_POLL_INTERVAL);
+    let job_sender = sender.clone();
+    let timers = Timers::start(sender);
 
     let mut input_mode_manager = InputModeMultiplexer::new();
-    let mut i = 0;
-    loop {
-        let mut events = stdin().events();
+    let mut expiring_message = None;
+    let mut key_sequence_timer = None;
+    let mut autosave = None;
+    schedule_autosave(&timers, &mut autosave, state);
+    while let Ok(editor_event) = receiver.recv() {
+        match editor_event {
+            EditorEvent::Input(event) => {
+                let mode_input_buffer = input_mode_manager.do_action_for_input(event, state);
+                state.mode_input_buffer = mode_input_buffer.clone();
 
-        if let Some(event) = events.next() {
-            let mode_input_buffer = input_mode_manager.do_action_for_input(event.unwrap(), state);
-            state.mode_input_buffer = mode_input_buffer.clone();
+                // Keys which could still become part of a longer sequence are only
+                // waited on for so long
+                if let Some(id) = key_sequence_timer.take() {
+                    timers.cancel(id);
+                }
+                if state.settings.timeout() && input_mode_manager.awaiting_timeout(state) {
+                    let delay = Duration::from_millis(state.settings.timeoutlen() as u64);
+                    key_sequence_timer = Some(timers.after(delay, TimerKind::KeySequenceTimeout));
+                }
+            },
+            // The old frame no longer lines up with the terminal, so lay everything
+            // out again from scratch at the new size.
+            EditorEvent::Resize => renderer.clear_screen(&mut backend)?,
+            // Saving changes the file too, which is no news
+            EditorEvent::FileChanged(path, modified_time) => if modified_time.is_none() || modified_time != state.saved_modified_time() {
+                state.set_status_message(format!("{} changed on disk", path.display()));
+            },
+            EditorEvent::Timer(_, TimerKind::MessageExpiry(message_id)) => state.expire_status_message(message_id),
+            EditorEvent::Timer(_, TimerKind::Autosave) => if state.is_modified() {
+                write_file(state);
+            },
+            EditorEvent::Timer(id, TimerKind::KeySequenceTimeout) => {
+                if key_sequence_timer == Some(id) {
+                    key_sequence_timer = None;
+                    input_mode_manager.timeout(state);
+                }
+            },
+            // Output of more than a line is shown in the panel, as `:registers` is
+            EditorEvent::JobFinished { name, result } => match result {
+                Ok(ref output) if output.contains('\n') => state.show_panel(output.lines().map(String::from).collect()),
+                Ok(output) => {
+                    state.set_status_message(format!("{}: {}", name, output));
+                },
+                Err(error) => {
+                    state.set_status_message(format!("{} failed: {}", name, error));
+                },
+            },
         }
-        // i += 1;
-        render(&mut out, highlighter, state);
+
+        // Shell commands run in the background, so the editor stays usable meanwhile
+        for command in state.shell_commands.drain(..) {
+            let name = format!("!{}", command);
+            spawn_job(job_sender.clone(), name, move || run_shell_command(&command));
+        }
+
+        state.pending_keys = format_keys(&input_mode_manager.pending_keys(state));
+        schedule_autosave(&timers, &mut autosave, state);
+
+        // Any message which appeared while handling the event gets cleared after a while
+        let current_message = state.get_status_message().map(|(id, _)| id);
+        if current_message.is_some() && current_message != expiring_message {
+            timers.after(MESSAGE_TIMEOUT, TimerKind::MessageExpiry(current_message.unwrap()));
+            expiring_message = current_message;
+        }
+
+        if state.should_quit() {
+            break;
+        }
+        renderer.render(&mut backend, state)?;
     }
+    Ok(())
+}
 
-}
\ No newline at end of file
+// Keeps the repeating autosave timer in step with the `autosave` option, which can
+// be changed at any time. `autosave` holds the running timer and its interval.
+fn schedule_autosave(timers: &Timers, autosave: &mut Option<(TimerId, usize)>, state: &EditorState) {
+    let interval = state.settings.autosave();
+    if autosave.map(|(_, current)| current) == Some(interval) || (autosave.is_none() && interval == 0) {
+        return;
+    }
+    if let Some((id, _)) = autosave.take() {
+        timers.cancel(id);
+    }
+    if interval > 0 {
+        *autosave = Some((timers.every(Duration::from_secs(interval as u64), TimerKind::Autosave), interval));
+    }
+}
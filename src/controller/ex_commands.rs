@@ -0,0 +1,320 @@
+use std::cmp;
+
+use data::editor_state::{StateApi, EditorState, Mode, VISUAL_MODES, line_and_offset};
+use data::io::write_file;
+use data::registers::REGISTER_NAMES;
+use controller::keymap::parse_keys;
+use controller::macros::run_normal;
+
+// Visual mappings apply to all the Visual modes alike, so only Visual's are listed
+const MAP_MODES: &[Mode] = &[Mode::Navigate, Mode::Visual, Mode::Insert, Mode::Command];
+
+// Runs a line entered in Command mode, e.g. `w`, `wq` or `set nowrap`. A range
+// of lines can come first, for the commands which take one.
+pub fn run_command(command_line: &str, state: &mut EditorState) {
+    let (range, command_line) = match parse_range(command_line.trim(), state) {
+        Ok(parsed) => parsed,
+        Err(message) => {
+            state.set_status_message(message);
+            return;
+        }
+    };
+    let (name, args) = match command_line.find(' ') {
+        Some(space_index) => (&command_line[..space_index], command_line[space_index..].trim()),
+        None => (command_line, ""),
+    };
+
+    match name {
+        // A range on its own goes to its last line, as a jump
+        "" => if let Some((_, last)) = range {
+            let from = state.cursor_index;
+            state.record_jump(from);
+            state.cursor_to_line(last);
+        },
+        "normal" | "norm" | "normal!" | "norm!" => normal(args, range, !name.ends_with('!'), state),
+        _ if range.is_some() => {
+            state.set_status_message(String::from("No range allowed"));
+        },
+        _ if name.starts_with('!') => match command_line[1..].trim() {
+            "" => {
+                state.set_status_message(String::from("Argument required"));
+            },
+            command => state.shell_commands.push(command.to_string()),
+        },
+        "w" => write_file(state),
+        "q" => state.request_quit(),
+        "wq" | "x" => {
+            write_file(state);
+            state.request_quit();
+        },
+        "set" | "se" => set_options(args, state, false),
+        "setlocal" | "setl" => set_options(args, state, true),
+        "map" | "nmap" | "nm" => map_keys(args, &[Mode::Navigate], false, state),
+        "noremap" | "no" | "nnoremap" | "nn" => map_keys(args, &[Mode::Navigate], true, state),
+        "vmap" | "vm" | "xmap" | "xm" => map_keys(args, VISUAL_MODES, false, state),
+        "vnoremap" | "vn" | "xnoremap" | "xn" => map_keys(args, VISUAL_MODES, true, state),
+        "imap" | "im" => map_keys(args, &[Mode::Insert], false, state),
+        "inoremap" | "ino" => map_keys(args, &[Mode::Insert], true, state),
+        "cmap" | "cm" => map_keys(args, &[Mode::Command], false, state),
+        "cnoremap" | "cno" => map_keys(args, &[Mode::Command], true, state),
+        "unmap" | "unm" | "nunmap" | "nun" => unmap_keys(args, &[Mode::Navigate], state),
+        "vunmap" | "vu" | "xunmap" | "xu" => unmap_keys(args, VISUAL_MODES, state),
+        "iunmap" | "iu" => unmap_keys(args, &[Mode::Insert], state),
+        "cunmap" | "cu" => unmap_keys(args, &[Mode::Command], state),
+        "maps" => list_mappings(MAP_MODES, "", state),
+        "registers" | "reg" | "display" | "di" => list_registers(args, state),
+        "marks" => list_marks(args, state),
+        "jumps" | "ju" => list_jumps(state),
+        "debug" => debug(args, state),
+        "retab" | "retab!" | "ret" | "ret!" => {
+            let bang = name.ends_with('!');
+            match args {
+                "" => state.retab(None, bang),
+                _ => match args.parse::<usize>() {
+                    Ok(tabstop) if tabstop > 0 => state.retab(Some(tabstop), bang),
+                    _ => {
+                        state.set_status_message(format!("Invalid tabstop: {}", args));
+                    }
+                }
+            }
+        },
+        "diffsave" => {
+            let diff = state.unsaved_diff();
+            if diff.is_empty() {
+                state.set_status_message(String::from("No unsaved changes"));
+            } else {
+                state.show_panel(diff);
+            }
+        },
+        _ => {
+            state.set_status_message(format!("Not an editor command: {}", name));
+        }
+    }
+}
+
+// Splits the range of lines from the front of the command line, as a pair of
+// line indices. A range is `%` for the whole file, or one or two lines separated
+// by a comma, each given by number, as `.` for the cursor line or `$` for the last.
+fn parse_range<'a>(command_line: &'a str, state: &EditorState) -> Result<(Option<(usize, usize)>, &'a str), String> {
+    let line_count = state.get_editor_lines().len();
+    let last_line = line_count.saturating_sub(1);
+    if command_line.starts_with('%') {
+        return Ok((Some((0, last_line)), command_line[1..].trim_left()));
+    }
+
+    let mut lines = vec![];
+    let mut rest = command_line;
+    loop {
+        let length = rest.find(|ch: char| !ch.is_digit(10)).unwrap_or(rest.len());
+        let line = if length > 0 {
+            match rest[..length].parse::<usize>() {
+                Ok(number) if number > 0 && number <= line_count => number - 1,
+                _ => return Err(String::from("Invalid range")),
+            }
+        } else if rest.starts_with('.') {
+            state.get_cursor_position().y
+        } else if rest.starts_with('$') {
+            last_line
+        } else if lines.is_empty() {
+            break;
+        } else {
+            return Err(String::from("Invalid range"));
+        };
+        lines.push(line);
+        rest = &rest[cmp::max(length, 1)..];
+        if lines.len() == 2 || !rest.starts_with(',') {
+            break;
+        }
+        rest = &rest[1..];
+    }
+
+    let range = match (lines.first(), lines.last()) {
+        (Some(&first), Some(&last)) if first > last => return Err(String::from("Backwards range given")),
+        (Some(&first), Some(&last)) => Some((first, last)),
+        _ => None,
+    };
+    Ok((range, rest.trim_left()))
+}
+
+// `:normal keys` types the keys as though in Navigate mode, on each line of the
+// range, or where the cursor is without one.
+fn normal(args: &str, range: Option<(usize, usize)>, remap: bool, state: &mut EditorState) {
+    let leader = state.settings.mapleader().to_string();
+    match parse_keys(args, &leader) {
+        Ok(keys) => run_normal(&keys, range, remap, state),
+        Err(message) => {
+            state.set_status_message(message);
+        }
+    }
+}
+
+fn debug(args: &str, state: &mut EditorState) {
+    match args {
+        "pieces" => {
+            let pieces = state.describe_pieces();
+            state.show_panel(pieces);
+        },
+        "tint" => state.tint_added_text = !state.tint_added_text,
+        _ => {
+            state.set_status_message(String::from("Usage: debug pieces|tint"));
+        }
+    }
+}
+
+// With no arguments, lists the options which have been changed; `all` lists every option.
+fn set_options(args: &str, state: &mut EditorState, local: bool) {
+    if args.is_empty() || args == "all" {
+        let options = state.settings.describe_options(args == "all");
+        if options.is_empty() {
+            state.set_status_message(String::from("All options are at their defaults"));
+        } else {
+            state.show_panel(options);
+        }
+        return;
+    }
+
+    let mut queried = vec![];
+    for arg in split_args(args) {
+        match state.settings.apply(&arg, local) {
+            Ok(Some(description)) => queried.push(description),
+            Ok(None) => (),
+            Err(message) => {
+                state.set_status_message(message);
+                return;
+            }
+        }
+    }
+    if !queried.is_empty() {
+        state.set_status_message(queried.join("  "));
+    }
+}
+
+// `:map lhs rhs` adds a mapping to each of the modes, while `:map lhs` and `:map`
+// list them.
+fn map_keys(args: &str, modes: &[Mode], noremap: bool, state: &mut EditorState) {
+    let (lhs, rhs) = match args.find(char::is_whitespace) {
+        Some(space_index) => (&args[..space_index], args[space_index..].trim_left()),
+        None => (args, ""),
+    };
+    if rhs.is_empty() {
+        list_mappings(&modes[..1], lhs, state);
+        return;
+    }
+
+    let leader = state.settings.mapleader().to_string();
+    match (parse_keys(lhs, &leader), parse_keys(rhs, &leader)) {
+        (Ok(lhs), Ok(rhs)) => for &mode in modes {
+            state.keymap.map(mode, lhs.clone(), rhs.clone(), noremap);
+        },
+        (Err(message), _) | (_, Err(message)) => {
+            state.set_status_message(message);
+        }
+    }
+}
+
+fn unmap_keys(args: &str, modes: &[Mode], state: &mut EditorState) {
+    let leader = state.settings.mapleader().to_string();
+    let removed = parse_keys(args, &leader).map(|lhs| {
+        modes.iter().fold(false, |removed, &mode| state.keymap.unmap(mode, &lhs) || removed)
+    });
+    if removed != Ok(true) {
+        state.set_status_message(format!("No such mapping: {}", args));
+    }
+}
+
+fn list_mappings(modes: &[Mode], prefix: &str, state: &mut EditorState) {
+    let leader = state.settings.mapleader().to_string();
+    let prefix = if prefix.is_empty() { vec![] } else { parse_keys(prefix, &leader).unwrap_or_default() };
+    let mappings = state.keymap.describe_mappings(modes, &prefix);
+    if mappings.is_empty() {
+        state.set_status_message(String::from("No mapping found"));
+    } else {
+        state.show_panel(mappings);
+    }
+}
+
+// Lists the registers which have something in them, or with arguments, only
+// those named, as `:registers a0` does.
+fn list_registers(args: &str, state: &mut EditorState) {
+    let mut lines = vec![String::from("Type Name Content")];
+    for name in REGISTER_NAMES.chars().filter(|&name| args.is_empty() || args.contains(name)) {
+        if let Ok(register) = state.read_register(name) {
+            lines.push(register.describe(name));
+        }
+    }
+    state.show_panel(lines);
+}
+
+// Lists the marks, or those named in the arguments, with the line and column each
+// is at and the text of the line. Marks in other files show the file instead.
+fn list_marks(args: &str, state: &mut EditorState) {
+    let lines = state.get_editor_lines();
+    let mut listed = vec![String::from("mark line  col file/text")];
+    for (name, file, index) in state.marks.all() {
+        if !args.is_empty() && !args.contains(name) {
+            continue;
+        }
+        let described = match file {
+            Some(file) if file != state.get_active_file_name() => format!("{:>5} {:>4} {}", "-", "-", file),
+            _ => describe_position(&lines, index),
+        };
+        listed.push(format!(" {}  {}", name, described));
+    }
+    if listed.len() == 1 {
+        state.set_status_message(String::from("No marks set"));
+    } else {
+        state.show_panel(listed);
+    }
+}
+
+// Lists the jump list, oldest first, numbered by how many Ctrl-o or Ctrl-i would
+// reach each jump. `>` shows where they've got to.
+fn list_jumps(state: &mut EditorState) {
+    let lines = state.get_editor_lines();
+    let (jumps, current) = state.marks.jumps();
+    let mut listed = vec![String::from(" jump line  col file/text")];
+    for (position, &index) in jumps.iter().enumerate() {
+        let distance = if position > current { position - current } else { current - position };
+        let pointer = if position == current { '>' } else { ' ' };
+        listed.push(format!("{}{:>4} {}", pointer, distance, describe_position(&lines, index)));
+    }
+    if current == jumps.len() {
+        listed.push(String::from(">"));
+    }
+    state.show_panel(listed);
+}
+
+// The line number, column and trimmed text of the line at the index
+fn describe_position(lines: &[String], index: usize) -> String {
+    if lines.is_empty() {
+        return format!("{:>5} {:>4}", 1, 0);
+    }
+    let (y, x) = line_and_offset(lines, index);
+    format!("{:>5} {:>4} {}", y + 1, x, lines[y].trim())
+}
+
+// Splits arguments on whitespace, except where it's escaped with a backslash.
+fn split_args(args: &str) -> Vec<String> {
+    let mut split = vec![];
+    let mut current = String::new();
+    let mut escaped = false;
+    for ch in args.chars() {
+        match ch {
+            '\\' if !escaped => escaped = true,
+            ch if ch.is_whitespace() && !escaped => {
+                if !current.is_empty() {
+                    split.push(current.clone());
+                    current.clear();
+                }
+            },
+            ch => {
+                current.push(ch);
+                escaped = false;
+            }
+        }
+    }
+    if !current.is_empty() {
+        split.push(current);
+    }
+    split
+}
@@ -1,12 +1,16 @@
 use std::fmt;
-use std::process::exit;
+use std::cmp;
 
 use termion::event::{Event, Key};
 
 use controller::util::{repeater_chain_to_usize, repeat_state_op};
-use controller::commands::{event_to_fn_alias, build_op_from_event};
-use data::editor_state::{StateApi, EditorState, Mode};
-use data::io::*;
+use controller::keymap::Binding;
+use controller::keytrie::Lookup;
+use controller::operators::{Verb, MotionKind, apply_verb, apply_verb_to_range, end_of_words, put_register};
+use controller::visual::*;
+use controller::macros::{start_recording, stop_recording, play_macro, abort_macro};
+use data::editor_state::{StateApi, EditorState, Mode, TextObject, CharSearch};
+use controller::ex_commands::run_command;
 
 #[derive(Clone, Debug)]
 pub enum Action {
@@ -16,16 +20,87 @@ pub enum Action {
     Up,
     StartNextWord,
     StartPrevWord,
+    EndOfWord,
+    EndOfPrevWord,
+    StartNextBigWord,
+    StartPrevBigWord,
+    EndOfBigWord,
+    EndOfPrevBigWord,
     StartOfLine,
+    FirstNonBlank,
     EndOfLine,
+    Column,
+    FirstLine,
+    LastLine,
+    ScreenTop,
+    ScreenMiddle,
+    ScreenBottom,
+    NextSentence,
+    PrevSentence,
+    NextParagraph,
+    PrevParagraph,
+    MatchingBracket,
+    DisplayRowDown,
+    DisplayRowUp,
+    NextHunk,
+    PrevHunk,
+    RepeatFind,
+    RepeatFindReverse,
     ToCommandMode,
+    ToInsertMode,
+    Append,  // `a`, after the cursor
+    AppendToLine,  // `A`, at the end of the line
+    InsertAtFirstNonBlank,  // `I`
+    OpenLineBelow,  // `o`
+    OpenLineAbove,  // `O`
+    ToVisualMode,
+    ToVisualLineMode,
+    ToVisualBlockMode,
+    ToNavigateMode,
+    SwapSelectionEnds,
+    Reselect,
+    BlockInsert,
+    BlockAppend,
+    PutAfter,
+    PutBefore,
+    PutAfterMovingPast,
+    PutBeforeMovingPast,
+    RepeatChange,
+    JumpOlder,
+    JumpNewer,
+    DeleteCharBefore,  // Backspace, in Insert and Command mode
+    InsertTab,
+    RunCommandLine,
     ExitEditor
 }
 
 #[derive(Clone, Debug)]
 pub enum FnAlias {
     FindNext,
-    NoOp
+    FindPrev,
+    TillNext,
+    TillPrev,
+    SelectRegister,  // `"x`, naming the register the next command uses
+    RecordMacro,  // `qx`, or `q` alone to stop recording
+    PlayMacro,
+    SetMark,
+    JumpToMark,  // `` `x ``, to the char the mark is on
+    JumpToMarkLine,  // `'x`, to the first non-blank of the mark's line
+}
+
+impl FnAlias {
+    // The search made by `f`, `F`, `t` and `T` for their argument
+    pub fn char_search(&self, target: char) -> Option<CharSearch> {
+        let (forward, till) = match *self {
+            FnAlias::FindNext => (true, false),
+            FnAlias::FindPrev => (false, false),
+            FnAlias::TillNext => (true, true),
+            FnAlias::TillPrev => (false, true),
+            FnAlias::SelectRegister | FnAlias::RecordMacro | FnAlias::PlayMacro |
+            FnAlias::SetMark | FnAlias::JumpToMark | FnAlias::JumpToMarkLine => return None,
+        };
+        Some(CharSearch { target, forward, till })
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -34,8 +109,6 @@ pub enum FnArg {
     Argument(char),
 }
 
-type Argument = char;
-type MappedKey = char;
 #[derive(Debug, Clone)]
 pub struct Repeatable { 
     pub times: String, 
@@ -44,7 +117,11 @@ pub struct Repeatable {
 #[derive(Debug, Clone)]
 pub enum ExecutableExpr {
     Operator ( Action ),
-    Function ( FnAlias, FnArg )
+    Function ( FnAlias, FnArg ),
+    TextObject ( TextObject ),
+    Edit ( Verb, Box<ExecutableExpr> ),  // A verb acting over the text a motion or text object covers
+    EditLines ( Verb ),  // A doubled verb, acting on whole lines
+    EditSelection ( Verb ),  // A verb typed in a Visual mode, acting on the selection
 }
 
 // State machine used to validate navigation expressions, represents the LAST event seen (not what is next expected)
@@ -56,6 +133,97 @@ pub enum ExprState {
     Execute { repeatable: Repeatable },  // e.g. 'w' to move to start of next word - leads to terminal state.
 }
 
+// A completed change to the text, kept so that `.` can make it again
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub repeatable: Repeatable,
+    pub register: Option<char>,  // The register named with `"x`, used again
+    pub selection: Option<SelectionSize>,  // How much was selected, for a change made in a Visual mode
+    pub inserted: Vec<Key>,  // The keys typed after the change entered Insert mode, up to the Esc
+}
+
+impl ExecutableExpr {
+    // Whether carrying out the expression changes the text, so that `.` repeats it
+    pub fn is_change(&self) -> bool {
+        match *self {
+            ExecutableExpr::Edit(verb, _) | ExecutableExpr::EditLines(verb) | ExecutableExpr::EditSelection(verb) => verb != Verb::Yank,
+            ExecutableExpr::Operator(ref action) => match *action {
+                Action::ToInsertMode | Action::Append | Action::AppendToLine | Action::InsertAtFirstNonBlank |
+                Action::OpenLineBelow | Action::OpenLineAbove | Action::BlockInsert | Action::BlockAppend |
+                Action::PutAfter | Action::PutBefore | Action::PutAfterMovingPast | Action::PutBeforeMovingPast => true,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    // Whether the expression is a motion which fails when it can't go anywhere, like
+    // `j` on the last line or an `f` which finds nothing. Failing stops any macro
+    // being played.
+    fn can_fail(&self) -> bool {
+        match *self {
+            ExecutableExpr::Function(ref alias, _) => alias.char_search('_').is_some(),
+            ExecutableExpr::Operator(ref action) => match *action {
+                Action::Right | Action::Left | Action::Down | Action::Up |
+                Action::StartNextWord | Action::StartPrevWord | Action::EndOfWord | Action::EndOfPrevWord |
+                Action::StartNextBigWord | Action::StartPrevBigWord | Action::EndOfBigWord | Action::EndOfPrevBigWord |
+                Action::NextSentence | Action::PrevSentence | Action::NextParagraph | Action::PrevParagraph |
+                Action::DisplayRowDown | Action::DisplayRowUp | Action::NextHunk | Action::PrevHunk |
+                Action::RepeatFind | Action::RepeatFindReverse => true,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+impl Action {
+    // Whether the action enters Insert mode to type text, which a count before it
+    // types that many times over
+    fn starts_insert(&self) -> bool {
+        match *self {
+            Action::ToInsertMode | Action::Append | Action::AppendToLine | Action::InsertAtFirstNonBlank |
+            Action::OpenLineBelow | Action::OpenLineAbove => true,
+            _ => false,
+        }
+    }
+
+    fn opens_line(&self) -> bool {
+        match *self {
+            Action::OpenLineBelow | Action::OpenLineAbove => true,
+            _ => false,
+        }
+    }
+
+    // Whether the action is a jump, which `''` and Ctrl-o can go back from
+    fn is_jump(&self) -> bool {
+        match *self {
+            Action::FirstLine | Action::LastLine | Action::ScreenTop | Action::ScreenMiddle | Action::ScreenBottom |
+            Action::NextSentence | Action::PrevSentence | Action::NextParagraph | Action::PrevParagraph |
+            Action::MatchingBracket => true,
+            _ => false,
+        }
+    }
+
+    // How a verb takes the text this action moves over, or None if it isn't a motion.
+    pub fn motion_kind(&self) -> Option<MotionKind> {
+        match *self {
+            Action::Down | Action::Up | Action::NextHunk | Action::PrevHunk |
+            Action::FirstLine | Action::LastLine |
+            Action::ScreenTop | Action::ScreenMiddle | Action::ScreenBottom => Some(MotionKind::Linewise),
+            Action::EndOfWord | Action::EndOfPrevWord | Action::EndOfBigWord | Action::EndOfPrevBigWord |
+            Action::EndOfLine | Action::MatchingBracket => Some(MotionKind::Inclusive),
+            Action::ToCommandMode | Action::ToInsertMode | Action::Append | Action::AppendToLine |
+            Action::InsertAtFirstNonBlank | Action::OpenLineBelow | Action::OpenLineAbove |
+            Action::ToVisualMode | Action::ToVisualLineMode | Action::ToVisualBlockMode | Action::ToNavigateMode | Action::SwapSelectionEnds | Action::Reselect |
+            Action::BlockInsert | Action::BlockAppend | Action::PutAfter | Action::PutBefore |
+            Action::PutAfterMovingPast | Action::PutBeforeMovingPast | Action::RepeatChange | Action::JumpOlder | Action::JumpNewer |
+            Action::DeleteCharBefore | Action::InsertTab | Action::RunCommandLine | Action::ExitEditor => None,
+            _ => Some(MotionKind::Exclusive),
+        }
+    }
+}
+
 impl fmt::Display for Action {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let to_write = match *self {
@@ -65,9 +233,57 @@ impl fmt::Display for Action {
             Action::Up => "Up",
             Action::StartNextWord => "StartNextWord",
             Action::StartPrevWord => "StartPrevWord",
+            Action::EndOfWord => "EndOfWord",
+            Action::EndOfPrevWord => "EndOfPrevWord",
+            Action::StartNextBigWord => "StartNextBigWord",
+            Action::StartPrevBigWord => "StartPrevBigWord",
+            Action::EndOfBigWord => "EndOfBigWord",
+            Action::EndOfPrevBigWord => "EndOfPrevBigWord",
             Action::StartOfLine => "StartOfLine",
+            Action::FirstNonBlank => "FirstNonBlank",
             Action::EndOfLine => "EndOfLine",
+            Action::Column => "Column",
+            Action::FirstLine => "FirstLine",
+            Action::LastLine => "LastLine",
+            Action::ScreenTop => "ScreenTop",
+            Action::ScreenMiddle => "ScreenMiddle",
+            Action::ScreenBottom => "ScreenBottom",
+            Action::NextSentence => "NextSentence",
+            Action::PrevSentence => "PrevSentence",
+            Action::NextParagraph => "NextParagraph",
+            Action::PrevParagraph => "PrevParagraph",
+            Action::MatchingBracket => "MatchingBracket",
+            Action::DisplayRowDown => "DisplayRowDown",
+            Action::DisplayRowUp => "DisplayRowUp",
+            Action::NextHunk => "NextHunk",
+            Action::PrevHunk => "PrevHunk",
+            Action::RepeatFind => "RepeatFind",
+            Action::RepeatFindReverse => "RepeatFindReverse",
             Action::ToCommandMode => "ToCommandMode",
+            Action::ToInsertMode => "ToInsertMode",
+            Action::Append => "Append",
+            Action::AppendToLine => "AppendToLine",
+            Action::InsertAtFirstNonBlank => "InsertAtFirstNonBlank",
+            Action::OpenLineBelow => "OpenLineBelow",
+            Action::OpenLineAbove => "OpenLineAbove",
+            Action::ToVisualMode => "ToVisualMode",
+            Action::ToVisualLineMode => "ToVisualLineMode",
+            Action::ToVisualBlockMode => "ToVisualBlockMode",
+            Action::ToNavigateMode => "ToNavigateMode",
+            Action::SwapSelectionEnds => "SwapSelectionEnds",
+            Action::Reselect => "Reselect",
+            Action::BlockInsert => "BlockInsert",
+            Action::BlockAppend => "BlockAppend",
+            Action::PutAfter => "PutAfter",
+            Action::PutBefore => "PutBefore",
+            Action::PutAfterMovingPast => "PutAfterMovingPast",
+            Action::PutBeforeMovingPast => "PutBeforeMovingPast",
+            Action::RepeatChange => "RepeatChange",
+            Action::JumpOlder => "JumpOlder",
+            Action::JumpNewer => "JumpNewer",
+            Action::DeleteCharBefore => "DeleteCharBefore",
+            Action::InsertTab => "InsertTab",
+            Action::RunCommandLine => "RunCommandLine",
             Action::ExitEditor => "EcitEditor"
         };
 
@@ -89,7 +305,15 @@ impl fmt::Display for FnAlias {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let to_write = match *self {
             FnAlias::FindNext => "FindNext",
-            FnAlias::NoOp => "NoOp"
+            FnAlias::FindPrev => "FindPrev",
+            FnAlias::TillNext => "TillNext",
+            FnAlias::TillPrev => "TillPrev",
+            FnAlias::SelectRegister => "SelectRegister",
+            FnAlias::RecordMacro => "RecordMacro",
+            FnAlias::PlayMacro => "PlayMacro",
+            FnAlias::SetMark => "SetMark",
+            FnAlias::JumpToMark => "JumpToMark",
+            FnAlias::JumpToMarkLine => "JumpToMarkLine",
         };
         write!(f, "{}", to_write)
     }
@@ -102,7 +326,19 @@ impl fmt::Display for ExecutableExpr {
                 write!(f, "CursorTo ( {} )", &action),
 
             ExecutableExpr::Function( ref alias, ref arg) => 
-                write!(f, "{} ( {} )", &alias, &arg)
+                write!(f, "{} ( {} )", &alias, &arg),
+
+            ExecutableExpr::TextObject( ref object) =>
+                write!(f, "{:?}", object),
+
+            ExecutableExpr::Edit( ref verb, ref motion) =>
+                write!(f, "{:?} ( {} )", verb, motion),
+
+            ExecutableExpr::EditLines( ref verb) =>
+                write!(f, "{:?} ( Lines )", verb),
+
+            ExecutableExpr::EditSelection( ref verb) =>
+                write!(f, "{:?} ( Selection )", verb)
         }
     }
 }
@@ -124,18 +360,54 @@ pub trait ModeInputHandler {
     // Returns the input buffer to inform client that command may not have been executed if buffer contains chars
     fn handle_input(&mut self, event: Event, state_api: &mut EditorState) -> &Vec<char>;
     fn get_input_buffer(&self) -> &Vec<char>;
-    fn push_input(&mut self, ch: char);
+
+    // True while the next key is taken literally (e.g. the char after `f`), so
+    // key mappings mustn't be applied to it.
+    fn awaiting_argument(&self) -> bool {
+        false
+    }
+
+    // True while the keys typed are a complete sequence, but might also be the
+    // start of a longer one. If nothing follows within `timeoutlen`, `timeout` is
+    // called to settle on the shorter one.
+    fn awaiting_timeout(&self) -> bool {
+        false
+    }
+
+    fn timeout(&mut self, _state_api: &mut EditorState) {
+    }
+
+    // The keys of a command which is still being typed, shown in the status line
+    fn pending_keys(&self) -> Vec<Key> {
+        vec![]
+    }
+}
+
+// The outcome of feeding a key to the Navigate mode keymap
+enum SequenceStep {
+    Incomplete,  // The keys so far are the start of a longer sequence
+    Complete(Option<Binding>),  // None if the keys aren't bound to anything
 }
 
 pub struct NavigateModeInputHandler {
     expression_state: ExprState,  
-    command_buffer: Vec<char>
+    command_buffer: Vec<char>,
+    sequence: Vec<Key>,  // Keys of a multi-key binding typed so far
+    ambiguous: Option<Binding>,  // What `sequence` is bound to, when longer sequences also start with it
+    verb: Option<(Verb, String)>,  // A verb waiting for its motion, along with the count typed before it
+    register: Option<char>,  // The register named with `"x` for the command being typed
+    typed: Vec<Key>,  // Every key of the expression so far, shown in the status line
 }
 impl NavigateModeInputHandler {
     pub fn new() -> Self {
         Self {
             expression_state: ExprState::Waiting,
-            command_buffer: vec![]
+            command_buffer: vec![],
+            sequence: vec![],
+            ambiguous: None,
+            verb: None,
+            register: None,
+            typed: vec![],
         }
     }
 
@@ -144,222 +416,718 @@ impl NavigateModeInputHandler {
         self.expression_state = state;
     }
 
-    fn get_state(&mut self) -> &ExprState {
-        &self.expression_state
+    // Adds the key to the sequence typed so far and looks the sequence up.
+    fn resolve_sequence(&mut self, event: &Event, state: &EditorState) -> SequenceStep {
+        let key = match *event {
+            Event::Key(key) => key,
+            _ => return SequenceStep::Complete(None),
+        };
+
+        // After a verb, its last key stands for the whole verb, so that `guu` is `gugu`
+        if let Some((verb, _)) = self.verb {
+            if self.sequence.is_empty() && key == Key::Char(verb.doubled_key()) {
+                return SequenceStep::Complete(Some(Binding::Verb(verb)));
+            }
+        }
+
+        self.sequence.push(key);
+        self.ambiguous = None;
+
+        // After a verb, a text object can be given instead of a motion
+        if self.verb.is_some() {
+            match state.keymap.text_object(&self.sequence) {
+                Lookup::NoMatch => (),
+                Lookup::Prefix => return SequenceStep::Incomplete,
+                Lookup::Ambiguous(&object) | Lookup::Match(&object) => {
+                    self.sequence.clear();
+                    return SequenceStep::Complete(Some(Binding::TextObject(object)));
+                },
+            }
+        }
+
+        let step = match state.keymap.builtin(*state.get_mode(), &self.sequence) {
+            Lookup::Prefix => return SequenceStep::Incomplete,
+            Lookup::Ambiguous(binding) => {
+                self.ambiguous = Some(binding.clone());
+                return SequenceStep::Incomplete;
+            },
+            Lookup::Match(binding) => SequenceStep::Complete(Some(binding.clone())),
+            Lookup::NoMatch => SequenceStep::Complete(None),
+        };
+        self.sequence.clear();
+        step
+    }
+
+    // The state following a complete key sequence, or None when it was a verb, which
+    // leaves us waiting for a motion.
+    fn complete_sequence(&mut self, binding: Option<Binding>, times: String, editor_state: &mut EditorState) -> Option<ExprState> {
+        match (self.verb.take(), binding) {
+            // In the Visual modes, a verb acts on the selection straight away
+            (None, Some(Binding::Verb(verb))) if editor_state.get_mode().is_visual() => Some(ExprState::Execute {
+                repeatable: Repeatable { times, expr: Some(ExecutableExpr::EditSelection(verb)) }
+            }),
+            // `q` while recording stops, rather than waiting for a register name
+            (None, Some(Binding::Function(FnAlias::RecordMacro))) if editor_state.recording.is_some() => Some(ExprState::Execute {
+                repeatable: Repeatable { times, expr: Some(ExecutableExpr::Function(FnAlias::RecordMacro, FnArg::NoArg)) }
+            }),
+            (None, Some(Binding::Verb(verb))) => {
+                self.verb = Some((verb, times));
+                self.goto_state(editor_state, ExprState::Waiting);
+                None
+            },
+            // A doubled verb, e.g. `dd` or `gUgU`, acts on whole lines
+            (Some((pending, verb_times)), Some(Binding::Verb(verb))) if pending == verb => Some(ExprState::Execute {
+                repeatable: Repeatable {
+                    times: multiply_counts(&verb_times, &times),
+                    expr: Some(ExecutableExpr::EditLines(verb))
+                }
+            }),
+            // Anything else after a verb has to be a motion
+            (Some(_), Some(Binding::Verb(_))) | (Some(_), Some(Binding::Edit(..))) => Some(ExprState::Waiting),
+            (Some(_), Some(Binding::Operator(ref action))) if action.motion_kind().is_none() => Some(ExprState::Waiting),
+            (verb, binding) => {
+                self.verb = verb;
+                Some(state_for_binding(binding, times))
+            },
+        }
+    }
+
+    // Moves to the state following a complete key sequence, carrying out the
+    // expression if it doesn't need any more input.
+    fn advance(&mut self, editor_state: &mut EditorState, state: ExprState) {
+        self.goto_state(editor_state, state);
+        match self.expression_state.clone() {
+            // `"x` only names the register, so the command itself is still to come
+            ExprState::Execute { repeatable: Repeatable { times, expr: Some(ExecutableExpr::Function(FnAlias::SelectRegister, FnArg::Argument(name))) } } => {
+                self.register = Some(name);
+                let state = if times.is_empty() {
+                    ExprState::Waiting
+                } else {
+                    ExprState::Repeater { repeatable: Repeatable { times, expr: None } }
+                };
+                self.goto_state(editor_state, state);
+            },
+            ExprState::Execute { repeatable } => {
+                let repeatable = match self.verb.take() {
+                    Some((verb, verb_times)) => Repeatable {
+                        times: multiply_counts(&verb_times, &repeatable.times),
+                        expr: repeatable.expr.map(|motion| ExecutableExpr::Edit(verb, Box::new(motion)))
+                    },
+                    None => repeatable,
+                };
+                let register = self.register.take();
+                let selection = if editor_state.get_mode().is_visual() { measure_selection(editor_state) } else { None };
+                editor_state.registers.selected = register;
+                execute_expression(&repeatable, editor_state);
+                editor_state.registers.selected = None;
+                if repeatable.expr.as_ref().map_or(false, ExecutableExpr::is_change) {
+                    record_change(Change { repeatable, register, selection, inserted: vec![] }, editor_state);
+                }
+                self.goto_state(editor_state, ExprState::Waiting);
+                self.typed.clear();
+            },
+            ExprState::Waiting => {
+                self.verb = None;
+                self.register = None;
+                self.typed.clear();
+            },
+            _ => (),
+        }
     }
 
+    // The count typed before the key sequence currently being typed, empty if none was
+    fn current_count(&self) -> String {
+        match self.expression_state {
+            ExprState::Repeater { ref repeatable } => repeatable.times.clone(),
+            _ => String::new(),
+        }
+    }
+}
+
+// Keeps the change for `.`. One which entered Insert mode waits there until the
+// typing is done.
+fn record_change(change: Change, state: &mut EditorState) {
+    if *state.get_mode() == Mode::Insert {
+        state.pending_change = Some(change);
+    } else {
+        state.last_change = Some(change);
+    }
+}
+
+// `.` makes the last change again from the cursor, typing the same text if it
+// entered Insert mode. A count replaces the one the change was made with, for
+// this and later repeats.
+fn repeat_change(times: &str, state_api: &mut EditorState) {
+    let change = match state_api.last_change.as_mut() {
+        Some(change) => {
+            if !times.is_empty() {
+                change.repeatable.times = times.to_string();
+            }
+            change.clone()
+        },
+        None => return,
+    };
+    let times = change.repeatable.times;
+    if let Some(size) = change.selection {
+        select_size(size, state_api);
+    }
+    state_api.registers.selected = change.register;
+    execute_expression(&Repeatable { times: times.clone(), expr: change.repeatable.expr.clone() }, state_api);
+    state_api.registers.selected = None;
+
+    if *state_api.get_mode() == Mode::Insert {
+        let mut insert = InsertModeInputHandler::new();
+        for &key in change.inserted.iter() {
+            insert.type_key(key, state_api);
+        }
+        if let Some(ExecutableExpr::Operator(ref action)) = change.repeatable.expr {
+            if action.starts_insert() {
+                insert.repeat_typing(repeater_chain_to_usize(&times), action.opens_line(), state_api);
+            }
+        }
+        insert.handle_input(Event::Key(Key::Esc), state_api);
+    }
 }
+
+// The state which follows a completed key sequence
+fn state_for_binding(binding: Option<Binding>, times: String) -> ExprState {
+    match binding {
+        // A Function is bound to the keys, so move into Function state, which will
+        // await the argument input
+        Some(Binding::Function(alias)) => ExprState::Function {
+            repeatable: Repeatable {
+                times,
+                expr: Some(
+                    ExecutableExpr::Function(alias, FnArg::NoArg)
+                )
+            }
+        },
+        // Otherwise we don't expect any further input, so move to the Execute state,
+        // which causes the actual execution of the ExecutableExpr we've built up.
+        Some(Binding::Operator(action)) => ExprState::Execute {
+            repeatable: Repeatable {
+                times,
+                expr: Some(ExecutableExpr::Operator(action))
+            }
+        },
+        Some(Binding::TextObject(object)) => ExprState::Execute {
+            repeatable: Repeatable {
+                times,
+                expr: Some(ExecutableExpr::TextObject(object))
+            }
+        },
+        Some(Binding::Edit(verb, action)) => ExprState::Execute {
+            repeatable: Repeatable {
+                times,
+                expr: Some(ExecutableExpr::Edit(verb, Box::new(ExecutableExpr::Operator(action))))
+            }
+        },
+        Some(Binding::Verb(_)) | None => ExprState::Execute {
+            repeatable: Repeatable { times, expr: None }
+        },
+    }
+}
+
+// Counts before a verb and before its motion multiply, so `2d3w` deletes six words.
+// Without either, there's still no count, so `dG` deletes to the last line.
+fn multiply_counts(verb_times: &str, motion_times: &str) -> String {
+    if verb_times.is_empty() && motion_times.is_empty() {
+        return String::new();
+    }
+    (repeater_chain_to_usize(verb_times) * repeater_chain_to_usize(motion_times)).to_string()
+}
+
 impl ModeInputHandler for NavigateModeInputHandler {
 
     fn handle_input(&mut self, event: Event, state_api: &mut EditorState) -> &Vec<char> {
-        
-        let move_to_state;
-        match self.get_state() {
+        if let Event::Key(key) = event {
+            self.typed.push(key);
+        }
 
-            &ExprState::Waiting => {
-                match event {
-                    // Waiting -> Repeater transition (We've received character 0-9)
-                    Event::Key(Key::Char(c @ '0'...'9')) => {
-                        move_to_state = ExprState::Repeater { 
-                            repeatable: Repeatable { 
-                                times: c.to_string(), 
-                                expr: None,
-                            }
-                        };
-                    }
+        let move_to_state = match self.expression_state.clone() {
 
-                    // Either move to Function state if the input is a Function name, or straight to Execute
-                    _ => {
-                        match event_to_fn_alias(&event) {
-                            // No function was found, so we don't expect any further input.
-                            // We move to state Execute, which causes the actual execution of the ExecutableExpr
-                            // we've built up.
-                            FnAlias::NoOp => move_to_state = ExprState::Execute {
-                                repeatable: Repeatable {
-                                    times: String::from("1"),
-                                    expr: build_op_from_event(&event)
-                                }
-                            },
-                            // We've found a Function for this event, move into Function state,
-                            // which will await the argument input
-                            alias => move_to_state = ExprState::Function {
-                                repeatable: Repeatable {
-                                    times: String::from("1"),
-                                    expr: Some(
-                                        ExecutableExpr::Function(alias, FnArg::NoArg)
-                                    )
-                                }
-                            },
-                        }
-                    }
+            // Waiting -> Repeater transition (We've received character 1-9, as 0 on its own
+            // goes to the start of the line)
+            ExprState::Waiting if self.sequence.is_empty() && is_digit(&event) && digit_of(&event) != '0' => ExprState::Repeater {
+                repeatable: Repeatable {
+                    times: digit_of(&event).to_string(),
+                    expr: None,
                 }
             },
 
-            &ExprState::Repeater { ref repeatable } => {
-                match event {
-                    // Repeater -> Repeater transition
-                    Event::Key(Key::Char(c @ '0'...'9')) => {
-                        move_to_state = ExprState::Repeater { 
-                            repeatable: Repeatable {
-                                times: format!("{}{}", repeatable.times, c),
-                                expr: None
-                            }  
-                        };
-                     },
-                    
-                    // Repeater -> Function transition
-                    _ => {
-                        match event_to_fn_alias(&event) {
-                            // No functions match, execute repeated Operation
-                            FnAlias::NoOp => move_to_state = ExprState::Execute {
-                                repeatable: Repeatable {
-                                    times: repeatable.clone().times,
-                                    expr: build_op_from_event(&event)
-                                }
-                            },
-
-                            // A Function exists for the input char, enter Function stage
-                            // with the appropriate FnAlias activated.
-                            alias => move_to_state = ExprState::Function {
-                                repeatable: Repeatable {
-                                    times: repeatable.clone().times,
-                                    expr: Some(
-                                        ExecutableExpr::Function(event_to_fn_alias(&event), FnArg::NoArg)
-                                    )
-                                }
-                            },
-                        }
-                    }
+            // Repeater -> Repeater transition
+            ExprState::Repeater { ref repeatable } if self.sequence.is_empty() && is_digit(&event) => ExprState::Repeater {
+                repeatable: Repeatable {
+                    times: format!("{}{}", repeatable.times, digit_of(&event)),
+                    expr: None
+                }
+            },
+
+            // Either move to Function state if the keys are bound to a Function, or straight
+            // to Execute. A key which might be the start of a longer sequence leaves the
+            // state as it is until we know.
+            ExprState::Waiting | ExprState::Repeater { .. } => {
+                let times = self.current_count();
+                let next_state = match self.resolve_sequence(&event, state_api) {
+                    SequenceStep::Incomplete => None,
+                    SequenceStep::Complete(binding) => self.complete_sequence(binding, times, state_api),
+                };
+                match next_state {
+                    Some(next_state) => next_state,
+                    None => return self.get_input_buffer(),
                 }
             },
 
             // We're already in the Function stage, so this input is the arg
-            &ExprState::Function { ref repeatable } => {
+            ExprState::Function { repeatable } => {
                 // Grab the alias of the function from the Function stage
-                let repeatable_clone = repeatable.clone();
-                let alias = match repeatable_clone {
-                    Repeatable {
-                        times: _, 
-                        expr: Some(ExecutableExpr::Function(alias, _))
-                    } => alias,
-                    _ => exit(0)
+                let alias = match repeatable.expr {
+                    Some(ExecutableExpr::Function(alias, _)) => alias,
+                    _ => unreachable!("Function state entered without a function expression")
                 };
 
                 match event {
-                    Event::Key(Key::Char(ch)) => {
-                        // We've received an argument, so move to the Execute state, passing Argument through
-                        move_to_state = ExprState::Execute {
-                            repeatable: Repeatable {
-                                times: repeatable_clone.times,
-                                expr: Some(
-                                    ExecutableExpr::Function(alias, FnArg::Argument(ch))
-                                )
-                            }
+                    // We've received an argument, so move to the Execute state, passing Argument through
+                    Event::Key(Key::Char(ch)) => ExprState::Execute {
+                        repeatable: Repeatable {
+                            times: repeatable.times,
+                            expr: Some(
+                                ExecutableExpr::Function(alias, FnArg::Argument(ch))
+                            )
                         }
-                    }
-                    _ => move_to_state = ExprState::Waiting
+                    },
+                    _ => ExprState::Waiting
                 }
             }
 
-            _ => move_to_state = ExprState::Waiting,
-        }
-        let mut finalised_state = move_to_state.clone();
-        self.goto_state(state_api, move_to_state);
-
-        // This'll deal only with termination (when the latest input has put us into the Execute state)
-        if let &ExprState::Execute { ref repeatable } = self.get_state() {
-            match repeatable {
-
-                // Handle operators
-                &Repeatable { ref times, expr: Some(ExecutableExpr::Operator(ref action)) } =>
-                    match action {
-                        &Action::Right =>
-                            repeat_state_op(
-                                &repeater_chain_to_usize(times), 
-                                &StateApi::inc_cursor, 
-                                state_api
-                            ),
-                        &Action::Left =>
-                            repeat_state_op(
-                                &repeater_chain_to_usize(times), 
-                                &StateApi::dec_cursor, 
-                                state_api
-                            ),
-                        &Action::Down =>
-                            repeat_state_op(
-                                &repeater_chain_to_usize(times), 
-                                &StateApi::cursor_line_down, 
-                                state_api
-                            ),
-                        &Action::Up =>
-                            repeat_state_op(
-                                &repeater_chain_to_usize(times), 
-                                &StateApi::cursor_line_up, 
-                                state_api
-                            ),
-                        &Action::StartNextWord =>
-                            repeat_state_op(
-                                &repeater_chain_to_usize(times), 
-                                &StateApi::cursor_start_next_word, 
-                                state_api
-                            ),
-                        &Action::StartPrevWord =>
-                            repeat_state_op(
-                                &repeater_chain_to_usize(times), 
-                                &StateApi::cursor_start_prev_word, 
-                                state_api
-                            ),
-                        &Action::StartOfLine =>
-                            repeat_state_op(
-                                &repeater_chain_to_usize(times), 
-                                &StateApi::cursor_start_of_line, 
-                                state_api
-                            ),
-                        &Action::EndOfLine =>
-                            repeat_state_op(
-                                &repeater_chain_to_usize(times), 
-                                &StateApi::cursor_end_of_line, 
-                                state_api
-                            ),
-                        &Action::ToCommandMode => state_api.set_mode(Mode::Command), 
-                        &Action::ExitEditor => exit(0), 
-                        _ => ()
+            _ => ExprState::Waiting,
+        };
 
-                    }
-                
-                &Repeatable { ref times, expr: Some(ExecutableExpr::Function(ref alias, FnArg::Argument(ref arg))) } =>
-                    match alias {
-                        &FnAlias::NoOp => (),
-                        
-                        // TODO: FindNext implementation is just for testing purposes atm.
-                        &FnAlias::FindNext => {
-                            repeat_state_op(
-                                &(arg.to_string().parse::<usize>().unwrap_or(1) * 
-                                    repeater_chain_to_usize(times)),
-                                &StateApi::cursor_line_down,
-                                state_api
-                            );
-                        }
-                    },
+        self.advance(state_api, move_to_state);
+        self.get_input_buffer()
+    }
+
+    fn get_input_buffer(&self) -> &Vec<char> {
+        &self.command_buffer
+    }
+    fn awaiting_argument(&self) -> bool {
+        match self.expression_state {
+            ExprState::Function { .. } => true,
+            _ => false,
+        }
+    }
+
+    fn awaiting_timeout(&self) -> bool {
+        self.ambiguous.is_some()
+    }
+
+    // Nothing longer followed an ambiguous sequence, so it's taken as it is
+    fn timeout(&mut self, state_api: &mut EditorState) {
+        let binding = match self.ambiguous.take() {
+            Some(binding) => binding,
+            None => return,
+        };
+        self.sequence.clear();
+        let times = self.current_count();
+        if let Some(next_state) = self.complete_sequence(Some(binding), times, state_api) {
+            self.advance(state_api, next_state);
+        }
+    }
+
+    fn pending_keys(&self) -> Vec<Key> {
+        self.typed.clone()
+    }
+}
+
+fn is_digit(event: &Event) -> bool {
+    match *event {
+        Event::Key(Key::Char('0'...'9')) => true,
+        _ => false,
+    }
+}
+
+fn digit_of(event: &Event) -> char {
+    match *event {
+        Event::Key(Key::Char(ch)) => ch,
+        _ => unreachable!("Not a digit"),
+    }
+}
+
+// Carries out a completed expression.
+fn execute_expression(repeatable: &Repeatable, state_api: &mut EditorState) {
+    let start = state_api.cursor_index;
+    match repeatable {
+
+        // Handle operators
+        &Repeatable { ref times, expr: Some(ExecutableExpr::Operator(ref action)) } =>
+            match action {
+                &Action::Right =>
+                    repeat_state_op(
+                        &repeater_chain_to_usize(times), 
+                        &StateApi::inc_cursor, 
+                        state_api
+                    ),
+                &Action::Left =>
+                    repeat_state_op(
+                        &repeater_chain_to_usize(times), 
+                        &StateApi::dec_cursor, 
+                        state_api
+                    ),
+                &Action::Down =>
+                    repeat_state_op(
+                        &repeater_chain_to_usize(times), 
+                        &StateApi::cursor_line_down, 
+                        state_api
+                    ),
+                &Action::Up =>
+                    repeat_state_op(
+                        &repeater_chain_to_usize(times), 
+                        &StateApi::cursor_line_up, 
+                        state_api
+                    ),
+                &Action::StartNextWord =>
+                    repeat_state_op(
+                        &repeater_chain_to_usize(times), 
+                        &StateApi::cursor_start_next_word, 
+                        state_api
+                    ),
+                &Action::StartPrevWord =>
+                    repeat_state_op(
+                        &repeater_chain_to_usize(times), 
+                        &StateApi::cursor_start_prev_word, 
+                        state_api
+                    ),
+                &Action::EndOfWord =>
+                    repeat_state_op(
+                        &repeater_chain_to_usize(times),
+                        &StateApi::cursor_end_of_word,
+                        state_api
+                    ),
+                &Action::EndOfPrevWord =>
+                    repeat_state_op(
+                        &repeater_chain_to_usize(times),
+                        &StateApi::cursor_end_of_prev_word,
+                        state_api
+                    ),
+                &Action::StartNextBigWord =>
+                    repeat_state_op(
+                        &repeater_chain_to_usize(times),
+                        &StateApi::cursor_start_next_big_word,
+                        state_api
+                    ),
+                &Action::StartPrevBigWord =>
+                    repeat_state_op(
+                        &repeater_chain_to_usize(times),
+                        &StateApi::cursor_start_prev_big_word,
+                        state_api
+                    ),
+                &Action::EndOfBigWord =>
+                    repeat_state_op(
+                        &repeater_chain_to_usize(times),
+                        &StateApi::cursor_end_of_big_word,
+                        state_api
+                    ),
+                &Action::EndOfPrevBigWord =>
+                    repeat_state_op(
+                        &repeater_chain_to_usize(times),
+                        &StateApi::cursor_end_of_prev_big_word,
+                        state_api
+                    ),
+                &Action::StartOfLine => state_api.cursor_start_of_line(),
+                &Action::FirstNonBlank => state_api.cursor_first_non_blank(),
+                // With a count, `$` goes to the end of a later line
+                &Action::EndOfLine => {
+                    repeat_state_op(
+                        &(repeater_chain_to_usize(times) - 1),
+                        &StateApi::cursor_line_down,
+                        state_api
+                    );
+                    state_api.cursor_end_of_line();
+                },
+                &Action::Column => state_api.cursor_to_column(repeater_chain_to_usize(times) - 1),
+
+                // Going to a line, by number when a count is given
+                &Action::FirstLine => state_api.cursor_to_line(repeater_chain_to_usize(times) - 1),
+                &Action::LastLine => {
+                    let last_line = state_api.get_editor_lines().len().saturating_sub(1);
+                    let line = if times.is_empty() { last_line } else { repeater_chain_to_usize(times) - 1 };
+                    state_api.cursor_to_line(line);
+                },
+                &Action::ScreenTop | &Action::ScreenMiddle | &Action::ScreenBottom => {
+                    let (top, bottom) = state_api.visible_lines();
+                    let offset = repeater_chain_to_usize(times) - 1;
+                    let line = match action {
+                        &Action::ScreenTop => cmp::min(top + offset, bottom),
+                        &Action::ScreenMiddle => (top + bottom) / 2,
+                        _ => cmp::max(bottom.saturating_sub(offset), top),
+                    };
+                    state_api.cursor_to_line(line);
+                },
+                &Action::NextSentence =>
+                    repeat_state_op(
+                        &repeater_chain_to_usize(times),
+                        &StateApi::cursor_next_sentence,
+                        state_api
+                    ),
+                &Action::PrevSentence =>
+                    repeat_state_op(
+                        &repeater_chain_to_usize(times),
+                        &StateApi::cursor_prev_sentence,
+                        state_api
+                    ),
+                &Action::NextParagraph =>
+                    repeat_state_op(
+                        &repeater_chain_to_usize(times),
+                        &StateApi::cursor_next_paragraph,
+                        state_api
+                    ),
+                &Action::PrevParagraph =>
+                    repeat_state_op(
+                        &repeater_chain_to_usize(times),
+                        &StateApi::cursor_prev_paragraph,
+                        state_api
+                    ),
+                // With a count, `%` goes that far through the file instead
+                &Action::MatchingBracket if times.is_empty() => state_api.cursor_matching_bracket(),
+                &Action::MatchingBracket => {
+                    let line_count = state_api.get_editor_lines().len();
+                    let percent = cmp::min(repeater_chain_to_usize(times), 100);
+                    state_api.cursor_to_line(((percent * line_count + 99) / 100).saturating_sub(1));
+                },
+                &Action::DisplayRowDown =>
+                    repeat_state_op(
+                        &repeater_chain_to_usize(times),
+                        &StateApi::cursor_display_row_down,
+                        state_api
+                    ),
+                &Action::DisplayRowUp =>
+                    repeat_state_op(
+                        &repeater_chain_to_usize(times),
+                        &StateApi::cursor_display_row_up,
+                        state_api
+                    ),
+                &Action::NextHunk =>
+                    repeat_state_op(
+                        &repeater_chain_to_usize(times),
+                        &StateApi::cursor_next_hunk,
+                        state_api
+                    ),
+                &Action::PrevHunk =>
+                    repeat_state_op(
+                        &repeater_chain_to_usize(times),
+                        &StateApi::cursor_prev_hunk,
+                        state_api
+                    ),
+                &Action::RepeatFind | &Action::RepeatFindReverse => {
+                    execute_char_search(&ExecutableExpr::Operator(action.clone()), times, state_api);
+                },
+                &Action::ToCommandMode => state_api.set_mode(Mode::Command), 
+                &Action::ToInsertMode | &Action::Append | &Action::AppendToLine | &Action::InsertAtFirstNonBlank |
+                &Action::OpenLineBelow | &Action::OpenLineAbove => start_insert(action, state_api),
+                &Action::ToVisualMode => switch_visual_mode(Mode::Visual, state_api),
+                &Action::ToVisualLineMode => switch_visual_mode(Mode::VisualLine, state_api),
+                &Action::ToVisualBlockMode => switch_visual_mode(Mode::VisualBlock, state_api),
+                &Action::ToNavigateMode => state_api.set_mode(Mode::Navigate),
+                &Action::SwapSelectionEnds => swap_selection_ends(state_api),
+                &Action::Reselect => reselect(state_api),
+                &Action::BlockInsert => start_block_insert(false, state_api),
+                &Action::BlockAppend => start_block_insert(true, state_api),
+                &Action::PutAfter => put_register(state_api, repeater_chain_to_usize(times), false, false),
+                &Action::PutBefore => put_register(state_api, repeater_chain_to_usize(times), true, false),
+                &Action::PutAfterMovingPast => put_register(state_api, repeater_chain_to_usize(times), false, true),
+                &Action::PutBeforeMovingPast => put_register(state_api, repeater_chain_to_usize(times), true, true),
+                &Action::RepeatChange => repeat_change(times, state_api),
+                &Action::JumpOlder => state_api.jump_older(repeater_chain_to_usize(times)),
+                &Action::JumpNewer => state_api.jump_newer(repeater_chain_to_usize(times)),
+                &Action::ExitEditor => state_api.request_quit(), 
                 _ => ()
+
             }
-            finalised_state = ExprState::Waiting;
+        
+        &Repeatable { ref times, expr: Some(ExecutableExpr::Edit(verb, ref motion)) } =>
+            execute_edit(verb, times, motion, state_api),
+
+        &Repeatable { ref times, expr: Some(ExecutableExpr::EditLines(verb)) } => {
+            let lines = state_api.get_editor_lines();
+            if lines.is_empty() {
+                return;
+            }
+            let first_line = state_api.get_cursor_position().y;
+            let last_line = cmp::min(first_line + repeater_chain_to_usize(times), lines.len()) - 1;
+            let end = EditorState::line_start_index(&lines, last_line);
+            let start = state_api.cursor_index;
+            apply_verb(verb, state_api, start, end, MotionKind::Linewise);
+        },
+
+        &Repeatable { ref times, expr: Some(ExecutableExpr::EditSelection(verb)) } =>
+            apply_verb_to_selection(verb, repeater_chain_to_usize(times), state_api),
+
+        &Repeatable { ref times, expr: Some(ExecutableExpr::TextObject(object)) } =>
+            select_text_object(object, repeater_chain_to_usize(times), state_api),
+
+        &Repeatable { expr: Some(ExecutableExpr::Function(FnAlias::RecordMacro, FnArg::NoArg)), .. } =>
+            stop_recording(state_api),
+        &Repeatable { expr: Some(ExecutableExpr::Function(FnAlias::RecordMacro, FnArg::Argument(name))), .. } =>
+            start_recording(name, state_api),
+        &Repeatable { ref times, expr: Some(ExecutableExpr::Function(FnAlias::PlayMacro, FnArg::Argument(name))) } =>
+            play_macro(name, repeater_chain_to_usize(times), state_api),
+        &Repeatable { expr: Some(ExecutableExpr::Function(FnAlias::SetMark, FnArg::Argument(name))), .. } =>
+            if let Err(message) = state_api.set_mark(name) {
+                state_api.set_status_message(message);
+            },
+        &Repeatable { expr: Some(ExecutableExpr::Function(FnAlias::JumpToMark, FnArg::Argument(name))), .. } =>
+            jump_to_mark(name, false, state_api),
+        &Repeatable { expr: Some(ExecutableExpr::Function(FnAlias::JumpToMarkLine, FnArg::Argument(name))), .. } =>
+            jump_to_mark(name, true, state_api),
+
+        &Repeatable { ref times, expr: Some(ref expr @ ExecutableExpr::Function(..)) } => {
+            execute_char_search(expr, times, state_api);
+        },
+        _ => ()
+    }
+
+    if repeatable.expr.as_ref().map_or(false, ExecutableExpr::can_fail) && state_api.cursor_index == start {
+        abort_macro(state_api);
+    }
+    if let Some(ExecutableExpr::Operator(ref action)) = repeatable.expr {
+        if action.is_jump() && state_api.cursor_index != start {
+            state_api.record_jump(start);
         }
+    }
+}
 
-        self.goto_state(state_api, finalised_state);
-        self.get_input_buffer()
+// Enters Insert mode with the cursor where `i`, `a`, `A`, `I`, `o` or `O` types
+fn start_insert(action: &Action, state_api: &mut EditorState) {
+    let lines = state_api.get_editor_lines();
+    let position = state_api.get_cursor_position();
+    let line_start = if lines.is_empty() { 0 } else { EditorState::line_start_index(&lines, position.y) };
+    let line_end = line_start + lines.get(position.y).map_or(0, |line| line.trim_right_matches('\n').chars().count());
+    match *action {
+        Action::Append => state_api.cursor_index = cmp::min(state_api.cursor_index + 1, line_end),
+        Action::AppendToLine => state_api.cursor_index = line_end,
+        Action::InsertAtFirstNonBlank => state_api.cursor_first_non_blank(),
+        Action::OpenLineBelow => {
+            state_api.cursor_index = line_end;
+            state_api.insert_at_cursor("\n");
+        },
+        Action::OpenLineAbove => {
+            state_api.cursor_index = line_start;
+            state_api.insert_at_cursor("\n");
+            state_api.dec_cursor();
+        },
+        _ => (),
+    }
+    state_api.set_mode(Mode::Insert);
+}
 
-        // Handle input in navigation mode here.
-        // Early iterations will not require a buffer.
-        // Later iterations allowing for composition of navigation commands will.
+// A mark which isn't set is a failing motion, stopping any macro being played
+fn jump_to_mark(name: char, linewise: bool, state_api: &mut EditorState) {
+    if let Err(message) = state_api.jump_to_mark(name, linewise) {
+        state_api.set_status_message(message);
+        abort_macro(state_api);
     }
+}
 
-    fn get_input_buffer(&self) -> &Vec<char> {
-        &self.command_buffer
+// Carries out `f`, `F`, `t`, `T`, `;` or `,`, returning the search made and whether
+// the char was found. Returns None for anything else.
+fn execute_char_search(expr: &ExecutableExpr, times: &str, state_api: &mut EditorState) -> Option<(CharSearch, bool)> {
+    let (search, repeating) = match *expr {
+        ExecutableExpr::Function(ref alias, FnArg::Argument(arg)) => (alias.char_search(arg)?, false),
+        ExecutableExpr::Operator(Action::RepeatFind) => (state_api.last_char_search?, true),
+        ExecutableExpr::Operator(Action::RepeatFindReverse) => (state_api.last_char_search?.reversed(), true),
+        _ => return None,
+    };
+    if !repeating {
+        state_api.last_char_search = Some(search);
+    }
+    let found = state_api.find_char(search, repeater_chain_to_usize(times), repeating);
+    Some((search, found))
+}
+
+// Carries out the motion to find the text the verb acts on, then applies the verb.
+fn execute_edit(verb: Verb, times: &str, motion: &ExecutableExpr, state_api: &mut EditorState) {
+    if let ExecutableExpr::TextObject(object) = *motion {
+        if let Some(range) = state_api.text_object_range(object, repeater_chain_to_usize(times)) {
+            apply_verb_to_range(verb, state_api, range);
+        }
+        return;
     }
 
-    fn push_input(&mut self, ch: char) {
-        self.command_buffer.push(ch);
+    // A char search which fails leaves nothing for the verb to act on. Forwards
+    // they include the char they stop at, backwards they don't.
+    let start = state_api.cursor_index;
+
+    // A mark takes the verb up to it, or over whole lines for `'`
+    let mark = match *motion {
+        ExecutableExpr::Function(FnAlias::JumpToMark, FnArg::Argument(name)) => Some((name, MotionKind::Exclusive)),
+        ExecutableExpr::Function(FnAlias::JumpToMarkLine, FnArg::Argument(name)) => Some((name, MotionKind::Linewise)),
+        _ => None,
+    };
+    if let Some((name, kind)) = mark {
+        match state_api.mark_index(name) {
+            Ok(end) => apply_verb(verb, state_api, start, end, kind),
+            Err(message) => {
+                state_api.set_status_message(message);
+                abort_macro(state_api);
+            }
+        }
+        return;
+    }
+    if let Some((search, found)) = execute_char_search(motion, times, state_api) {
+        if found {
+            let end = state_api.cursor_index;
+            state_api.cursor_index = start;
+            let kind = if search.forward { MotionKind::Inclusive } else { MotionKind::Exclusive };
+            apply_verb(verb, state_api, start, end, kind);
+        } else {
+            abort_macro(state_api);
+        }
+        return;
+    }
+
+    let kind = match *motion {
+        ExecutableExpr::Operator(ref action) => action.motion_kind(),
+        _ => None,
+    };
+    let kind = match kind {
+        Some(kind) => kind,
+        None => return,
+    };
+
+    let text: Vec<char> = state_api.get_text().chars().collect();
+    let on_word = text.get(start).map_or(false, |ch| !ch.is_whitespace());
+    let end = match *motion {
+        // `cw` on a word changes to the end of it, leaving the whitespace after it
+        ExecutableExpr::Operator(Action::StartNextWord) if verb == Verb::Change && on_word =>
+            end_of_words(&text, start, repeater_chain_to_usize(times), false),
+        ExecutableExpr::Operator(Action::StartNextBigWord) if verb == Verb::Change && on_word =>
+            end_of_words(&text, start, repeater_chain_to_usize(times), true),
+        // `l` stops on the last char of the line, but `dl` there still takes it
+        ExecutableExpr::Operator(Action::Right) => {
+            let line_end = start + text.iter().skip(start).take_while(|&&ch| ch != '\n').count();
+            cmp::min(start + repeater_chain_to_usize(times), line_end)
+        },
+        _ => {
+            execute_expression(&Repeatable { times: times.to_string(), expr: Some(motion.clone()) }, state_api);
+            state_api.cursor_index
+        }
+    };
+    state_api.cursor_index = start;
+    // `$` on an empty line stops on the line break, which isn't taken with the line
+    let kind = if kind == MotionKind::Inclusive && end >= start && text.get(end) == Some(&'\n') {
+        MotionKind::Exclusive
+    } else {
+        kind
+    };
+    apply_verb(verb, state_api, start, end, kind);
+}
+
+// What the key does while typing in Insert or Command mode, going by its built-in
+// binding. Keys without one are typed as they are.
+fn typing_action(mode: Mode, key: Key, state_api: &EditorState) -> Option<Action> {
+    match state_api.keymap.builtin(mode, &[key]) {
+        Lookup::Match(&Binding::Operator(ref action)) | Lookup::Ambiguous(&Binding::Operator(ref action)) => Some(action.clone()),
+        _ => None,
     }
 }
 
@@ -374,12 +1142,10 @@ impl CommandModeInputHandler {
     }
 
     fn process_command_buffer(&mut self, state: &mut EditorState) {
-        for cmd_char in self.command_buffer.iter() {
-            match cmd_char {
-                &'w' => write_file(state),
-                &'q' => exit(0),
-                _ => ()
-            }
+        let command_line: String = self.command_buffer.iter().collect();
+        run_command(&command_line, state);
+        if !command_line.trim().is_empty() {
+            state.registers.last_command = Some(command_line);
         }
         self.command_buffer.clear();
     }
@@ -389,49 +1155,654 @@ impl ModeInputHandler for CommandModeInputHandler {
         // Handle Command mode input - add chars to buffer until enter is pressed.
         // When enter is pressed, execute buffered commands and clear buffer.
         // Return to navigate mode.
-        match event {
-            Event::Key(Key::Char('\n')) => {
+        let key = match event {
+            Event::Key(key) => key,
+            _ => return self.get_input_buffer(),
+        };
+        match typing_action(Mode::Command, key, state_api) {
+            Some(Action::RunCommandLine) => {
                 self.process_command_buffer(state_api);
                 state_api.set_mode(Mode::Navigate);
             },
-            Event::Key(Key::Char(ch)) => self.command_buffer.push(ch),
-            _ => ()
+            Some(Action::ToNavigateMode) => {
+                self.command_buffer.clear();
+                state_api.set_mode(Mode::Navigate);
+            },
+            Some(Action::DeleteCharBefore) => {
+                self.command_buffer.pop();
+            },
+            Some(_) => (),
+            None => if let Key::Char(ch) = key {
+                self.command_buffer.push(ch);
+            },
         }
         self.get_input_buffer()
     }
 
     fn get_input_buffer(&self) -> &Vec<char> {
         &self.command_buffer
-    }
-
-    fn push_input(&mut self, ch: char) {
-        self.command_buffer.push(ch);
-    }
-}
+    }}
 
 pub struct InsertModeInputHandler {
-    command_buffer: Vec<char>
+    command_buffer: Vec<char>,
+    inserted: String,  // The text typed since Insert mode was entered, kept as `".` when it's left
+    keys: Vec<Key>,  // The keys typed since Insert mode was entered, kept for `.`
 }
 impl InsertModeInputHandler {
     pub fn new() -> Self {
         Self {
-            command_buffer: vec![]
+            command_buffer: vec![],
+            inserted: String::new(),
+            keys: vec![],
+        }
+    }
+
+    fn type_key(&mut self, key: Key, state_api: &mut EditorState) {
+        self.keys.push(key);
+        match typing_action(Mode::Insert, key, state_api) {
+            Some(Action::DeleteCharBefore) => {
+                self.inserted.pop();
+                state_api.soft_backspace();
+            },
+            Some(Action::InsertTab) => {
+                self.inserted.push('\t');
+                state_api.insert_tab();
+            },
+            Some(Action::Left) => state_api.dec_cursor(),
+            Some(Action::Right) => state_api.inc_cursor(),
+            Some(_) => (),
+            None => if let Key::Char(ch) = key {
+                self.inserted.push(ch);
+                state_api.insert_at_cursor(&ch.to_string());
+            },
+        }
+    }
+
+    // A count before `i` types the text that many times over, each time on a line
+    // of its own for `o` and `O`
+    fn repeat_typing(&mut self, times: usize, new_lines: bool, state_api: &mut EditorState) {
+        let keys = self.keys.clone();
+        for _ in 1..times {
+            if new_lines {
+                state_api.insert_at_cursor("\n");
+            }
+            for &key in keys.iter() {
+                self.type_key(key, state_api);
+            }
         }
+        self.keys = keys;
     }
 }
 impl ModeInputHandler for InsertModeInputHandler {
     fn handle_input(&mut self, event: Event, state_api: &mut EditorState) -> &Vec<char> {
         // Handle input in insertion mode. Will need reference to the StateApi to 
         // update the editor state.
+        let key = match event {
+            Event::Key(key) => key,
+            _ => return self.get_input_buffer(),
+        };
+        match typing_action(Mode::Insert, key, state_api) {
+            Some(Action::ToNavigateMode) => {
+                let index = state_api.cursor_index;
+                state_api.marks.set_automatic('^', index);
+                // The change which entered Insert mode is complete, along with what was typed
+                if let Some(mut change) = state_api.pending_change.take() {
+                    if let Some(ExecutableExpr::Operator(ref action)) = change.repeatable.expr {
+                        if action.starts_insert() {
+                            self.repeat_typing(repeater_chain_to_usize(&change.repeatable.times), action.opens_line(), state_api);
+                        }
+                    }
+                    change.inserted = self.keys.clone();
+                    state_api.last_change = Some(change);
+                }
+                self.keys.clear();
+                // The cursor goes back onto the last char typed, as in Vim, except
+                // after typing into a block, where it goes to the block's corner
+                if state_api.block_insert.is_none() && state_api.get_cursor_position().x > 0 {
+                    state_api.dec_cursor();
+                }
+                finish_block_insert(state_api);
+                if !self.inserted.is_empty() {
+                    state_api.registers.last_inserted = Some(self.inserted.clone());
+                    self.inserted.clear();
+                }
+                state_api.set_mode(Mode::Navigate);
+            },
+            _ => self.type_key(key, state_api),
+        }
         self.get_input_buffer()
     }
 
     fn get_input_buffer(&self) -> &Vec<char> {
         &self.command_buffer
+    }}
+
+
+#[cfg(test)]
+mod tests {
+    use termion::event::Event;
+
+    use controller::events::InputModeMultiplexer;
+    use controller::keymap::{parse_keys, format_keys};
+    use data::editor_state::{StateApi, EditorState, Mode};
+    use data::piece_table::PieceTable;
+
+    // Types the keys, given in key notation, into a buffer holding the text, on a
+    // screen four lines high
+    fn after_keys(text: &str, keys: &str) -> EditorState {
+        typing(text, keys).0
     }
 
-    fn push_input(&mut self, ch: char) {
-        
+    // The same, keeping the input handlers too, for keys still waiting on more
+    fn typing(text: &str, keys: &str) -> (EditorState, InputModeMultiplexer) {
+        let mut state = EditorState::new(String::from("test.txt"), Mode::Navigate, 0, PieceTable::from_text(text.to_string()), vec![]);
+        state.viewport.text_width = 80;
+        state.viewport.text_height = 4;
+        let mut input = InputModeMultiplexer::new();
+        for key in parse_keys(keys, "").unwrap() {
+            input.do_action_for_input(Event::Key(key), &mut state);
+        }
+        (state, input)
+    }
+
+    fn cursor_after(text: &str, keys: &str) -> usize {
+        after_keys(text, keys).cursor_index
+    }
+
+    const TEXT: &str = "  one two.  Three (four)\n\nfive-six\nseven";
+
+    #[test]
+    fn line_verbs_on_an_empty_buffer() {
+        for keys in ["dd", "yy", ">>", "==", "gUU"].iter() {
+            assert_eq!(after_keys("", keys).get_text(), "");
+        }
+        assert_eq!(after_keys("one\ntwo\n", "ggdGdd").get_text(), "");
+    }
+
+    #[test]
+    fn verbs_at_the_end_of_the_file() {
+        assert_eq!(after_keys("abc", "$dl").get_text(), "ab");
+        assert_eq!(after_keys("abc", "$gUl").get_text(), "abC");
+        assert_eq!(after_keys("abc", "$yl$p").get_text(), "abcc");
+    }
+
+    #[test]
+    fn line_verbs_after_non_ascii_text() {
+        let text = "héllo\nwörld\nxyz\n";
+        assert_eq!(after_keys(text, "jdd").get_text(), "héllo\nxyz\n");
+        assert_eq!(after_keys(text, "jjdd").get_text(), "héllo\nwörld\n");
+        assert_eq!(after_keys(text, "jyyP").get_text(), "héllo\nwörld\nwörld\nxyz\n");
+        assert_eq!(after_keys(text, "j>>").get_text(), "héllo\n\twörld\nxyz\n");
     }
-}
 
+    #[test]
+    fn line_motions() {
+        assert_eq!(cursor_after(TEXT, "$"), 23);
+        assert_eq!(cursor_after(TEXT, "$0"), 0);
+        assert_eq!(cursor_after(TEXT, "^"), 2);
+        assert_eq!(cursor_after(TEXT, "2G$"), 25);
+        assert_eq!(cursor_after(TEXT, "G$"), 39);
+        assert_eq!(cursor_after(TEXT, "2$"), 25);
+        assert_eq!(cursor_after(TEXT, "10|"), 9);
+        assert_eq!(cursor_after(TEXT, "G3|"), 37);
+        assert_eq!(after_keys("abc\ndef\n", "d$").get_text(), "\ndef\n");
+        assert_eq!(after_keys("abc\n\ndef\n", "jd$").get_text(), "abc\n\ndef\n");
+    }
+
+    #[test]
+    fn moving_between_lines() {
+        assert_eq!(cursor_after(TEXT, "G"), 35);
+        assert_eq!(cursor_after(TEXT, "Ggg"), 2);
+        assert_eq!(cursor_after(TEXT, "3G"), 26);
+        assert_eq!(cursor_after(TEXT, "9G"), 35);
+        assert_eq!(cursor_after(TEXT, "75%"), 26);
+        assert_eq!(cursor_after(TEXT, "H"), 2);
+        assert_eq!(cursor_after(TEXT, "M"), 25);
+        assert_eq!(cursor_after(TEXT, "L"), 35);
+    }
+
+    #[test]
+    fn scrolling_to_the_cursor() {
+        // The long line wraps onto two rows of the four on screen
+        let text = "a\n".repeat(8) + &"b".repeat(100) + "\nc\n";
+        let mut state = after_keys(&text, "G");
+        state.scroll_to_cursor(80, 4);
+        assert_eq!(state.viewport.top_line, 7);
+        state.cursor_index = 0;
+        state.scroll_to_cursor(80, 4);
+        assert_eq!(state.viewport.top_line, 0);
+        state.settings.apply("nowrap", false).unwrap();
+        state.cursor_to_eof();
+        state.scroll_to_cursor(80, 4);
+        assert_eq!(state.viewport.top_line, 6);
+    }
+
+    #[test]
+    fn j_and_k_keep_the_column() {
+        assert_eq!(cursor_after(TEXT, "5|j"), 25);
+        assert_eq!(cursor_after(TEXT, "5|jj"), 30);
+        assert_eq!(cursor_after(TEXT, "5|jjj"), 39);
+        assert_eq!(cursor_after(TEXT, "5|jjjkkk"), 4);
+        assert_eq!(cursor_after(TEXT, "$j"), 25);
+        assert_eq!(cursor_after(TEXT, "$jj"), 33);
+        assert_eq!(cursor_after(TEXT, "$jjj"), 39);
+        assert_eq!(cursor_after(TEXT, "$jjjk"), 33);
+    }
+
+    #[test]
+    fn motions_over_non_ascii_text() {
+        let text = "héllo\nwörld\nxyz\n";
+        assert_eq!(cursor_after(text, "jj"), 12);
+        assert_eq!(cursor_after(text, "lljj"), 14);
+        assert_eq!(cursor_after(text, "lljjk"), 8);
+        assert_eq!(cursor_after(text, "$j"), 10);
+        assert_eq!(cursor_after(text, "j^"), 6);
+        assert_eq!(cursor_after(text, "Gk"), 6);
+        assert_eq!(cursor_after("héllo wörld", "w"), 6);
+        assert_eq!(after_keys(text, "j:.normal dl<CR>").get_text(), "héllo\nörld\nxyz\n");
+        assert_eq!(after_keys(text, ":2,3normal >><CR>").get_text(), "héllo\n\twörld\n\txyz\n");
+    }
+
+    #[test]
+    fn word_motions() {
+        assert_eq!(cursor_after(TEXT, "e"), 4);
+        assert_eq!(cursor_after(TEXT, "W"), 2);
+        assert_eq!(cursor_after(TEXT, "3Gw"), 30);
+        assert_eq!(cursor_after(TEXT, "3GW"), 35);
+        assert_eq!(cursor_after(TEXT, "3GE"), 33);
+        assert_eq!(cursor_after(TEXT, "GB"), 26);
+        assert_eq!(cursor_after(TEXT, "Gge"), 33);
+        assert_eq!(cursor_after("one two", "$ge"), 2);
+    }
+
+    #[test]
+    fn sentence_and_paragraph_motions() {
+        assert_eq!(cursor_after(TEXT, ")"), 2);
+        assert_eq!(cursor_after(TEXT, "))"), 12);
+        assert_eq!(cursor_after(TEXT, "$("), 12);
+        assert_eq!(cursor_after(TEXT, "}"), 25);
+        assert_eq!(cursor_after(TEXT, "}}"), 39);
+        assert_eq!(cursor_after(TEXT, "G{"), 25);
+        assert_eq!(cursor_after(TEXT, "G{{"), 0);
+    }
+
+    #[test]
+    fn matching_bracket() {
+        assert_eq!(cursor_after("f(a[1])", "$%"), 1);
+        assert_eq!(cursor_after("f(a[1])", "%"), 6);
+        assert_eq!(cursor_after("f(a[1])", "3l%"), 5);
+    }
+
+    #[test]
+    fn playing_macros() {
+        let lines = "a\nb\nc\nd\ne\n";
+        assert_eq!(after_keys(lines, "qajq2@a").get_cursor_position().y, 3);
+        assert_eq!(after_keys(lines, "qajq2@a@@").get_cursor_position().y, 4);
+        // A motion which fails stops the copies still to come
+        let state = after_keys(lines, "qajq1000000@a");
+        assert_eq!(state.get_cursor_position().y, 4);
+        assert!(state.typeahead.is_empty());
+    }
+
+    #[test]
+    fn a_macro_which_plays_itself_is_given_up_on() {
+        let state = after_keys("abc\n", "qa@aq@a");
+        assert_eq!(state.get_status_message().map(|(_, message)| message), Some("Recursive macro"));
+        assert!(state.typeahead.is_empty());
+    }
+
+    #[test]
+    fn typing_keys_with_bindings() {
+        assert_eq!(after_keys("", "ia<Tab>b<BS>c<Left>d<Esc>").get_text(), "a\tdc");
+        assert_eq!(after_keys("", ":set tx<BS>s=5<CR>").settings.tabstop(), 5);
+        let state = after_keys("", ":set ts=5<Esc>");
+        assert_eq!(state.settings.tabstop(), 8);
+        assert_eq!(*state.get_mode(), Mode::Navigate);
+    }
+
+    #[test]
+    fn sequences_of_several_keys() {
+        let (state, input) = typing("one\ntwo\n", "G2g");
+        assert_eq!(format_keys(&input.pending_keys(&state)), "2g");
+        assert!(!input.awaiting_timeout(&state));
+        assert_eq!(cursor_after("one\ntwo\n", "G2gg"), 4);
+        assert_eq!(after_keys("one\ntwo\n", "gUiw").get_text(), "ONE\ntwo\n");
+        // Keys which aren't bound to anything are dropped, along with the count
+        let (state, input) = typing("one\ntwo\n", "2gqj");
+        assert_eq!((state.get_cursor_position().y, input.pending_keys(&state)), (1, vec![]));
+    }
+
+    #[test]
+    fn ambiguous_sequences_wait_for_a_timeout() {
+        let maps = ":nnoremap ,d dd<CR>:nnoremap , l<CR>";
+        assert_eq!(after_keys("one\ntwo\n", &format!("{},d", maps)).get_text(), "two\n");
+
+        // `,` on its own could still become `,d`
+        let (mut state, mut input) = typing("one\ntwo\n", &format!("{},", maps));
+        assert!(input.awaiting_timeout(&state));
+        assert_eq!(format_keys(&input.pending_keys(&state)), ",");
+        assert_eq!(state.cursor_index, 0);
+        input.timeout(&mut state);
+        assert_eq!(state.cursor_index, 1);
+        assert!(!input.awaiting_timeout(&state));
+
+        // Or another key shows it isn't, and is then taken by itself
+        let (state, input) = typing("one\ntwo\n", &format!("{},j", maps));
+        assert_eq!((state.cursor_index, input.pending_keys(&state)), (5, vec![]));
+    }
+
+    #[test]
+    fn word_and_sentence_objects() {
+        assert_eq!(after_keys("one two three", "wdiw").get_text(), "one  three");
+        assert_eq!(after_keys("one two three", "wdaw").get_text(), "one three");
+        assert_eq!(after_keys("one two three", "$daw").get_text(), "one two");
+        assert_eq!(after_keys("one two three", "d2aw").get_text(), "three");
+        assert_eq!(after_keys("a.b c.d", "diW").get_text(), " c.d");
+        assert_eq!(after_keys("a.b c.d", "diw").get_text(), ".b c.d");
+        assert_eq!(after_keys("One. Two three.  Four.", "fTdis").get_text(), "One.   Four.");
+        assert_eq!(after_keys("One. Two three.  Four.", "fTdas").get_text(), "One. Four.");
+    }
+
+    #[test]
+    fn quote_and_bracket_objects() {
+        let text = "say(\"a, b\", [1, (2)])";
+        assert_eq!(after_keys(text, "fadi\"").get_text(), "say(\"\", [1, (2)])");
+        assert_eq!(after_keys(text, "fada\"").get_text(), "say(, [1, (2)])");
+        assert_eq!(after_keys(text, "f2di(").get_text(), "say(\"a, b\", [1, ()])");
+        assert_eq!(after_keys(text, "f2d2i(").get_text(), "say()");
+        assert_eq!(after_keys(text, "f2da]").get_text(), "say(\"a, b\", )");
+        assert_eq!(after_keys(text, "f2dib").get_text(), "say(\"a, b\", [1, ()])");
+        assert_eq!(after_keys("{ x }", "di}").get_text(), "{}");
+        assert_eq!(after_keys("{ x }", "diB").get_text(), "{}");
+        assert_eq!(after_keys("if a<b>c", "fbdi<lt>").get_text(), "if a<>c");
+        // With nothing around the cursor, nothing is deleted
+        assert_eq!(after_keys(text, "di{").get_text(), text);
+        assert_eq!(after_keys("say it", "di'").get_text(), "say it");
+    }
+
+    #[test]
+    fn tag_and_indent_objects() {
+        let html = "<p>one <b>two</b></p>";
+        assert_eq!(after_keys(html, "fwdit").get_text(), "<p>one <b></b></p>");
+        assert_eq!(after_keys(html, "fwdat").get_text(), "<p>one </p>");
+        assert_eq!(after_keys(html, "fwd2it").get_text(), "<p></p>");
+
+        let code = "def f():\n    if x:\n        y()\n\n    z()\nend\n";
+        assert_eq!(after_keys(code, "jjdii").get_text(), "def f():\n    if x:\n\n    z()\nend\n");
+        assert_eq!(after_keys(code, "jdii").get_text(), "def f():\nend\n");
+        assert_eq!(after_keys(code, "jjdai").get_text(), "def f():\n\n    z()\nend\n");
+        assert_eq!(after_keys(code, "jjd2ii").get_text(), "def f():\nend\n");
+    }
+
+    #[test]
+    fn paragraph_objects() {
+        let text = "one\ntwo\n\nthree\n\nfour\n";
+        assert_eq!(after_keys(text, "dip").get_text(), "\nthree\n\nfour\n");
+        assert_eq!(after_keys(text, "dap").get_text(), "three\n\nfour\n");
+        assert_eq!(after_keys(text, "yapGp").get_text(), "one\ntwo\n\nthree\n\nfour\none\ntwo\n\n");
+    }
+
+    #[test]
+    fn finding_chars_on_the_line() {
+        let text = "a-b-c-d\nx-y\n";
+        assert_eq!(cursor_after(text, "f-"), 1);
+        assert_eq!(cursor_after(text, "2f-"), 3);
+        assert_eq!(cursor_after(text, "t-"), 0);
+        assert_eq!(cursor_after(text, "lt-"), 2);
+        assert_eq!(cursor_after(text, "$F-"), 5);
+        assert_eq!(cursor_after(text, "$T-"), 6);
+        assert_eq!(cursor_after(text, "$2T-"), 4);
+        // A search doesn't leave the line, and nothing moves if it fails
+        assert_eq!(cursor_after(text, "fy"), 0);
+        assert_eq!(cursor_after(text, "f-4f-"), 1);
+    }
+
+    #[test]
+    fn repeating_char_searches() {
+        let text = "a-b-c-d\n";
+        assert_eq!(cursor_after(text, "f-;"), 3);
+        assert_eq!(cursor_after(text, "f-;;,"), 3);
+        assert_eq!(cursor_after(text, "f-2;"), 5);
+        assert_eq!(cursor_after(text, "$F-;,"), 5);
+        // `;` after `t` goes on past the char it stopped next to
+        assert_eq!(cursor_after(text, "t-;"), 2);
+        assert_eq!(cursor_after(text, "$T-;"), 4);
+        assert_eq!(cursor_after(text, ";"), 0);
+        // The search is remembered when a verb uses it
+        assert_eq!(cursor_after(text, "dt-;"), 1);
+        assert_eq!(after_keys(text, "df-;.").get_text(), "bd\n");
+        assert_eq!(after_keys(text, "d2f-").get_text(), "c-d\n");
+        assert_eq!(after_keys(text, "dt-").get_text(), "-b-c-d\n");
+        assert_eq!(after_keys(text, "$dF-").get_text(), "a-b-cd\n");
+        assert_eq!(after_keys(text, "$dT-").get_text(), "a-b-c-d\n");
+    }
+
+    #[test]
+    fn visual_selections() {
+        let text = "one two\nthree four\nfive six\n";
+        assert_eq!(after_keys(text, "wvld").get_text(), "one o\nthree four\nfive six\n");
+        assert_eq!(after_keys(text, "wvjd").get_text(), "one  four\nfive six\n");
+        assert_eq!(after_keys(text, "vex").get_text(), " two\nthree four\nfive six\n");
+        assert_eq!(after_keys(text, "wvhhU").get_text(), "onE Two\nthree four\nfive six\n");
+        assert_eq!(after_keys(text, "wvllohd").get_text(), "one\nthree four\nfive six\n");
+        assert_eq!(after_keys(text, "vly$gvd").get_text(), "e two\nthree four\nfive six\n");
+        assert_eq!(after_keys(text, "jviwy$p").get_text(), "one two\nthree fourthree\nfive six\n");
+        let state = after_keys(text, "wvjl<Esc>");
+        assert_eq!((state.get_mode().clone(), state.cursor_index), (Mode::Navigate, 13));
+    }
+
+    #[test]
+    fn visual_line_and_block_selections() {
+        let text = "one two\nthree four\nfive six\n";
+        assert_eq!(after_keys(text, "jVd").get_text(), "one two\nfive six\n");
+        assert_eq!(after_keys(text, "lVj>").get_text(), "\tone two\n\tthree four\nfive six\n");
+        assert_eq!(after_keys(text, "VjyGp").get_text(), "one two\nthree four\nfive six\none two\nthree four\n");
+        // Switching between the Visual modes keeps the selection
+        assert_eq!(after_keys(text, "lvjVd").get_text(), "five six\n");
+        assert_eq!(after_keys(text, "<C-v>jld").get_text(), "e two\nree four\nfive six\n");
+        assert_eq!(after_keys(text, "l<C-v>jjhU").get_text(), "ONe two\nTHree four\nFIve six\n");
+        assert_eq!(after_keys(text, "<C-v>jI# <Esc>").get_text(), "# one two\n# three four\nfive six\n");
+        assert_eq!(after_keys(text, "<C-v>jlA!<Esc>").get_text(), "on!e two\nth!ree four\nfive six\n");
+    }
+
+    #[test]
+    fn named_registers() {
+        let text = "one\ntwo\nthree\n";
+        assert_eq!(after_keys(text, "\"ayyj\"Ayy\"ap").get_text(), "one\ntwo\none\ntwo\nthree\n");
+        assert_eq!(after_keys("one two", "\"ayiww\"Ayiw$\"ap").get_text(), "one twoonetwo");
+        // Appending lines to words makes lines of them all
+        assert_eq!(after_keys(text, "\"ayiwj\"AyyG\"ap").get_text(), "one\ntwo\nthree\none\ntwo\n");
+        assert_eq!(message(&after_keys(text, "\"!yy")), Some("Invalid register name: \"!"));
+    }
+
+    #[test]
+    fn numbered_registers_and_the_black_hole() {
+        let text = "one\ntwo\nthree four\n";
+        // Each delete of lines pushes the older ones along
+        assert_eq!(after_keys(text, "dddd\"1P").get_text(), "two\nthree four\n");
+        assert_eq!(after_keys(text, "dddd\"2P").get_text(), "one\nthree four\n");
+        // Deletes within a line go to `"-` instead, and yanks to `"0`
+        assert_eq!(after_keys(text, "ddjdw\"1p").get_text(), "two\nfour\none\n");
+        assert_eq!(after_keys(text, "jjdwp").get_text(), "one\ntwo\nfthree our\n");
+        assert_eq!(after_keys(text, "yyjdd\"0p").get_text(), "one\nthree four\none\n");
+        // Nothing is kept of what goes to `"_`, and `""` still holds the yank
+        assert_eq!(after_keys(text, "yyj\"_ddp").get_text(), "one\nthree four\none\n");
+    }
+
+    #[test]
+    fn putting_text() {
+        let text = "one\ntwo\nthree\n";
+        assert_eq!(after_keys(text, "yyjP").get_text(), "one\none\ntwo\nthree\n");
+        assert_eq!(after_keys(text, "yy2p").get_text(), "one\none\none\ntwo\nthree\n");
+        assert_eq!(after_keys("ab", "yl3p").get_text(), "aaaab");
+        assert_eq!(after_keys("ab", "ylP").get_text(), "aab");
+        // `gp` and `gP` leave the cursor just after the text put
+        assert_eq!(cursor_after(text, "yygp"), 8);
+        assert_eq!(cursor_after(text, "yygP"), 4);
+        assert_eq!(cursor_after("ab", "yl2gp"), 3);
+        assert_eq!(cursor_after(text, "yyp"), 4);
+
+        let state = after_keys(text, "\"ayiwjdd:registers a1<CR>");
+        assert_eq!(state.get_panel(), Some(&vec![String::from("Type Name Content"), String::from("  l  \"1   two^J"), String::from("  c  \"a   one")]));
+    }
+
+    #[test]
+    fn entering_insert_mode() {
+        let text = "one\n  two\n";
+        assert_eq!(after_keys(text, "a!<Esc>").get_text(), "o!ne\n  two\n");
+        assert_eq!(after_keys(text, "$a!<Esc>").get_text(), "one!\n  two\n");
+        assert_eq!(after_keys(text, "A!<Esc>").get_text(), "one!\n  two\n");
+        assert_eq!(after_keys(text, "jI#<Esc>").get_text(), "one\n  #two\n");
+        assert_eq!(after_keys(text, "ox<Esc>").get_text(), "one\nx\n  two\n");
+        assert_eq!(after_keys(text, "jOx<Esc>").get_text(), "one\nx\n  two\n");
+        assert_eq!(after_keys(text, "3ox<Esc>").get_text(), "one\nx\nx\nx\n  two\n");
+        assert_eq!(after_keys(text, "2Ox<Esc>").get_text(), "x\nx\none\n  two\n");
+        assert_eq!(after_keys(text, "2a-<Esc>").get_text(), "o--ne\n  two\n");
+        assert_eq!(after_keys("", "ox<Esc>").get_text(), "\nx");
+        assert_eq!(after_keys("\n", "a!<Esc>").get_text(), "!\n");
+        // Leaving Insert mode puts the cursor on the last char typed
+        assert_eq!(cursor_after(text, "A!?<Esc>"), 4);
+        assert_eq!(cursor_after(text, "ix<BS><Esc>"), 0);
+        assert_eq!(cursor_after(text, "jox<Esc>"), 10);
+    }
+
+    #[test]
+    fn deleting_and_substituting_chars() {
+        let text = "one\ntwo\n";
+        assert_eq!(after_keys(text, "x").get_text(), "ne\ntwo\n");
+        assert_eq!(after_keys(text, "5x").get_text(), "\ntwo\n");
+        assert_eq!(after_keys(text, "$x").get_text(), "on\ntwo\n");
+        assert_eq!(after_keys(text, "xp").get_text(), "noe\ntwo\n");
+        assert_eq!(after_keys("\nab\n", "x").get_text(), "\nab\n");
+        assert_eq!(after_keys(text, "sX<Esc>").get_text(), "Xne\ntwo\n");
+        assert_eq!(after_keys(text, "2sX<Esc>").get_text(), "Xe\ntwo\n");
+        // `dx` isn't anything
+        assert_eq!(after_keys(text, "dxl").get_text(), text);
+    }
+
+    #[test]
+    fn repeating_insert_mode_changes() {
+        let text = "one\ntwo\n";
+        assert_eq!(after_keys(text, "A!<Esc>j.").get_text(), "one!\ntwo!\n");
+        assert_eq!(after_keys(text, "I-<Esc>j.").get_text(), "-one\n-two\n");
+        assert_eq!(after_keys(text, "ox<Esc>.").get_text(), "one\nx\nx\ntwo\n");
+        assert_eq!(after_keys(text, "sX<Esc>l.").get_text(), "XXe\ntwo\n");
+        // Each repeat goes on from where the last one left the cursor
+        assert_eq!(after_keys(text, "ia<Esc>.").get_text(), "aaone\ntwo\n");
+        assert_eq!(after_keys(text, "a-<Esc>..").get_text(), "o---ne\ntwo\n");
+        assert_eq!(cursor_after(text, "a-<Esc>.."), 3);
+        assert_eq!(cursor_after(text, "A!<Esc>j."), 8);
+    }
+
+    #[test]
+    fn repeating_changes_with_counts() {
+        assert_eq!(after_keys("abcdefgh", "x3.").get_text(), "efgh");
+        // A new count is kept for later repeats
+        assert_eq!(after_keys("abcdefgh", "x3..").get_text(), "h");
+        assert_eq!(after_keys("a b c d e", "dw2.").get_text(), "d e");
+        assert_eq!(after_keys("a b c d e", "2dw.").get_text(), "e");
+        assert_eq!(after_keys("one", "3i-<Esc>").get_text(), "---one");
+        assert_eq!(after_keys("one", "3i-<Esc>.").get_text(), "------one");
+        assert_eq!(after_keys("one", "i-<Esc>2.").get_text(), "---one");
+        assert_eq!(after_keys("a\nb\nc\nd\n", "dd2.").get_text(), "d\n");
+        assert_eq!(after_keys("ab\n", "\"ayl\"ap.").get_text(), "aaab\n");
+    }
+
+    #[test]
+    fn marks_follow_edits() {
+        let text = "one\ntwo\nthree\nfour\n";
+        assert_eq!(cursor_after(text, "jlmagg`a"), 5);
+        assert_eq!(cursor_after(text, "jlmaggOnew<Esc>`a"), 9);
+        assert_eq!(cursor_after(text, "jlmaggdd`a"), 1);
+        // A mark in text which is deleted moves to where the text was
+        assert_eq!(cursor_after(text, "jlmadd`a"), 4);
+        assert_eq!(cursor_after("one\n  two\n", "j$mk0gg'k"), 6);
+        assert_eq!(message(&after_keys(text, "`b")), Some("Mark not set"));
+        assert_eq!(message(&after_keys(text, "m!")), Some("Argument must be a letter or forward/backward quote"));
+        // `'.` is the last change
+        assert_eq!(cursor_after(text, "jjlxgg`."), 9);
+    }
+
+    #[test]
+    fn verbs_up_to_marks() {
+        let text = "one\ntwo\nthree\nfour\n";
+        assert_eq!(after_keys(text, "jlmagg0d`a").get_text(), "wo\nthree\nfour\n");
+        assert_eq!(after_keys(text, "jjmaggd'a").get_text(), "four\n");
+        assert_eq!(after_keys(text, "jlmaggy`a$p").get_text(), "oneone\nt\ntwo\nthree\nfour\n");
+    }
+
+    #[test]
+    fn the_jump_list() {
+        let text = "one\ntwo\nthree\nfour\n";
+        assert_eq!(cursor_after(text, "G''"), 0);
+        assert_eq!(cursor_after(text, "G``"), 0);
+        assert_eq!(cursor_after(text, "G''''"), 14);
+        assert_eq!(cursor_after(text, "3GG<C-o>"), 8);
+        assert_eq!(cursor_after(text, "3GG<C-o><C-o>"), 0);
+        assert_eq!(cursor_after(text, "3GG<C-o><C-o><C-i>"), 8);
+        assert_eq!(cursor_after(text, "3GG<C-o><C-o><Tab><Tab>"), 14);
+        assert_eq!(cursor_after(text, "3GG2<C-o>"), 0);
+        // Going back past the oldest jump doesn't move
+        assert_eq!(cursor_after(text, "G<C-o><C-o>"), 0);
+        // Motions which aren't jumps aren't noted
+        assert_eq!(cursor_after(text, "jj<C-o>"), 8);
+        // Jumps follow the text as it's edited
+        assert_eq!(cursor_after(text, "Gggdd<C-o>"), 10);
+
+        let state = after_keys(text, "3GG:jumps<CR>");
+        assert_eq!(state.get_panel(), Some(&vec![
+            String::from(" jump line  col file/text"),
+            String::from("    2     1    0 one"),
+            String::from("    1     3    0 three"),
+            String::from(">"),
+        ]));
+        let state = after_keys(text, "jlma:marks<CR>");
+        assert_eq!(state.get_panel(), Some(&vec![String::from("mark line  col file/text"), String::from(" a      2    1 two")]));
+    }
+
+    fn message(state: &EditorState) -> Option<&str> {
+        state.get_status_message().map(|(_, message)| message)
+    }
+
+    #[test]
+    fn setting_options() {
+        let state = after_keys("", ":set ts=4 sw=0 nowrap list<CR>");
+        assert_eq!((state.settings.tabstop(), state.settings.effective_shiftwidth()), (4, 4));
+        assert_eq!((state.settings.wrap(), state.settings.list()), (false, true));
+        assert!(!after_keys("", ":set invwrap<CR>").settings.wrap());
+        assert!(after_keys("", ":set wrap!<CR>:set wrap!<CR>").settings.wrap());
+        assert_eq!(after_keys("", ":set ts=4<CR>:set ts&<CR>").settings.tabstop(), 8);
+        assert_eq!(message(&after_keys("", ":set nowrap ts=3<CR>:set wrap? ts? sts<CR>")), Some("nowrap  tabstop=3  softtabstop=0"));
+
+        // A bad argument is reported, and nothing after it is applied
+        let state = after_keys("", ":set ts=0 sw=2<CR>");
+        assert_eq!(message(&state), Some("Invalid tabstop: must be at least 1"));
+        assert_eq!(state.settings.effective_shiftwidth(), 8);
+        assert_eq!(message(&after_keys("", ":set ts=x<CR>")), Some("Number required: tabstop=x"));
+        assert_eq!(message(&after_keys("", ":set nosuch<CR>")), Some("Unknown option: nosuch"));
+        assert_eq!(message(&after_keys("", ":set nots<CR>")), Some("Unknown option: nots"));
+        assert_eq!(message(&after_keys("", ":set ff=amiga<CR>")), Some("Invalid fileformat: amiga"));
+    }
+
+    #[test]
+    fn local_options_win_over_global_ones() {
+        let state = after_keys("", ":setlocal ts=4<CR>:set sw=2<CR>");
+        assert_eq!((state.settings.tabstop(), state.settings.effective_shiftwidth()), (4, 2));
+        assert!(state.settings.is_local("tabstop"));
+        // `:set` replaces the local value too, and global options can't have one
+        let state = after_keys("", ":setlocal ts=4<CR>:set ts=6<CR>");
+        assert_eq!(state.settings.tabstop(), 6);
+        assert!(!state.settings.is_local("tabstop"));
+        assert!(!after_keys("", ":setlocal nonumber<CR>").settings.is_local("number"));
+        assert!(!after_keys("", ":setlocal nonumber<CR>").settings.number());
+    }
+
+    #[test]
+    fn motions_in_an_empty_buffer() {
+        let motions = ["0", "^", "$", "gg", "G", "3G", "e", "ge", "w", "W", "B", "E", "(", ")", "{", "}",
+                       "H", "M", "L", "%", "50%", "|", "5|", "h", "l", "j", "k"];
+        for keys in motions.iter() {
+            assert_eq!(cursor_after("", keys), 0);
+        }
+    }
+}
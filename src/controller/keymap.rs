@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+
+use termion::event::Key;
+
+use controller::input::{Action, FnAlias};
+use controller::commands::{default_bindings, default_text_objects};
+use controller::keytrie::{KeyTrie, Lookup};
+use controller::operators::Verb;
+use data::editor_state::{Mode, TextObject};
+
+// What a key sequence does. Built-in bindings either run an Action straight
+// away, start a function which waits for an argument key, or start a Verb which
+// waits for the motion it acts over.
+#[derive(Clone, Debug)]
+pub enum Binding {
+    Operator(Action),
+    Function(FnAlias),
+    Verb(Verb),
+    TextObject(TextObject),
+    Edit(Verb, Action),  // A verb with its motion already given, as `x` is `dl`
+}
+
+// A user mapping from `:map` and friends: typing `lhs` behaves as if `rhs` had
+// been typed instead. With `noremap`, the keys of `rhs` aren't mapped again.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mapping {
+    pub rhs: Vec<Key>,
+    pub noremap: bool,
+}
+
+// The keys understood by each Mode. User mappings are kept apart from the
+// built-in bindings, so that `:unmap` brings back the original behaviour. Text
+// objects are only looked up after a verb, where they take the place of a motion.
+pub struct Keymap {
+    builtin: HashMap<Mode, KeyTrie<Binding>>,
+    user: HashMap<Mode, KeyTrie<Mapping>>,
+    text_objects: KeyTrie<TextObject>,
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        let mut builtin: HashMap<Mode, KeyTrie<Binding>> = HashMap::new();
+        for (mode, keys, binding) in default_bindings() {
+            builtin.entry(mode).or_insert_with(KeyTrie::new).insert(&keys, binding);
+        }
+        let mut text_objects = KeyTrie::new();
+        for (keys, object) in default_text_objects() {
+            text_objects.insert(&keys, object);
+        }
+        Keymap { builtin, user: HashMap::new(), text_objects }
+    }
+
+    pub fn builtin(&self, mode: Mode, keys: &[Key]) -> Lookup<Binding> {
+        self.builtin.get(&mode).map_or(Lookup::NoMatch, |bindings| bindings.lookup(keys))
+    }
+
+    pub fn text_object(&self, keys: &[Key]) -> Lookup<TextObject> {
+        self.text_objects.lookup(keys)
+    }
+
+    pub fn map(&mut self, mode: Mode, lhs: Vec<Key>, rhs: Vec<Key>, noremap: bool) {
+        self.user.entry(mode).or_insert_with(KeyTrie::new).insert(&lhs, Mapping { rhs, noremap });
+    }
+
+    // Returns false if there was no such mapping
+    pub fn unmap(&mut self, mode: Mode, lhs: &[Key]) -> bool {
+        self.user.get_mut(&mode).map_or(false, |mappings| mappings.remove(lhs).is_some())
+    }
+
+    pub fn mapping(&self, mode: Mode, keys: &[Key]) -> Lookup<Mapping> {
+        self.user.get(&mode).map_or(Lookup::NoMatch, |mappings| mappings.lookup(keys))
+    }
+
+    // The longest mapping at the start of `keys`, and how many keys it covers
+    pub fn longest_mapping(&self, mode: Mode, keys: &[Key]) -> Option<(usize, &Mapping)> {
+        self.user.get(&mode).and_then(|mappings| mappings.longest_match(keys))
+    }
+
+    // One line per mapping in the given modes whose lhs starts with `prefix`, in the
+    // style of Vim's `:map` listing.
+    pub fn describe_mappings(&self, modes: &[Mode], prefix: &[Key]) -> Vec<String> {
+        let mut lines = vec![];
+        for &mode in modes {
+            let mappings = match self.user.get(&mode) {
+                Some(mappings) => mappings,
+                None => continue,
+            };
+            let mut mode_lines: Vec<String> = mappings.entries().into_iter()
+                .filter(|&(ref lhs, _)| lhs.starts_with(prefix))
+                .map(|(lhs, mapping)| format!(
+                    "{}  {:<12} {}{}",
+                    mode_letter(mode),
+                    format_keys(&lhs),
+                    if mapping.noremap { "* " } else { "  " },
+                    format_keys(&mapping.rhs)
+                ))
+                .collect();
+            mode_lines.sort();
+            lines.extend(mode_lines);
+        }
+        lines
+    }
+}
+
+fn mode_letter(mode: Mode) -> char {
+    match mode {
+        Mode::Navigate => 'n',
+        Mode::Insert => 'i',
+        Mode::Command => 'c',
+        Mode::Visual | Mode::VisualLine | Mode::VisualBlock => 'v',
+    }
+}
+
+// Parses key notation like `<leader>w`, `<C-x>` or `:w<CR>`. `<leader>` is
+// replaced by the keys of `leader`. Anything in angle brackets which isn't a key
+// name is taken literally, as Vim does.
+pub fn parse_keys(notation: &str, leader: &str) -> Result<Vec<Key>, String> {
+    let chars: Vec<char> = notation.chars().collect();
+    let mut keys = vec![];
+    let mut index = 0;
+    while index < chars.len() {
+        if chars[index] == '<' {
+            if let Some(length) = chars[index..].iter().position(|&ch| ch == '>') {
+                let name: String = chars[index + 1..index + length].iter().collect();
+                if name.to_lowercase() == "leader" {
+                    keys.extend(parse_keys(leader, "")?);
+                    index += length + 1;
+                    continue;
+                }
+                if let Some(key) = named_key(&name) {
+                    keys.push(key);
+                    index += length + 1;
+                    continue;
+                }
+            }
+        }
+        keys.push(Key::Char(chars[index]));
+        index += 1;
+    }
+
+    if keys.is_empty() {
+        return Err(String::from("Empty key sequence"));
+    }
+    Ok(keys)
+}
+
+fn named_key(name: &str) -> Option<Key> {
+    let lower = name.to_lowercase();
+    let modified_char = |prefix: &str| {
+        if lower.starts_with(prefix) && name.chars().count() == prefix.len() + 1 {
+            name.chars().last()
+        } else {
+            None
+        }
+    };
+
+    if let Some(ch) = modified_char("c-") {
+        return Some(Key::Ctrl(ch.to_ascii_lowercase()));
+    }
+    if let Some(ch) = modified_char("a-").or_else(|| modified_char("m-")) {
+        return Some(Key::Alt(ch));
+    }
+    if lower.starts_with('f') && lower.len() > 1 {
+        if let Ok(number) = lower[1..].parse::<u8>() {
+            return if number >= 1 && number <= 12 { Some(Key::F(number)) } else { None };
+        }
+    }
+
+    let key = match lower.as_str() {
+        "esc" => Key::Esc,
+        "cr" | "enter" | "return" | "nl" => Key::Char('\n'),
+        "tab" => Key::Char('\t'),
+        "bs" | "backspace" => Key::Backspace,
+        "del" => Key::Delete,
+        "space" => Key::Char(' '),
+        "lt" => Key::Char('<'),
+        "bslash" => Key::Char('\\'),
+        "bar" => Key::Char('|'),
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        "insert" => Key::Insert,
+        _ => return None,
+    };
+    Some(key)
+}
+
+// The inverse of `parse_keys`, used when showing keys to the user.
+pub fn format_keys(keys: &[Key]) -> String {
+    keys.iter().map(|key| match *key {
+        Key::Char('\n') => String::from("<CR>"),
+        Key::Char('\t') => String::from("<Tab>"),
+        Key::Char(' ') => String::from("<Space>"),
+        Key::Char('<') => String::from("<lt>"),
+        Key::Char(ch) => ch.to_string(),
+        Key::Ctrl(ch) => format!("<C-{}>", ch),
+        Key::Alt(ch) => format!("<A-{}>", ch),
+        Key::F(number) => format!("<F{}>", number),
+        Key::Esc => String::from("<Esc>"),
+        Key::Backspace => String::from("<BS>"),
+        Key::Delete => String::from("<Del>"),
+        Key::Up => String::from("<Up>"),
+        Key::Down => String::from("<Down>"),
+        Key::Left => String::from("<Left>"),
+        Key::Right => String::from("<Right>"),
+        Key::Home => String::from("<Home>"),
+        Key::End => String::from("<End>"),
+        Key::PageUp => String::from("<PageUp>"),
+        Key::PageDown => String::from("<PageDown>"),
+        Key::Insert => String::from("<Insert>"),
+        _ => String::from("<?>"),
+    }).collect()
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+use termion::event::Key;
+
+// A trie of key sequences. Walking it with the keys typed so far tells us
+// whether they select a binding yet, or whether more keys are needed.
+pub struct KeyTrie<T> {
+    value: Option<T>,
+    children: HashMap<Key, KeyTrie<T>>,
+}
+
+pub enum Lookup<'a, T: 'a> {
+    NoMatch,
+    Prefix,  // The keys are the start of one or more sequences, but not one themselves
+    Ambiguous(&'a T),  // The keys are a sequence, and also the start of longer ones
+    Match(&'a T),
+}
+
+impl<T> KeyTrie<T> {
+    pub fn new() -> Self {
+        KeyTrie { value: None, children: HashMap::new() }
+    }
+
+    pub fn insert(&mut self, keys: &[Key], value: T) -> Option<T> {
+        match keys.split_first() {
+            None => self.value.replace(value),
+            Some((first, rest)) => self.children.entry(*first)
+                                               .or_insert_with(KeyTrie::new)
+                                               .insert(rest, value),
+        }
+    }
+
+    // Removes the value for exactly these keys, pruning branches left empty.
+    pub fn remove(&mut self, keys: &[Key]) -> Option<T> {
+        match keys.split_first() {
+            None => self.value.take(),
+            Some((first, rest)) => {
+                let (removed, now_empty) = match self.children.get_mut(first) {
+                    Some(child) => {
+                        let removed = child.remove(rest);
+                        (removed, child.value.is_none() && child.children.is_empty())
+                    },
+                    None => (None, false),
+                };
+                if now_empty {
+                    self.children.remove(first);
+                }
+                removed
+            }
+        }
+    }
+
+    fn node(&self, keys: &[Key]) -> Option<&KeyTrie<T>> {
+        match keys.split_first() {
+            None => Some(self),
+            Some((first, rest)) => self.children.get(first).and_then(|child| child.node(rest)),
+        }
+    }
+
+    pub fn lookup(&self, keys: &[Key]) -> Lookup<T> {
+        match self.node(keys) {
+            None => Lookup::NoMatch,
+            Some(node) => match (node.value.as_ref(), node.children.is_empty()) {
+                (Some(value), true) => Lookup::Match(value),
+                (Some(value), false) => Lookup::Ambiguous(value),
+                (None, false) => Lookup::Prefix,
+                (None, true) => Lookup::NoMatch,
+            },
+        }
+    }
+
+    // The longest sequence at the start of `keys` which has a value, along with
+    // its length.
+    pub fn longest_match(&self, keys: &[Key]) -> Option<(usize, &T)> {
+        let mut node = self;
+        let mut longest = None;
+        for (index, key) in keys.iter().enumerate() {
+            node = match node.children.get(key) {
+                Some(child) => child,
+                None => break,
+            };
+            if let Some(ref value) = node.value {
+                longest = Some((index + 1, value));
+            }
+        }
+        longest
+    }
+
+    // Every sequence in the trie along with its value, in no particular order.
+    pub fn entries(&self) -> Vec<(Vec<Key>, &T)> {
+        let mut entries = vec![];
+        self.collect_entries(&mut vec![], &mut entries);
+        entries
+    }
+
+    fn collect_entries<'a>(&'a self, prefix: &mut Vec<Key>, entries: &mut Vec<(Vec<Key>, &'a T)>) {
+        if let Some(ref value) = self.value {
+            entries.push((prefix.clone(), value));
+        }
+        for (key, child) in self.children.iter() {
+            prefix.push(*key);
+            child.collect_entries(prefix, entries);
+            prefix.pop();
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::mem;
+use std::rc::Rc;
+
+use termion::event::Key;
+
+use controller::events::InputModeMultiplexer;
+use controller::ex_commands::run_command;
+use controller::keymap::{parse_keys, format_keys};
+use data::editor_state::{StateApi, EditorState, Mode};
+use data::registers::{Register, RegisterKind};
+
+// Macros played inside macros, like one which plays itself, are given up on once
+// this many are still being typed.
+const MAX_MACRO_DEPTH: usize = 1000;
+
+// What a macro still has to type. Each copy of its keys is followed by a count of
+// the copies left to type after it, so a large count isn't typed out up front.
+pub enum Typeahead {
+    Key(Key, bool),  // A key, and whether it may be remapped
+    Copies(Rc<Vec<Key>>, usize),  // The end of a copy of the keys, and how many more copies to type
+}
+
+// `q{reg}` records every key typed from then on into the register, until `q` is
+// typed again in Navigate mode. Uppercase names add to the end of the register.
+pub fn start_recording(name: char, state: &mut EditorState) {
+    match name {
+        'a'...'z' | 'A'...'Z' | '0'...'9' | '"' => state.recording = Some((name, vec![])),
+        _ => {
+            state.set_status_message(format!("Invalid register name: \"{}", name));
+        }
+    }
+}
+
+// Keeps the keys recorded as the register's text, written in key notation, less
+// the `q` which stopped the recording.
+pub fn stop_recording(state: &mut EditorState) {
+    let (name, mut keys) = match state.recording.take() {
+        Some(recording) => recording,
+        None => return,
+    };
+    keys.pop();
+    state.registers.selected = Some(name);
+    if let Err(message) = state.registers.store(Register::new(format_keys(&keys), RegisterKind::Charwise), false) {
+        state.set_status_message(message);
+    }
+}
+
+// `@{reg}` types the keys in the register, `count` times over. `@@` plays the last
+// macro played again, and `@:` runs the last command line again.
+pub fn play_macro(name: char, count: usize, state: &mut EditorState) {
+    let name = match name {
+        '@' => match state.last_macro {
+            Some(name) => name,
+            None => {
+                state.set_status_message(String::from("No previously used register"));
+                return;
+            }
+        },
+        name => name,
+    };
+    state.last_macro = Some(name);
+
+    if name == ':' {
+        if let Some(command_line) = state.registers.last_command.clone() {
+            for _ in 0..count {
+                run_command(&command_line, state);
+            }
+        }
+        return;
+    }
+
+    let leader = state.settings.mapleader().to_string();
+    let keys = match state.read_register(name).and_then(|register| parse_keys(&register.text, &leader)) {
+        Ok(keys) => keys,
+        Err(message) => {
+            state.set_status_message(message);
+            return;
+        }
+    };
+    type_copies(Rc::new(keys), count, state);
+}
+
+// Queues the first of `count` copies of a macro's keys, ahead of anything else still
+// to be played, like the rest of the macro which played this one.
+pub fn type_copies(keys: Rc<Vec<Key>>, count: usize, state: &mut EditorState) {
+    if count == 0 {
+        return;
+    }
+    let depth = state.typeahead.iter().filter(|entry| match **entry {
+        Typeahead::Copies(..) => true,
+        Typeahead::Key(..) => false,
+    }).count();
+    if depth >= MAX_MACRO_DEPTH {
+        state.set_status_message(String::from("Recursive macro"));
+        abort_macro(state);
+        return;
+    }
+    state.typeahead.push_front(Typeahead::Copies(keys.clone(), count - 1));
+    for &key in keys.iter().rev() {
+        state.typeahead.push_front(Typeahead::Key(key, true));
+    }
+}
+
+// A motion which can't go anywhere stops the macro being played, along with any
+// others waiting on it.
+pub fn abort_macro(state: &mut EditorState) {
+    state.typeahead.clear();
+}
+
+// `:normal` types the keys in Navigate mode, on each line of the range in turn, or
+// once where the cursor is without one. With `remap` false, as for `:normal!`, the
+// user's key mappings are ignored.
+pub fn run_normal(keys: &[Key], lines: Option<(usize, usize)>, remap: bool, state: &mut EditorState) {
+    // Anything still to be played waits until these keys have been typed
+    let waiting = mem::replace(&mut state.typeahead, Default::default());
+
+    match lines {
+        Some((first, last)) => for y in first..last + 1 {
+            let lines = state.get_editor_lines();
+            if y >= lines.len() {
+                break;
+            }
+            state.cursor_index = EditorState::line_start_index(&lines, y);
+            type_keys(keys, remap, state);
+        },
+        None => type_keys(keys, remap, state),
+    }
+
+    state.typeahead = waiting;
+}
+
+// An unfinished command is given up on and Insert mode is left, as if Esc had been
+// typed after the keys.
+fn type_keys(keys: &[Key], remap: bool, state: &mut EditorState) {
+    state.set_mode(Mode::Navigate);
+    let mut input = InputModeMultiplexer::new();
+    state.typeahead.extend(keys.iter().map(|&key| Typeahead::Key(key, remap)));
+    input.play_typeahead(state);
+    input.timeout(state);
+    if *state.get_mode() != Mode::Navigate {
+        state.typeahead.push_back(Typeahead::Key(Key::Esc, false));
+        input.play_typeahead(state);
+    }
+    state.typeahead.clear();
+    state.set_mode(Mode::Navigate);
+}
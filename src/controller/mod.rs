@@ -1,4 +1,11 @@
 pub mod events;
 pub mod input;
 pub mod util;
-pub mod commands;
\ No newline at end of file
+pub mod commands;
+pub mod operators;
+pub mod visual;
+pub mod macros;
+pub mod keymap;
+pub mod keytrie;
+pub mod ex_commands;
+pub mod sources;
\ No newline at end of file
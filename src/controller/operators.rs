@@ -0,0 +1,334 @@
+use std::cmp;
+
+use data::editor_state::{StateApi, EditorState, Mode, TextRange, char_class};
+use data::registers::{Register, RegisterKind};
+use data::wrap::whitespace_between;
+use controller::visual::put_block;
+
+// Commands which act on the text a motion moves over, like the `d` of `dw`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verb {
+    Delete,
+    Change,
+    Yank,
+    ShiftRight,
+    ShiftLeft,
+    Lowercase,
+    Uppercase,
+    ToggleCase,
+    Reindent,
+}
+
+impl Verb {
+    // The key which, typed straight after the verb, makes it act on whole lines:
+    // the second `d` of `dd`, or the last `u` of `guu`.
+    pub fn doubled_key(&self) -> char {
+        match *self {
+            Verb::Delete => 'd',
+            Verb::Change => 'c',
+            Verb::Yank => 'y',
+            Verb::ShiftRight => '>',
+            Verb::ShiftLeft => '<',
+            Verb::Lowercase => 'u',
+            Verb::Uppercase => 'U',
+            Verb::ToggleCase => '~',
+            Verb::Reindent => '=',
+        }
+    }
+}
+
+// How the text between the cursor and where a motion leaves it is taken.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MotionKind {
+    Exclusive,  // Up to but not including the char the motion ends on, as with `w`
+    Inclusive,  // Including the char the motion ends on, as with `$`
+    Linewise,  // Every line the motion touches, as with `j`
+}
+
+// Applies the verb to the text between the `start` and `end` indices of a
+// motion, which may be either way around.
+pub fn apply_verb(verb: Verb, state: &mut EditorState, start: usize, end: usize, kind: MotionKind) {
+    let text: Vec<char> = state.get_text().chars().collect();
+    if text.is_empty() {
+        return;
+    }
+    let (from, to) = motion_range(&text, start, end, kind);
+    apply_verb_to_range(verb, state, TextRange { start: from, end: to, linewise: kind == MotionKind::Linewise });
+}
+
+// Applies the verb to exactly the text in the range, e.g. that of a text object.
+pub fn apply_verb_to_range(verb: Verb, state: &mut EditorState, range: TextRange) {
+    let text: Vec<char> = state.get_text().chars().collect();
+    if text.is_empty() {
+        return;
+    }
+    let to = cmp::min(range.end, text.len());
+    let (from, linewise) = (cmp::min(range.start, to), range.linewise);
+    // Only `c` has anything to do with no text, as it still starts Insert mode
+    if from == to && verb != Verb::Change {
+        return;
+    }
+    let selected: String = text[from..to].iter().collect();
+    let kind = if linewise { RegisterKind::Linewise } else { RegisterKind::Charwise };
+
+    match verb {
+        Verb::Delete => {
+            store_register(state, Register::new(selected, kind), true);
+            delete_range(state, &text, from, to, linewise);
+        },
+        Verb::Change => {
+            store_register(state, Register::new(selected, kind), true);
+            if linewise {
+                // The lines are replaced by a single empty one, keeping the indent
+                let indent: String = text[from..to].iter().take_while(|&&ch| ch == ' ' || ch == '\t').collect();
+                let ending = if to > from && text[to - 1] == '\n' { "\n" } else { "" };
+                state.replace_text(from, to - from, &format!("{}{}", indent, ending));
+                state.cursor_index = from + indent.chars().count();
+            } else {
+                state.replace_text(from, to - from, "");
+                state.cursor_index = from;
+            }
+            state.set_mode(Mode::Insert);
+        },
+        Verb::Yank => {
+            store_register(state, Register::new(selected, kind), false);
+            if !linewise || from < line_start(&text, state.cursor_index) {
+                state.cursor_index = from;
+            }
+        },
+        Verb::ShiftRight | Verb::ShiftLeft => {
+            state.cursor_index = from;
+            state.shift_lines(line_count(&text, from, to), verb == Verb::ShiftRight);
+        },
+        Verb::Lowercase | Verb::Uppercase | Verb::ToggleCase => {
+            let converted: String = text[from..to].iter().map(|&ch| convert_case(verb, ch)).collect();
+            state.replace_text(from, to - from, &converted);
+            state.cursor_index = from;
+        },
+        Verb::Reindent => {
+            let first_line = text[..from].iter().filter(|&&ch| ch == '\n').count();
+            reindent(state, first_line, line_count(&text, from, to));
+        },
+    }
+}
+
+// Keeps the text a verb took, in the register named with `"x` if there was one.
+pub fn store_register(state: &mut EditorState, register: Register, deleted: bool) {
+    let result = match state.registers.selected {
+        Some(name) if name == '+' || name == '*' => {
+            state.registers.selected = None;
+            state.write_clipboard(register, name == '*')
+        },
+        _ => state.registers.store(register, deleted),
+    };
+    if let Err(message) = result {
+        state.set_status_message(message);
+    }
+}
+
+// `p` and `P`, which put the text of the register named with `"x`, or of `""`,
+// after or before the cursor, `count` times over. With `move_past`, as for `gp`
+// and `gP`, the cursor is left just after the new text.
+pub fn put_register(state: &mut EditorState, count: usize, before: bool, move_past: bool) {
+    let name = state.registers.selected.take().unwrap_or('"');
+    let register = match state.read_register(name) {
+        Ok(register) => register,
+        Err(message) => {
+            state.set_status_message(message);
+            return;
+        }
+    };
+    match register.kind {
+        RegisterKind::Charwise => put_chars(state, &register.text.repeat(count), before, move_past),
+        RegisterKind::Linewise => put_lines(state, &register.text, count, before, move_past),
+        RegisterKind::Blockwise => put_block(state, &register.text, count, before, move_past),
+    }
+}
+
+// Charwise text goes within the cursor line. The cursor is left on its last char,
+// or at its start if it spans lines.
+fn put_chars(state: &mut EditorState, text: &str, before: bool, move_past: bool) {
+    let buffer: Vec<char> = state.get_text().chars().collect();
+    let cursor = cmp::min(state.cursor_index, buffer.len());
+    // Nothing is put after a line break, which is all an empty line has
+    let index = if before || cursor == buffer.len() || buffer[cursor] == '\n' { cursor } else { cursor + 1 };
+    let length = text.chars().count();
+    state.replace_text(index, 0, text);
+    state.cursor_index = if move_past {
+        index + length
+    } else if text.contains('\n') {
+        index
+    } else {
+        index + length.saturating_sub(1)
+    };
+}
+
+// Linewise text goes on lines of its own, below or above the cursor line. The
+// cursor is left at the first non-blank of the first of them.
+fn put_lines(state: &mut EditorState, text: &str, count: usize, before: bool, move_past: bool) {
+    let buffer: Vec<char> = state.get_text().chars().collect();
+    let mut lines = text.to_string();
+    if !lines.ends_with('\n') {
+        lines.push('\n');
+    }
+    let mut lines = lines.repeat(count);
+    let length = lines.chars().count();
+
+    let cursor = cmp::min(state.cursor_index, buffer.len().saturating_sub(1));
+    let index = match (buffer.is_empty(), before) {
+        (true, _) => 0,
+        (false, true) => line_start(&buffer, cursor),
+        (false, false) => line_end(&buffer, cursor),
+    };
+    // After a last line without a line break, the new lines need one before them instead
+    let start = if index == buffer.len() && index > 0 && buffer[index - 1] != '\n' {
+        lines.pop();
+        lines.insert(0, '\n');
+        index + 1
+    } else {
+        index
+    };
+    state.replace_text(index, 0, &lines);
+
+    state.cursor_index = if move_past {
+        cmp::min(start + length, state.get_file_length_in_chars())
+    } else {
+        start + lines.chars().skip(start - index).take_while(|&ch| ch == ' ' || ch == '\t').count()
+    };
+}
+
+// The chars a motion covers, as a half-open range.
+fn motion_range(text: &[char], start: usize, end: usize, kind: MotionKind) -> (usize, usize) {
+    let from = cmp::min(cmp::min(start, end), text.len() - 1);
+    let to = cmp::max(start, end);
+    if kind == MotionKind::Exclusive {
+        return exclusive_range(text, from, cmp::min(to, text.len()));
+    }
+    let to = cmp::min(to, text.len() - 1);
+    match kind {
+        MotionKind::Linewise => (line_start(text, from), line_end(text, to)),
+        _ => (from, to + 1),
+    }
+}
+
+// An exclusive motion ending at the start of a later line stops at the end of the
+// line before instead, so `dw` on the last word of a line leaves the line break
+// alone. It can end just past the last char, as `dl` on it does.
+fn exclusive_range(text: &[char], from: usize, to: usize) -> (usize, usize) {
+    if to > from && line_start(text, to) == to {
+        (from, to - 1)
+    } else {
+        (from, to)
+    }
+}
+
+fn delete_range(state: &mut EditorState, text: &[char], from: usize, to: usize, linewise: bool) {
+    // Deleting the last lines of a file without a final line break takes the line
+    // break before them instead.
+    let from = if linewise && to == text.len() && to > from && text[to - 1] != '\n' && from > 0 { from - 1 } else { from };
+    state.replace_text(from, to - from, "");
+
+    let remaining: Vec<char> = state.get_text().chars().collect();
+    if remaining.is_empty() {
+        state.cursor_index = 0;
+        return;
+    }
+    let cursor = cmp::min(from, remaining.len() - 1);
+    state.cursor_index = if linewise {
+        let start = line_start(&remaining, cursor);
+        start + remaining[start..].iter().take_while(|&&ch| ch == ' ' || ch == '\t').count()
+    } else {
+        cursor
+    };
+}
+
+fn line_start(text: &[char], index: usize) -> usize {
+    text[..index].iter().rposition(|&ch| ch == '\n').map_or(0, |newline| newline + 1)
+}
+
+// The index just past the line break of the line containing `index`
+fn line_end(text: &[char], index: usize) -> usize {
+    text[index..].iter().position(|&ch| ch == '\n').map_or(text.len(), |newline| index + newline + 1)
+}
+
+// The number of lines touched by the chars in the range
+fn line_count(text: &[char], from: usize, to: usize) -> usize {
+    let last = cmp::max(from, to.saturating_sub(1));
+    text[from..last].iter().filter(|&&ch| ch == '\n').count() + 1
+}
+
+pub fn convert_case(verb: Verb, ch: char) -> String {
+    let to_upper = match verb {
+        Verb::Uppercase => true,
+        Verb::ToggleCase => ch.is_lowercase(),
+        _ => false,
+    };
+    if to_upper { ch.to_uppercase().collect() } else { ch.to_lowercase().collect() }
+}
+
+// The end of the `count`th word from `index`, for `cw` and `cW`, which unlike `dw`
+// leave the whitespace after the word alone.
+pub fn end_of_words(text: &[char], index: usize, count: usize, big: bool) -> usize {
+    let mut end = index;
+    for word in 0..count {
+        if word > 0 {
+            end += text[end..].iter().take_while(|ch| ch.is_whitespace()).count();
+        }
+        if end < text.len() {
+            let class = char_class(text[end], big);
+            end += text[end..].iter().take_while(|&&ch| ch != '\n' && char_class(ch, big) == class).count();
+        }
+    }
+    end
+}
+
+// Re-indents lines to steps of `shiftwidth`, keeping their structure. The first
+// line keeps its indent, a line indented deeper than the one before it goes one
+// step further in, and one indented less goes back out to the enclosing line it
+// lines up with. Blank lines lose any whitespace.
+fn reindent(state: &mut EditorState, first_line: usize, count: usize) {
+    let lines = state.get_editor_lines();
+    let last_line = cmp::min(first_line + count, lines.len());
+    let (tabstop, shiftwidth, expandtab) = (state.settings.tabstop(), state.settings.effective_shiftwidth(), state.settings.expandtab());
+
+    let mut levels: Vec<(usize, usize)> = vec![];  // The old and new indent of each enclosing line
+    let mut reindented = String::new();
+    let mut length = 0;
+    for line in lines[first_line..last_line].iter() {
+        length += line.chars().count();
+        let content = line.trim_right_matches('\n');
+        let indent_length = content.chars().take_while(|&ch| ch == ' ' || ch == '\t').count();
+        if indent_length == content.chars().count() {
+            reindented.push('\n');
+            continue;
+        }
+
+        let width = state.line_columns(line)[indent_length];
+        while levels.last().map_or(false, |&(old, _)| old > width) {
+            levels.pop();
+        }
+        let new_width = match levels.last() {
+            None => width,
+            Some(&(old, new)) if old == width => new,
+            Some(&(_, new)) => new + shiftwidth,
+        };
+        if levels.last().map_or(true, |&(old, _)| old != width) {
+            levels.push((width, new_width));
+        }
+
+        reindented.push_str(&whitespace_between(0, new_width, tabstop, expandtab));
+        reindented.extend(content.chars().skip(indent_length));
+        reindented.push('\n');
+    }
+
+    // `get_editor_lines` gives every line a line break, even when the file doesn't end with one
+    let start = EditorState::line_start_index(&lines, first_line);
+    let file_length = state.get_file_length_in_chars();
+    if start + length > file_length {
+        reindented.pop();
+    }
+    let length = cmp::min(length, file_length - start);
+    state.replace_text(start, length, &reindented);
+    let first_non_blank = reindented.chars().take_while(|&ch| ch == ' ' || ch == '\t').count();
+    state.cursor_index = start + first_non_blank;
+}
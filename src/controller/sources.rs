@@ -0,0 +1,205 @@
+use std::fs;
+use std::io::stdin;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use termion::input::TermRead;
+
+use signal_hook;
+use signal_hook::iterator::Signals;
+
+use controller::events::EditorEvent;
+
+// Each source of events runs on its own thread and posts into the event loop's
+// channel, so nothing but the loop itself ever blocks the handling of keystrokes.
+
+pub fn spawn_input_reader(sender: Sender<EditorEvent>) {
+    thread::spawn(move || {
+        for event in stdin().events() {
+            match event {
+                Ok(event) => if sender.send(EditorEvent::Input(event)).is_err() { break },
+                Err(_) => break,
+            }
+        }
+    });
+}
+
+pub fn spawn_resize_listener(sender: Sender<EditorEvent>) {
+    let signals = Signals::new(&[signal_hook::SIGWINCH])
+                          .expect("Unable to listen for terminal resizes");
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            if sender.send(EditorEvent::Resize).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+// Polls the modification time of a file, posting an event with the new time
+// whenever it changes.
+pub fn spawn_file_watcher(sender: Sender<EditorEvent>, path: PathBuf, poll_interval: Duration) {
+    thread::spawn(move || {
+        let modified_time = |path: &PathBuf| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+        let mut last_modified = modified_time(&path);
+        loop {
+            thread::sleep(poll_interval);
+            let modified = modified_time(&path);
+            if modified != last_modified {
+                last_modified = modified;
+                if sender.send(EditorEvent::FileChanged(path.clone(), modified)).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+}
+
+// Runs some work in the background, posting its result back to the event loop
+// once it's done. Should the loop have gone by then, the result is of no use.
+pub fn spawn_job<F>(sender: Sender<EditorEvent>, name: String, job: F)
+    where F: FnOnce() -> Result<String, String> + Send + 'static
+{
+    thread::spawn(move || {
+        let result = job();
+        let _ = sender.send(EditorEvent::JobFinished { name, result });
+    });
+}
+
+// Runs a command through the shell, as `:!` does, giving what it printed. A
+// command which fails gives what it printed to stderr instead.
+pub fn run_shell_command(command: &str) -> Result<String, String> {
+    let output = Command::new("sh").arg("-c").arg(command).output().map_err(|error| error.to_string())?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim_right().to_string())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim_right().to_string())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TimerKind {
+    KeySequenceTimeout,
+    Autosave,
+    MessageExpiry(usize),  // Carries the id of the status message to clear
+}
+
+pub type TimerId = usize;
+
+enum TimerRequest {
+    Schedule { id: TimerId, delay: Duration, repeat: bool, kind: TimerKind },
+    Cancel(TimerId),
+}
+
+struct PendingTimer {
+    id: TimerId,
+    deadline: Instant,
+    interval: Option<Duration>,
+    kind: TimerKind,
+}
+
+// A handle to the timer thread. Cloning the handle is cheap, so it can be given
+// to whichever subsystem needs to schedule things.
+#[derive(Clone)]
+pub struct Timers {
+    requests: Sender<TimerRequest>,
+    next_id: Arc<AtomicUsize>,
+}
+
+impl Timers {
+
+    pub fn start(sender: Sender<EditorEvent>) -> Timers {
+        let (requests, incoming) = channel();
+        thread::spawn(move || {
+            let mut pending: Vec<PendingTimer> = vec![];
+            loop {
+                let next_deadline = pending.iter().map(|timer| timer.deadline).min();
+                let request = match next_deadline {
+                    Some(deadline) => {
+                        let now = Instant::now();
+                        let timeout = if deadline > now { deadline - now } else { Duration::from_millis(0) };
+                        incoming.recv_timeout(timeout)
+                    },
+                    None => incoming.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+
+                match request {
+                    Ok(TimerRequest::Schedule { id, delay, repeat, kind }) => pending.push(PendingTimer {
+                        id,
+                        deadline: Instant::now() + delay,
+                        interval: if repeat { Some(delay) } else { None },
+                        kind
+                    }),
+                    Ok(TimerRequest::Cancel(id)) => pending.retain(|timer| timer.id != id),
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+
+                let now = Instant::now();
+                for timer in pending.iter_mut().filter(|timer| timer.deadline <= now) {
+                    if sender.send(EditorEvent::Timer(timer.id, timer.kind.clone())).is_err() {
+                        return;
+                    }
+                    if let Some(interval) = timer.interval {
+                        timer.deadline = now + interval;
+                    }
+                }
+                pending.retain(|timer| timer.deadline > now);
+            }
+        });
+
+        Timers {
+            requests,
+            next_id: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn after(&self, delay: Duration, kind: TimerKind) -> TimerId {
+        self.schedule(delay, false, kind)
+    }
+
+    pub fn every(&self, interval: Duration, kind: TimerKind) -> TimerId {
+        self.schedule(interval, true, kind)
+    }
+
+    // The timer thread only stops once the event loop has gone, after which there's
+    // nothing left to time, so requests it doesn't get are of no consequence.
+    pub fn cancel(&self, id: TimerId) {
+        let _ = self.requests.send(TimerRequest::Cancel(id));
+    }
+
+    fn schedule(&self, delay: Duration, repeat: bool, kind: TimerKind) -> TimerId {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let _ = self.requests.send(TimerRequest::Schedule { id, delay, repeat, kind });
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jobs_post_their_results_to_the_event_loop() {
+        let (sender, receiver) = channel();
+        spawn_job(sender.clone(), String::from("echo"), || run_shell_command("echo one; echo two"));
+        spawn_job(sender, String::from("false"), || run_shell_command("echo oops >&2; false"));
+        let mut results = vec![];
+        for _ in 0..2 {
+            match receiver.recv().unwrap() {
+                EditorEvent::JobFinished { name, result } => results.push((name, result)),
+                _ => panic!("Expected a finished job"),
+            }
+        }
+        results.sort();
+        assert_eq!(results, vec![
+            (String::from("echo"), Ok(String::from("one\ntwo"))),
+            (String::from("false"), Err(String::from("oops"))),
+        ]);
+    }
+}
@@ -0,0 +1,274 @@
+use std::cmp;
+use std::mem;
+
+use controller::operators::{Verb, MotionKind, apply_verb, convert_case, store_register};
+use data::editor_state::{StateApi, EditorState, Mode, TextObject, SelectedSpan, BlockInsert, line_and_offset};
+use data::registers::{Register, RegisterKind};
+
+// `v`, `V` and Ctrl-v start selecting in their Visual mode, switch to it from
+// another Visual mode, or stop selecting when already in it.
+pub fn switch_visual_mode(mode: Mode, state: &mut EditorState) {
+    let current = *state.get_mode();
+    if current == mode {
+        state.set_mode(Mode::Navigate);
+        return;
+    }
+    if !current.is_visual() {
+        state.selection_anchor = state.cursor_index;
+    }
+    state.set_mode(mode);
+}
+
+// `o` moves the cursor to the other end of the selection
+pub fn swap_selection_ends(state: &mut EditorState) {
+    mem::swap(&mut state.selection_anchor, &mut state.cursor_index);
+}
+
+// `gv` selects whatever was last selected, in the same Visual mode
+pub fn reselect(state: &mut EditorState) {
+    let selection = match state.last_selection {
+        Some(selection) => selection,
+        None => return,
+    };
+    let last_index = state.get_file_length_in_chars().saturating_sub(1);
+    state.selection_anchor = cmp::min(selection.anchor, last_index);
+    state.cursor_index = cmp::min(selection.cursor, last_index);
+    state.set_mode(selection.mode);
+}
+
+// A text object typed in a Visual mode becomes the selection. Those made of whole
+// lines, like `ip`, select linewise.
+pub fn select_text_object(object: TextObject, count: usize, state: &mut EditorState) {
+    let range = match state.text_object_range(object, count) {
+        Some(range) if range.end > range.start => range,
+        _ => return,
+    };
+    state.selection_anchor = range.start;
+    state.cursor_index = range.end - 1;
+    if range.linewise && *state.get_mode() == Mode::Visual {
+        state.set_mode(Mode::VisualLine);
+    }
+}
+
+// How much a Visual mode command acted on, so that `.` can act on as much again
+// from the cursor: a number of lines, and of chars except when linewise. The
+// chars are those of the selection within a line, or up to its end on the last of
+// several lines.
+#[derive(Clone, Copy, Debug)]
+pub struct SelectionSize {
+    mode: Mode,
+    lines: usize,
+    chars: usize,
+}
+
+pub fn measure_selection(state: &EditorState) -> Option<SelectionSize> {
+    let spans = state.selected_spans();
+    let (first, last) = match (spans.first(), spans.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return None,
+    };
+    let mode = *state.get_mode();
+    let chars = match mode {
+        Mode::Visual if spans.len() > 1 => last.end,
+        _ => first.end - first.start,
+    };
+    Some(SelectionSize { mode, lines: spans.len(), chars })
+}
+
+// Selects as much as the size given, starting from the cursor
+pub fn select_size(size: SelectionSize, state: &mut EditorState) {
+    let lines = state.get_editor_lines();
+    if lines.is_empty() {
+        return;
+    }
+    let (first_line, x) = line_and_offset(&lines, state.cursor_index);
+    let last_line = cmp::min(first_line + size.lines - 1, lines.len() - 1);
+    let offset = match size.mode {
+        Mode::VisualLine => 0,
+        Mode::Visual if size.lines > 1 => size.chars.saturating_sub(1),
+        _ => x + size.chars.saturating_sub(1),
+    };
+    let length = lines[last_line].chars().count();
+    state.selection_anchor = state.cursor_index;
+    state.cursor_index = EditorState::line_start_index(&lines, last_line) + cmp::min(offset, length - 1);
+    state.set_mode(size.mode);
+}
+
+// Applies the verb to the selection, leaving Visual mode. A count shifts the
+// lines that many times over.
+pub fn apply_verb_to_selection(verb: Verb, count: usize, state: &mut EditorState) {
+    let mode = *state.get_mode();
+    let spans = state.selected_spans();
+    let (first_line, last_line) = match (spans.first(), spans.last()) {
+        (Some(first), Some(last)) => (first.line, last.line),
+        _ => return,
+    };
+    let (anchor, cursor) = (state.selection_anchor, state.cursor_index);
+    let (left, _) = state.block_columns();
+    state.set_mode(Mode::Navigate);
+
+    match verb {
+        Verb::ShiftRight | Verb::ShiftLeft => {
+            let lines = state.get_editor_lines();
+            for _ in 0..count {
+                state.cursor_index = EditorState::line_start_index(&lines, first_line);
+                state.shift_lines(last_line - first_line + 1, verb == Verb::ShiftRight);
+            }
+        },
+        Verb::Reindent => apply_verb(verb, state, anchor, cursor, MotionKind::Linewise),
+        _ if mode == Mode::VisualBlock => {
+            apply_verb_to_block(verb, &spans, state);
+            if verb == Verb::Change {
+                begin_block_insert(state.cursor_index, first_line, last_line, left, false, state);
+            }
+        },
+        _ if mode == Mode::VisualLine => apply_verb(verb, state, anchor, cursor, MotionKind::Linewise),
+        _ => apply_verb(verb, state, anchor, cursor, MotionKind::Inclusive),
+    }
+}
+
+// Applies the verb to the part of each line inside a Visual-block selection,
+// leaving the cursor at its top left.
+fn apply_verb_to_block(verb: Verb, spans: &[SelectedSpan], state: &mut EditorState) {
+    let lines = state.get_editor_lines();
+    let selected: Vec<String> = spans.iter()
+        .map(|span| lines[span.line].chars().skip(span.start).take(span.end - span.start).collect())
+        .collect();
+    if verb == Verb::Yank || verb == Verb::Delete || verb == Verb::Change {
+        let deleted = verb != Verb::Yank;
+        store_register(state, Register::new(selected.join("\n"), RegisterKind::Blockwise), deleted);
+    }
+
+    // The lines are changed from the bottom up, so the indices of those above still hold
+    for (span, text) in spans.iter().zip(selected.iter()).rev() {
+        let replacement: String = match verb {
+            Verb::Delete | Verb::Change => String::new(),
+            Verb::Lowercase | Verb::Uppercase | Verb::ToggleCase => text.chars().map(|ch| convert_case(verb, ch)).collect(),
+            _ => continue,
+        };
+        let start = EditorState::line_start_index(&lines, span.line) + span.start;
+        state.replace_text(start, span.end - span.start, &replacement);
+    }
+    state.cursor_index = EditorState::line_start_index(&lines, spans[0].line) + spans[0].start;
+}
+
+// `I` and `A` in Visual-block mode, which type the same text into every line of
+// the block, before or after it. After `$`, `A` appends to the end of each line.
+pub fn start_block_insert(append: bool, state: &mut EditorState) {
+    let spans = state.selected_spans();
+    let (first_line, last_line) = match (spans.first(), spans.last()) {
+        (Some(first), Some(last)) => (first.line, last.line),
+        _ => return,
+    };
+    let (left, right) = state.block_columns();
+    state.set_mode(Mode::Navigate);
+
+    let column = match (append, right) {
+        (false, _) => left,
+        (true, usize::MAX) => usize::MAX,
+        (true, right) => right + 1,
+    };
+    // The first line is typed on even when it's too short to reach the block
+    let start = insert_index(first_line, column, true, state).unwrap_or(0);
+    begin_block_insert(start, first_line, last_line, column, append, state);
+}
+
+fn begin_block_insert(start: usize, first_line: usize, last_line: usize, column: usize, pad: bool, state: &mut EditorState) {
+    state.cursor_index = start;
+    state.block_insert = Some(BlockInsert { start, first_line, last_line, column, pad });
+    state.set_mode(Mode::Insert);
+}
+
+// Copies the text typed on the first line of the block to the others, once Insert
+// mode is left after a Visual-block `I`, `A` or `c`. Nothing is copied if the
+// typing went beyond the first line.
+pub fn finish_block_insert(state: &mut EditorState) {
+    let insert = match state.block_insert.take() {
+        Some(insert) => insert,
+        None => return,
+    };
+    let text: Vec<char> = state.get_text().chars().collect();
+    if state.cursor_index <= insert.start || state.cursor_index > text.len() {
+        return;
+    }
+    let typed: String = text[insert.start..state.cursor_index].iter().collect();
+    if typed.contains('\n') {
+        return;
+    }
+
+    for line in (insert.first_line + 1..insert.last_line + 1).rev() {
+        if let Some(index) = insert_index(line, insert.column, insert.pad, state) {
+            state.replace_text(index, 0, &typed);
+        }
+    }
+    state.cursor_index = insert.start;
+}
+
+// Puts the lines of a blockwise register as a block, each on its own line from the
+// cursor line down, after or before the cursor column. Lines are added to the end
+// of the file when there aren't enough.
+pub fn put_block(state: &mut EditorState, text: &str, count: usize, before: bool, move_past: bool) {
+    if state.get_editor_lines().is_empty() {
+        state.replace_text(0, 0, "\n");
+    }
+    let lines = state.get_editor_lines();
+    let position = state.get_cursor_position();
+    let first_line = cmp::min(position.y, lines.len() - 1);
+    let columns = state.line_columns(&lines[first_line]);
+    let x = cmp::min(position.x, columns.len() - 1);
+    let column = if before || x == columns.len() - 1 { columns[x] } else { columns[x + 1] };
+
+    let block: Vec<&str> = text.split('\n').collect();
+    let width = block.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+    let (mut top_left, mut end) = (0, 0);
+    for (offset, line) in block.iter().enumerate() {
+        let y = first_line + offset;
+        while state.get_editor_lines().len() <= y {
+            let text = state.get_text();
+            let length = state.get_file_length_in_chars();
+            state.replace_text(length, 0, if text.is_empty() || text.ends_with('\n') { "\n" } else { "\n\n" });
+        }
+
+        let index = match insert_index(y, column, true, state) {
+            Some(index) => index,
+            None => continue,
+        };
+        // Each line is padded to the width of the block, unless nothing follows it
+        let at_line_end = state.get_text().chars().nth(index).map_or(true, |ch| ch == '\n');
+        let mut piece = format!("{:<width$}", line, width=width).repeat(count);
+        if at_line_end {
+            piece = piece.trim_right().to_string();
+        }
+        state.replace_text(index, 0, &piece);
+        if offset == 0 {
+            top_left = index;
+        }
+        end = index + piece.chars().count();
+    }
+    state.cursor_index = if move_past { end } else { top_left };
+}
+
+// The index at which text is typed into the line to start at the display column,
+// or usize::MAX for the end of the line. Lines too short to reach the column are
+// padded out with spaces if `pad` is set, and otherwise left alone.
+fn insert_index(line: usize, column: usize, pad: bool, state: &mut EditorState) -> Option<usize> {
+    let lines = state.get_editor_lines();
+    if line >= lines.len() {
+        return None;
+    }
+    let line_start = EditorState::line_start_index(&lines, line);
+    let columns = state.line_columns(&lines[line]);
+    let line_end = line_start + columns.len() - 1;
+    let width = columns[columns.len() - 1];
+
+    if column == usize::MAX {
+        Some(line_end)
+    } else if let Some(x) = columns.iter().position(|&start| start >= column) {
+        Some(line_start + x)
+    } else if pad {
+        state.replace_text(line_end, 0, &" ".repeat(column - width));
+        Some(line_end + column - width)
+    } else {
+        None
+    }
+}
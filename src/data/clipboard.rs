@@ -0,0 +1,217 @@
+use std::env;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// The ways the `"+` and `"*` registers can reach the system clipboard. Each of the
+// helper programs is run afresh for every copy and paste.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClipboardTool {
+    Wayland,  // `wl-copy` and `wl-paste`
+    Xclip,
+    Xsel,
+    Osc52,  // An escape sequence asking the terminal to copy, which works over SSH
+}
+
+impl ClipboardTool {
+    // The tool given by the `clipboardtool` option, working one out for `auto`
+    pub fn from_option(value: &str) -> Result<ClipboardTool, String> {
+        match value {
+            "wl-copy" => Ok(ClipboardTool::Wayland),
+            "xclip" => Ok(ClipboardTool::Xclip),
+            "xsel" => Ok(ClipboardTool::Xsel),
+            "osc52" => Ok(ClipboardTool::Osc52),
+            _ => detect().ok_or_else(|| String::from("No clipboard tool found, see :set clipboardtool")),
+        }
+    }
+
+    // The program and arguments which copy their input, or print what was copied
+    fn command(&self, primary: bool, paste: bool) -> Option<(&'static str, Vec<&'static str>)> {
+        let (selection, xsel_selection) = if primary { ("primary", "--primary") } else { ("clipboard", "--clipboard") };
+        let (program, mut args) = match (*self, paste) {
+            (ClipboardTool::Wayland, false) => ("wl-copy", vec![]),
+            (ClipboardTool::Wayland, true) => ("wl-paste", vec!["--no-newline"]),
+            (ClipboardTool::Xclip, false) => ("xclip", vec!["-in", "-selection", selection]),
+            (ClipboardTool::Xclip, true) => ("xclip", vec!["-out", "-selection", selection]),
+            (ClipboardTool::Xsel, false) => ("xsel", vec!["--input", xsel_selection]),
+            (ClipboardTool::Xsel, true) => ("xsel", vec!["--output", xsel_selection]),
+            (ClipboardTool::Osc52, _) => return None,
+        };
+        if primary && *self == ClipboardTool::Wayland {
+            args.push("--primary");
+        }
+        Some((program, args))
+    }
+}
+
+// Over SSH the terminal is the only clipboard within reach. Otherwise the helper
+// for the display server in use is chosen, if it's installed.
+fn detect() -> Option<ClipboardTool> {
+    let has_var = |name: &str| env::var_os(name).map_or(false, |value| !value.is_empty());
+    if has_var("SSH_TTY") || has_var("SSH_CONNECTION") {
+        Some(ClipboardTool::Osc52)
+    } else if has_var("WAYLAND_DISPLAY") && on_path("wl-copy") {
+        Some(ClipboardTool::Wayland)
+    } else if has_var("DISPLAY") && on_path("xclip") {
+        Some(ClipboardTool::Xclip)
+    } else if has_var("DISPLAY") && on_path("xsel") {
+        Some(ClipboardTool::Xsel)
+    } else {
+        None
+    }
+}
+
+fn on_path(program: &str) -> bool {
+    env::var_os("PATH").map_or(false, |paths| env::split_paths(&paths).any(|dir| Path::new(&dir).join(program).is_file()))
+}
+
+// Hands the text to the clipboard helper. The helpers keep running in the
+// background to serve the clipboard, so only their exit status is waited on.
+pub fn copy(tool: ClipboardTool, primary: bool, text: &str) -> Result<(), String> {
+    let (program, args) = match tool.command(primary, false) {
+        Some(command) => command,
+        None => return Err(String::from("Copying by escape sequence goes through the terminal")),
+    };
+    let mut child = Command::new(program)
+        .args(&args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|error| format!("Unable to run {}: {}", program, error))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes()).map_err(|error| format!("Unable to copy with {}: {}", program, error))?;
+    }
+    match child.wait() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("{} failed: {}", program, status)),
+        Err(error) => Err(format!("{} failed: {}", program, error)),
+    }
+}
+
+pub fn paste(tool: ClipboardTool, primary: bool) -> Result<String, String> {
+    let (program, args) = match tool.command(primary, true) {
+        Some(command) => command,
+        None => return Err(String::from("The terminal's clipboard can't be read")),
+    };
+    let output = Command::new(program)
+        .args(&args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|error| format!("Unable to run {}: {}", program, error))?;
+    if !output.status.success() {
+        return Err(format!("{} failed: {}", program, output.status));
+    }
+    String::from_utf8(output.stdout).map_err(|_| format!("{} gave text which isn't UTF-8", program))
+}
+
+// The OSC 52 escape sequence which asks the terminal to put the text on the
+// clipboard, or the primary selection.
+pub fn osc52_sequence(text: &str, primary: bool) -> String {
+    format!("\x1b]52;{};{}\x07", if primary { "p" } else { "c" }, base64(text.as_bytes()))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (index, &byte)| group | (byte as u32) << (16 - 8 * index));
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * index) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+    use std::process;
+
+    use termion::event::Event;
+
+    use controller::events::InputModeMultiplexer;
+    use controller::keymap::parse_keys;
+    use data::editor_state::{EditorState, Mode};
+    use data::piece_table::PieceTable;
+    use super::{base64, osc52_sequence};
+
+    // Stand-ins for the clipboard helpers, which keep the clipboard and primary
+    // selection in files next to them
+    const XCLIP: &str = "#!/bin/sh\n\
+        dir=$(dirname \"$0\")\n\
+        case \"$1\" in\n\
+            -in) cat > \"$dir/$3\" ;;\n\
+            -out) cat \"$dir/$3\" ;;\n\
+        esac\n";
+    const WL_COPY: &str = "#!/bin/sh\n\
+        dir=$(dirname \"$0\")\n\
+        if [ \"$1\" = --primary ]; then cat > \"$dir/primary\"; else cat > \"$dir/clipboard\"; fi\n";
+    const WL_PASTE: &str = "#!/bin/sh\n\
+        dir=$(dirname \"$0\")\n\
+        if [ \"$2\" = --primary ]; then cat \"$dir/primary\"; else cat \"$dir/clipboard\"; fi\n";
+
+    // Puts the stand-ins on the PATH, ahead of any real helpers, in a directory of
+    // their own
+    fn install_helpers() -> PathBuf {
+        let dir = env::temp_dir().join(format!("bullet-clipboard-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for &(name, script) in [("xclip", XCLIP), ("wl-copy", WL_COPY), ("wl-paste", WL_PASTE)].iter() {
+            let path = dir.join(name);
+            fs::write(&path, script).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let paths = env::var_os("PATH").unwrap_or_default();
+        let paths = env::join_paths(Some(dir.clone()).into_iter().chain(env::split_paths(&paths))).unwrap();
+        env::set_var("PATH", paths);
+        dir
+    }
+
+    fn after_keys(tool: &str, text: &str, keys: &str) -> EditorState {
+        let mut state = EditorState::new(String::from("test.txt"), Mode::Navigate, 0, PieceTable::from_text(text.to_string()), vec![]);
+        state.settings.apply(&format!("clipboardtool={}", tool), false).unwrap();
+        let mut input = InputModeMultiplexer::new();
+        for key in parse_keys(keys, "").unwrap() {
+            input.do_action_for_input(Event::Key(key), &mut state);
+        }
+        state
+    }
+
+    #[test]
+    fn copying_and_pasting_through_the_helpers() {
+        let dir = install_helpers();
+        for tool in ["xclip", "wl-copy"].iter() {
+            assert_eq!(after_keys(tool, "one\ntwo\n", "\"+yyj\"+p").get_text(), "one\ntwo\none\n");
+            assert_eq!(fs::read_to_string(dir.join("clipboard")).unwrap(), "one\n");
+            assert_eq!(after_keys(tool, "one\ntwo\n", "j\"*yyk\"*P").get_text(), "two\none\ntwo\n");
+            assert_eq!(fs::read_to_string(dir.join("primary")).unwrap(), "two\n");
+
+            // Text copied by other programs is pasted as it is
+            fs::write(dir.join("clipboard"), "new").unwrap();
+            assert_eq!(after_keys(tool, "one\n", "\"+P").get_text(), "newone\n");
+            fs::write(dir.join("primary"), "old").unwrap();
+            assert_eq!(after_keys(tool, "one\n", "\"*P").get_text(), "oldone\n");
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn encoding_for_osc52() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"abc"), "YWJj");
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"a"), "YQ==");
+        assert_eq!(base64(b"abcd"), "YWJjZA==");
+        assert_eq!(base64("é\n".as_bytes()), "w6kK");
+        assert_eq!(osc52_sequence("abc", false), "\x1b]52;c;YWJj\x07");
+        assert_eq!(osc52_sequence("ab", true), "\x1b]52;p;YWI=\x07");
+    }
+}
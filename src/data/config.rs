@@ -0,0 +1,249 @@
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use toml::Value;
+use toml::value::Table;
+
+use data::settings::{Settings, OptionValue, find_option};
+use data::editor_state::{Mode, VISUAL_MODES};
+use controller::keymap::{Keymap, parse_keys};
+
+// The user's configuration, read from `config.toml` at start-up. Top level keys
+// are options, as they'd be given to `:set`, and the tables are:
+//
+//     theme = "Solarized (dark)"
+//     tabstop = 4
+//
+//     [filetype.python]     # Options set with :setlocal for Python files
+//     expandtab = true
+//
+//     [keys.normal]         # Key mappings by mode (normal, visual, insert or command),
+//     "<leader>w" = ":w<CR>"  # which are never remapped, as with :noremap
+//
+// Problems are collected in `errors` rather than stopping the editor starting.
+#[derive(Debug, Default)]
+pub struct Config {
+    pub theme: Option<String>,
+    options: Table,
+    filetypes: Table,
+    pub key_mappings: Vec<KeyMapping>,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyMapping {
+    pub mode: String,
+    pub keys: String,
+    pub action: String,
+}
+
+// `$XDG_CONFIG_HOME/bullet/config.toml`, falling back to `~/.config`
+pub fn config_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    config_home.map(|dir| dir.join("bullet").join("config.toml"))
+}
+
+// Loads the config file, if there is one.
+pub fn load_config() -> Config {
+    let path = match config_path() {
+        Some(path) => path,
+        None => return Config::default(),
+    };
+    if !path.exists() {
+        return Config::default();
+    }
+
+    let mut text = String::new();
+    match File::open(&path).and_then(|mut file| file.read_to_string(&mut text)) {
+        Ok(_) => parse_config(&text),
+        Err(error) => Config {
+            errors: vec![format!("Unable to read {}: {}", path.display(), error)],
+            ..Config::default()
+        },
+    }
+}
+
+pub fn parse_config(text: &str) -> Config {
+    let mut config = Config::default();
+    let table = match text.parse::<Value>() {
+        Ok(Value::Table(table)) => table,
+        Ok(_) => return config,
+        Err(error) => {
+            config.errors.push(format!("config.toml: {}", error));
+            return config;
+        }
+    };
+
+    for (key, value) in table {
+        match (key.as_str(), value) {
+            ("theme", Value::String(theme)) => config.theme = Some(theme),
+            ("filetype", Value::Table(filetypes)) => config.filetypes = filetypes,
+            ("keys", Value::Table(modes)) => {
+                for (mode, mappings) in modes {
+                    match mappings {
+                        Value::Table(mappings) => for (keys, action) in mappings {
+                            match action {
+                                Value::String(action) => config.key_mappings.push(KeyMapping { mode: mode.clone(), keys, action }),
+                                _ => config.errors.push(format!("config.toml: keys.{}: {} must map to a string", mode, keys)),
+                            }
+                        },
+                        _ => config.errors.push(format!("config.toml: keys.{} must be a table", mode)),
+                    }
+                }
+            },
+            ("theme", _) | ("filetype", _) | ("keys", _) => {
+                config.errors.push(format!("config.toml: {} has the wrong type", key));
+            },
+            (_, value) => {
+                config.options.insert(key, value);
+            }
+        }
+    }
+    config
+}
+
+impl Config {
+
+    // Sets the global options, then the local overrides for the file's type.
+    pub fn apply_options(&mut self, settings: &mut Settings, filetype: &str) {
+        for (name, value) in self.options.iter() {
+            if let Err(error) = set_option(settings, name, value, false) {
+                self.errors.push(format!("config.toml: {}", error));
+            }
+        }
+
+        match self.filetypes.get(filetype) {
+            Some(&Value::Table(ref options)) => for (name, value) in options.iter() {
+                if let Err(error) = set_option(settings, name, value, true) {
+                    self.errors.push(format!("config.toml: filetype.{}: {}", filetype, error));
+                }
+            },
+            Some(_) => self.errors.push(format!("config.toml: filetype.{} must be a table", filetype)),
+            None => (),
+        }
+    }
+
+    pub fn apply_key_mappings(&mut self, keymap: &mut Keymap, leader: &str) {
+        for mapping in self.key_mappings.iter() {
+            let modes = match mapping.mode.as_str() {
+                "normal" | "navigate" => vec![Mode::Navigate],
+                "visual" => VISUAL_MODES.to_vec(),
+                "insert" => vec![Mode::Insert],
+                "command" => vec![Mode::Command],
+                other => {
+                    self.errors.push(format!("config.toml: unknown mode keys.{}", other));
+                    continue;
+                }
+            };
+            match (parse_keys(&mapping.keys, leader), parse_keys(&mapping.action, leader)) {
+                (Ok(lhs), Ok(rhs)) => for mode in modes {
+                    keymap.map(mode, lhs.clone(), rhs.clone(), true);
+                },
+                (Err(error), _) | (_, Err(error)) => {
+                    self.errors.push(format!("config.toml: keys.{}: {}", mapping.mode, error));
+                }
+            }
+        }
+    }
+}
+
+// Converts the TOML value to the option's own type, by way of the text `:set`
+// would have been given, so that both are validated identically.
+fn set_option(settings: &mut Settings, name: &str, value: &Value, local: bool) -> Result<(), String> {
+    let option = find_option(name).ok_or(format!("Unknown option: {}", name))?;
+    let value = match *value {
+        Value::Boolean(enable) => OptionValue::Bool(enable),
+        Value::String(ref text) => option.parse(text)?,
+        Value::Integer(number) if number >= 0 => option.parse(&number.to_string())?,
+        Value::Array(ref items) => {
+            let items: Option<Vec<String>> = items.iter().map(|item| match *item {
+                Value::String(ref text) => Some(text.clone()),
+                Value::Integer(number) => Some(number.to_string()),
+                _ => None,
+            }).collect();
+            let items = items.ok_or(format!("Invalid value for {}", name))?;
+            option.parse(&items.join(","))?
+        },
+        _ => return Err(format!("Invalid value for {}", name)),
+    };
+    settings.set(option.name, value, local)
+}
+
+#[cfg(test)]
+mod tests {
+    use controller::keymap::Keymap;
+    use data::editor_state::Mode;
+    use data::settings::Settings;
+    use super::parse_config;
+
+    const CONFIG: &str = r#"
+        theme = "InspiredGitHub"
+        tabstop = 4
+        wrap = false
+        colorcolumn = [80, 100]
+
+        [filetype.python]
+        expandtab = true
+        shiftwidth = 2
+
+        [keys.normal]
+        "<leader>w" = ":w<CR>"
+
+        [keys.visual]
+        "<C-c>" = "\"+y"
+    "#;
+
+    #[test]
+    fn options_are_set_with_the_file_types_locally() {
+        let mut config = parse_config(CONFIG);
+        assert_eq!(config.theme, Some(String::from("InspiredGitHub")));
+
+        let mut settings = Settings::new();
+        config.apply_options(&mut settings, "python");
+        assert!(config.errors.is_empty(), "{:?}", config.errors);
+        assert_eq!((settings.tabstop(), settings.wrap()), (4, false));
+        assert_eq!(settings.colorcolumn(), vec![80, 100]);
+        assert_eq!((settings.expandtab(), settings.effective_shiftwidth()), (true, 2));
+        assert!(settings.is_local("expandtab") && !settings.is_local("tabstop"));
+
+        let mut settings = Settings::new();
+        config.apply_options(&mut settings, "rust");
+        assert_eq!((settings.expandtab(), settings.effective_shiftwidth()), (false, 8));
+    }
+
+    #[test]
+    fn key_mappings_use_the_leader() {
+        let mut config = parse_config(CONFIG);
+        let mut keymap = Keymap::new();
+        config.apply_key_mappings(&mut keymap, ",");
+        assert!(config.errors.is_empty(), "{:?}", config.errors);
+        assert_eq!(keymap.describe_mappings(&[Mode::Navigate], &[]), vec!["n  ,w           * :w<CR>"]);
+        assert_eq!(keymap.describe_mappings(&[Mode::VisualBlock], &[]), vec!["v  <C-c>        * \"+y"]);
+    }
+
+    #[test]
+    fn problems_are_collected_rather_than_stopping_start_up() {
+        assert_eq!(parse_config("tabstop = ").errors.len(), 1);
+        assert_eq!(parse_config("theme = 3").errors, vec!["config.toml: theme has the wrong type"]);
+
+        let mut config = parse_config("tabstop = 0\nnosuch = 1\nwrap = \"yes\"\ntimeoutlen = 500\n[keys]\nnormal = 3\n[filetype]\npython = 1\n");
+        let mut settings = Settings::new();
+        config.apply_options(&mut settings, "python");
+        config.apply_key_mappings(&mut Keymap::new(), "\\");
+        config.errors.sort();
+        assert_eq!(config.errors, vec![
+            "config.toml: Invalid argument: wrap=yes",
+            "config.toml: Invalid tabstop: must be at least 1",
+            "config.toml: Unknown option: nosuch",
+            "config.toml: filetype.python must be a table",
+            "config.toml: keys.normal must be a table",
+        ]);
+        // The rest of the file still applies
+        assert_eq!(settings.timeoutlen(), 500);
+    }
+}
@@ -0,0 +1,164 @@
+use std::cmp;
+
+// Line based diffing between the file as loaded and the current buffer.
+
+// A contiguous region of change. Lines [old_start, old_start + old_len) of the old
+// text were replaced by lines [new_start, new_start + new_len) of the new text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineChange {
+    Added,
+    Modified,
+    DeletedBelow,  // Lines were removed directly after this one
+    DeletedAbove,  // Lines were removed before the first line of the file
+}
+
+// Above this many (old x new) line comparisons, the changed middle section is
+// reported as a single hunk rather than being diffed line by line.
+const MAX_LCS_CELLS: usize = 4_000_000;
+
+pub fn diff_lines(old: &[String], new: &[String]) -> Vec<Hunk> {
+    // Most edits touch a small region, so strip the common prefix and suffix
+    // before doing any real work.
+    let prefix = old.iter().zip(new.iter()).take_while(|&(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev()
+                              .zip(new[prefix..].iter().rev())
+                              .take_while(|&(a, b)| a == b)
+                              .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    if old_middle.is_empty() && new_middle.is_empty() {
+        return vec![];
+    }
+    if old_middle.len() * new_middle.len() > MAX_LCS_CELLS {
+        return vec![Hunk { old_start: prefix, old_len: old_middle.len(), new_start: prefix, new_len: new_middle.len() }];
+    }
+
+    // lcs[i][j] is the length of the longest common subsequence of old_middle[i..] and new_middle[j..]
+    let (n, m) = (old_middle.len(), new_middle.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old_middle[i] == new_middle[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut hunks = vec![];
+    let mut current: Option<Hunk> = None;
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old_middle[i] == new_middle[j] {
+            if let Some(hunk) = current.take() {
+                hunks.push(hunk);
+            }
+            i += 1;
+            j += 1;
+            continue;
+        }
+
+        let hunk = current.get_or_insert(Hunk { old_start: prefix + i, old_len: 0, new_start: prefix + j, new_len: 0 });
+        if j < m && (i == n || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            hunk.new_len += 1;
+            j += 1;
+        } else {
+            hunk.old_len += 1;
+            i += 1;
+        }
+    }
+    if let Some(hunk) = current.take() {
+        hunks.push(hunk);
+    }
+    hunks
+}
+
+// The change marker for each line of the new text.
+pub fn line_changes(hunks: &[Hunk], new_line_count: usize) -> Vec<Option<LineChange>> {
+    let mut changes = vec![None; new_line_count];
+    for hunk in hunks {
+        if hunk.new_len == 0 {
+            if hunk.new_start == 0 {
+                if new_line_count > 0 {
+                    changes[0] = Some(LineChange::DeletedAbove);
+                }
+            } else {
+                changes[hunk.new_start - 1] = Some(LineChange::DeletedBelow);
+            }
+            continue;
+        }
+
+        let modified = cmp::min(hunk.old_len, hunk.new_len);
+        for offset in 0..hunk.new_len {
+            changes[hunk.new_start + offset] = Some(
+                if offset < modified { LineChange::Modified } else { LineChange::Added }
+            );
+        }
+    }
+    changes
+}
+
+// Formats the hunks as a unified diff with the given number of context lines.
+pub fn unified_diff(old_name: &str, new_name: &str, old: &[String], new: &[String], hunks: &[Hunk], context: usize) -> Vec<String> {
+    let mut output = vec![];
+    if hunks.is_empty() {
+        return output;
+    }
+    output.push(format!("--- {}", old_name));
+    output.push(format!("+++ {}", new_name));
+
+    // Hunks whose context would overlap are printed as one group
+    let mut groups: Vec<Vec<&Hunk>> = vec![];
+    for hunk in hunks {
+        let joins_previous = groups.last().and_then(|group| group.last()).map_or(false, |previous| {
+            previous.old_start + previous.old_len + 2 * context >= hunk.old_start
+        });
+        if joins_previous {
+            groups.last_mut().unwrap().push(hunk);
+        } else {
+            groups.push(vec![hunk]);
+        }
+    }
+
+    for group in groups {
+        let (first, last) = (group[0], group[group.len() - 1]);
+        let old_start = first.old_start.saturating_sub(context);
+        let old_end = cmp::min(last.old_start + last.old_len + context, old.len());
+        let new_start = first.new_start.saturating_sub(context);
+        let new_end = cmp::min(last.new_start + last.new_len + context, new.len());
+
+        output.push(format!(
+            "@@ -{},{} +{},{} @@",
+            if old_end > old_start { old_start + 1 } else { old_start }, old_end - old_start,
+            if new_end > new_start { new_start + 1 } else { new_start }, new_end - new_start
+        ));
+
+        let mut old_index = old_start;
+        for hunk in group {
+            for line in old[old_index..hunk.old_start].iter() {
+                output.push(format!(" {}", line.trim_right_matches('\n')));
+            }
+            for line in old[hunk.old_start..hunk.old_start + hunk.old_len].iter() {
+                output.push(format!("-{}", line.trim_right_matches('\n')));
+            }
+            for line in new[hunk.new_start..hunk.new_start + hunk.new_len].iter() {
+                output.push(format!("+{}", line.trim_right_matches('\n')));
+            }
+            old_index = hunk.old_start + hunk.old_len;
+        }
+        for line in old[old_index..old_end].iter() {
+            output.push(format!(" {}", line.trim_right_matches('\n')));
+        }
+    }
+    output
+}
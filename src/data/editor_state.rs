@@ -1,11 +1,22 @@
 use std::fmt;
 use std::cmp;
+use std::collections::VecDeque;
+use std::time::SystemTime;
 
-use termion::event::Event;
+use termion::event::{Event, Key};
 
-use data::piece_table::PieceTable;
-use controller::input::ExprState;
+use data::piece_table::{PieceTable, Source};
+use data::diff::{Hunk, LineChange, diff_lines, line_changes, unified_diff};
+use data::settings::{Settings, OptionValue};
+use data::registers::{Registers, Register, RegisterKind};
+use data::marks::Marks;
+use data::io::{detect_fileformat, strip_line_endings};
+use data::clipboard::{self, ClipboardTool};
+use data::wrap::{wrap_line, row_containing, char_columns, char_at_column, expand_tabs, whitespace_between};
+use controller::input::{ExprState, Change};
+use controller::keymap::Keymap;
 use controller::events::InputModeMultiplexer;
+use controller::macros::Typeahead;
 
 pub struct EditorState {
     file_name: String,
@@ -14,6 +25,30 @@ pub struct EditorState {
     piece_table: PieceTable,
     pub mode_input_buffer: Vec<char>,
     pub expression_state: ExprState,
+    pub pending_keys: String,  // Keys of a command which is still being typed, as shown in the status line
+    pub settings: Settings,
+    pub keymap: Keymap,
+    pub registers: Registers,
+    pub terminal_sequences: Vec<String>,  // Escape sequences for the terminal itself, like OSC 52, sent with the next frame
+    pub last_char_search: Option<CharSearch>,  // Repeated by `;` and `,`
+    pub last_change: Option<Change>,  // Made again by `.`
+    pub pending_change: Option<Change>,  // A change which entered Insert mode, complete once the typing is done
+    pub recording: Option<(char, Vec<Key>)>,  // The register a macro is being recorded into, and the keys typed so far
+    pub typeahead: VecDeque<Typeahead>,  // What macros being played have still to type
+    pub last_macro: Option<char>,  // Played again by `@@`
+    pub marks: Marks,
+    pub selection_anchor: usize,  // The end of the selection the cursor moves away from, in the Visual modes
+    pub last_selection: Option<Selection>,  // Selected again by `gv`
+    pub block_insert: Option<BlockInsert>,  // Text typed after a Visual-block `I` or `A`, waiting to be copied to the other lines
+    pub shell_commands: Vec<String>,  // Commands given to `:!`, waiting for the event loop to run them
+    sticky_column: Option<(usize, usize)>,  // The column `j` and `k` keep to, while the cursor is still at the index given
+    pub viewport: Viewport,
+    pub tint_added_text: bool,  // Debugging aid, highlights text which came from the add buffer
+    panel: Option<Vec<String>>,  // Multi-line output shown above the status line until the next key press
+    status_message: Option<(usize, String)>,  // Messages are numbered, so a stale expiry can't clear a newer one
+    next_message_id: usize,
+    quit_requested: bool,
+    saved_modified_time: Option<SystemTime>,  // The modification time the file was left with when last saved
 }
 
 impl EditorState {
@@ -25,12 +60,544 @@ impl EditorState {
         mode_input_buffer: Vec<char>
     ) -> Self {
         let expression_state = ExprState::Waiting;
-        EditorState { file_name, mode, cursor_index, piece_table, mode_input_buffer, expression_state }
+        let text = piece_table.text();
+        let mut state = EditorState {
+            file_name,
+            mode,
+            cursor_index,
+            piece_table,
+            mode_input_buffer,
+            expression_state,
+            pending_keys: String::new(),
+            settings: Settings::new(),
+            keymap: Keymap::new(),
+            registers: Registers::new(),
+            terminal_sequences: vec![],
+            last_char_search: None,
+            last_change: None,
+            pending_change: None,
+            recording: None,
+            typeahead: VecDeque::new(),
+            last_macro: None,
+            marks: Marks::new(),
+            selection_anchor: 0,
+            last_selection: None,
+            block_insert: None,
+            shell_commands: vec![],
+            sticky_column: None,
+            viewport: Viewport::new(),
+            tint_added_text: false,
+            panel: None,
+            status_message: None,
+            next_message_id: 0,
+            quit_requested: false,
+            saved_modified_time: None,
+        };
+        state.load_text(text);
+        state
+    }
+
+    // Replaces the buffer with the text of the file. Lines are edited with plain LFs
+    // whatever the file has, and the line endings found are kept in `fileformat`
+    // for them to be saved as they were, unless the buffer has been given a
+    // `fileformat` of its own already.
+    pub fn load_text(&mut self, text: String) {
+        let fileformat = detect_fileformat(&text);
+        if fileformat != "unix" && !self.settings.is_local("fileformat") {
+            self.settings.set("fileformat", OptionValue::Str(fileformat.to_string()), true).expect("A fileformat which was detected");
+        }
+        self.piece_table = PieceTable::from_text(strip_line_endings(&text, fileformat));
+    }
+
+    // The whole buffer as one string
+    pub fn get_text(&self) -> String {
+        self.piece_table.text()
+    }
+
+    // `m` sets the mark at the cursor
+    pub fn set_mark(&mut self, name: char) -> Result<(), String> {
+        let index = self.cursor_index;
+        self.marks.set(name, index, &self.file_name)
+    }
+
+    // Where the mark is now, kept within the text in case what followed it was deleted
+    pub fn mark_index(&self, name: char) -> Result<usize, String> {
+        let index = self.marks.get(name, &self.file_name)?;
+        Ok(cmp::min(index, self.get_file_length_in_chars().saturating_sub(1)))
+    }
+
+    // Goes to the mark, or the first non-blank of its line for `'`, as a jump
+    pub fn jump_to_mark(&mut self, name: char, linewise: bool) -> Result<(), String> {
+        let index = self.mark_index(name)?;
+        let from = self.cursor_index;
+        self.record_jump(from);
+        self.cursor_index = index;
+        if linewise {
+            let y = line_and_offset(&self.get_editor_lines(), index).0;
+            self.cursor_to_line(y);
+        }
+        Ok(())
+    }
+
+    // Adds the position to the jump list, as the place `''` and Ctrl-o go back to
+    pub fn record_jump(&mut self, from: usize) {
+        let lines = self.get_editor_lines();
+        self.marks.push_jump(from, &|index| line_and_offset(&lines, index).0);
+    }
+
+    // Ctrl-o and Ctrl-i, going back and forward through the jump list
+    pub fn jump_older(&mut self, count: usize) {
+        let lines = self.get_editor_lines();
+        let from = self.cursor_index;
+        if let Some(index) = self.marks.older_jump(from, count, &|index| line_and_offset(&lines, index).0) {
+            self.cursor_index = cmp::min(index, self.get_file_length_in_chars().saturating_sub(1));
+        }
+    }
+
+    pub fn jump_newer(&mut self, count: usize) {
+        if let Some(index) = self.marks.newer_jump(count) {
+            self.cursor_index = cmp::min(index, self.get_file_length_in_chars().saturating_sub(1));
+        }
+    }
+
+    // The text of the named register. `"%` is the name of the file, while `"+` and
+    // `"*` are the system clipboard and primary selection.
+    pub fn read_register(&self, name: char) -> Result<Register, String> {
+        let register = match if name == '"' { self.registers.unnamed() } else { name } {
+            '%' => Some(Register::new(self.file_name.clone(), RegisterKind::Charwise)),
+            '+' => return self.read_clipboard(false),
+            '*' => return self.read_clipboard(true),
+            other => self.registers.get(other),
+        };
+        register.ok_or_else(|| format!("Nothing in register {}", name))
+    }
+
+    // Text from elsewhere is taken as lines if it ends with a line break, as Vim
+    // does, while text copied from here comes back as it went.
+    fn read_clipboard(&self, primary: bool) -> Result<Register, String> {
+        let copied = self.registers.clipboard(primary);
+        let text = match ClipboardTool::from_option(self.settings.clipboardtool())? {
+            // The terminal can't be asked for its clipboard, so the last copy will have to do
+            ClipboardTool::Osc52 => return copied.cloned().ok_or_else(|| String::from("Nothing in the clipboard")),
+            tool => clipboard::paste(tool, primary)?,
+        };
+        match copied {
+            Some(register) if register.text == text => Ok(register.clone()),
+            _ => {
+                let kind = if text.ends_with('\n') { RegisterKind::Linewise } else { RegisterKind::Charwise };
+                Ok(Register::new(text, kind))
+            }
+        }
+    }
+
+    pub fn write_clipboard(&mut self, register: Register, primary: bool) -> Result<(), String> {
+        match ClipboardTool::from_option(self.settings.clipboardtool())? {
+            ClipboardTool::Osc52 => self.terminal_sequences.push(clipboard::osc52_sequence(&register.text, primary)),
+            tool => clipboard::copy(tool, primary, &register.text)?,
+        }
+        self.registers.remember_clipboard(register, primary);
+        Ok(())
     }
 
     pub fn set_mode(&mut self, new_mode: Mode) {
+        if self.mode.is_visual() && !new_mode.is_visual() {
+            self.last_selection = Some(Selection { mode: self.mode, anchor: self.selection_anchor, cursor: self.cursor_index });
+        }
         self.mode = new_mode;
     }
+
+    // Quitting is left to the event loop, so that the terminal can be restored
+    // on the way out rather than exiting the process from inside an input handler.
+    pub fn request_quit(&mut self) {
+        self.quit_requested = true;
+    }
+
+    pub fn should_quit(&self) -> bool {
+        self.quit_requested
+    }
+
+    // Shows a message in the status line, returning the id used to expire it.
+    pub fn set_status_message(&mut self, message: String) -> usize {
+        let id = self.next_message_id;
+        self.next_message_id += 1;
+        self.status_message = Some((id, message));
+        id
+    }
+
+    pub fn get_status_message(&self) -> Option<(usize, &str)> {
+        self.status_message.as_ref().map(|&(id, ref message)| (id, message.as_str()))
+    }
+
+    pub fn expire_status_message(&mut self, id: usize) {
+        let is_current = self.status_message.as_ref().map_or(false, |&(current_id, _)| current_id == id);
+        if is_current {
+            self.status_message = None;
+        }
+    }
+
+    pub fn show_panel(&mut self, lines: Vec<String>) {
+        self.panel = Some(lines);
+    }
+
+    pub fn get_panel(&self) -> Option<&Vec<String>> {
+        self.panel.as_ref()
+    }
+
+    pub fn close_panel(&mut self) {
+        self.panel = None;
+    }
+
+    // The source buffer of every char on each line, excluding newlines.
+    pub fn get_line_sources(&self) -> Vec<Vec<Source>> {
+        let mut line_sources = vec![vec![]];
+        for (ch, source, _, _) in self.piece_table.debug_iter() {
+            if ch == '\n' {
+                line_sources.push(vec![]);
+            } else {
+                line_sources.last_mut().unwrap().push(source);
+            }
+        }
+        line_sources
+    }
+
+    // One row per piece, for inspecting the state of the piece table.
+    pub fn describe_pieces(&self) -> Vec<String> {
+        let mut rows = vec![format!(
+            "{:>5}  {:<6} {:>8} {:>8}  {}", "piece", "source", "start", "length", "text"
+        )];
+        for (piece_index, piece) in self.piece_table.get_pieces().iter().enumerate() {
+            let text: String = self.piece_table.debug_iter()
+                .filter(|&(_, _, index, _)| index == piece_index)
+                .map(|(ch, _, _, _)| ch)
+                .collect();
+            rows.push(format!(
+                "{:>5}  {:<6} {:>8} {:>8}  {:?}",
+                piece_index, format!("{:?}", piece.source), piece.start, piece.length, text
+            ));
+        }
+        rows.push(format!(
+            "original buffer: {} chars, add buffer: {} chars",
+            self.piece_table.original_file.chars().count(),
+            self.piece_table.add_buffer.chars().count()
+        ));
+        rows
+    }
+
+    // Whether there's anything to save
+    pub fn is_modified(&self) -> bool {
+        !self.piece_table.is_unmodified()
+    }
+
+    // Takes the text as just written to be the file on disk, which changes are
+    // marked against from then on. The file's modification time is kept, so the
+    // write can be told apart from changes made to the file by anything else.
+    pub fn mark_saved(&mut self, modified_time: Option<SystemTime>) {
+        self.piece_table = PieceTable::from_text(self.piece_table.text());
+        self.saved_modified_time = modified_time;
+    }
+
+    pub fn saved_modified_time(&self) -> Option<SystemTime> {
+        self.saved_modified_time
+    }
+
+    // Hunks of lines changed relative to the file as it was last loaded or saved.
+    pub fn change_hunks(&self) -> Vec<Hunk> {
+        // No text from the add buffer and nothing deleted means nothing to diff
+        if self.piece_table.is_unmodified() {
+            return vec![];
+        }
+        diff_lines(&self.piece_table.original_lines(), &self.get_editor_lines())
+    }
+
+    // The change marker, if any, for each line of the buffer.
+    pub fn get_line_changes(&self) -> Vec<Option<LineChange>> {
+        line_changes(&self.change_hunks(), self.get_editor_lines().len())
+    }
+
+    // The unsaved changes as a unified diff against the file on disk.
+    pub fn unsaved_diff(&self) -> Vec<String> {
+        let original_lines = self.piece_table.original_lines();
+        let lines = self.get_editor_lines();
+        let hunks = diff_lines(&original_lines, &lines);
+        unified_diff(
+            &format!("a/{}", self.file_name),
+            &format!("b/{}", self.file_name),
+            &original_lines,
+            &lines,
+            &hunks,
+            3
+        )
+    }
+
+    // The line each hunk is marked on, in order.
+    fn hunk_lines(&self) -> Vec<usize> {
+        self.change_hunks().iter()
+            .map(|hunk| if hunk.new_len == 0 { hunk.new_start.saturating_sub(1) } else { hunk.new_start })
+            .collect()
+    }
+
+    // The [start, end) char ranges of the line shown on each display row. With
+    // nowrap, the whole line is a single row.
+    pub fn display_rows(&self, line: &str) -> Vec<(usize, usize)> {
+        let chars: Vec<char> = line.trim_right_matches('\n').chars().collect();
+        let columns = char_columns(&chars, self.settings.tabstop());
+        if !self.settings.wrap() || self.viewport.text_width == 0 {
+            return vec![(0, columns[chars.len()])];
+        }
+        wrap_line(
+            &expand_tabs(&chars, &columns),
+            self.viewport.text_width,
+            self.settings.showbreak().chars().count(),
+            self.settings.linebreak()
+        )
+    }
+
+    // The display column at which each char of the line starts, see `char_columns`.
+    pub fn line_columns(&self, line: &str) -> Vec<usize> {
+        let chars: Vec<char> = line.trim_right_matches('\n').chars().collect();
+        char_columns(&chars, self.settings.tabstop())
+    }
+
+    // The display column of the cursor within its line.
+    pub fn get_cursor_column(&self) -> usize {
+        let pos = self.get_cursor_position();
+        let lines = self.get_editor_lines();
+        let columns = self.line_columns(&lines[pos.y]);
+        columns[cmp::min(pos.x, columns.len() - 1)]
+    }
+
+    // Scrolls the viewport just enough for the cursor to be visible within a text
+    // area of the given size.
+    pub fn scroll_to_cursor(&mut self, text_width: usize, text_height: usize) {
+        self.viewport.text_width = text_width;
+        self.viewport.text_height = text_height;
+
+        let pos = self.get_cursor_position();
+        let cursor_column = self.get_cursor_column();
+        if pos.y < self.viewport.top_line {
+            self.viewport.top_line = pos.y;
+        }
+
+        if self.settings.wrap() {
+            self.viewport.left_column = 0;
+            let lines = self.get_editor_lines();
+            // Works back from the cursor line, taking in as many of the lines above it
+            // as fit, but never scrolling up
+            let mut rows = row_containing(&self.display_rows(&lines[pos.y]), cursor_column) + 1;
+            let mut top_line = pos.y;
+            while top_line > self.viewport.top_line {
+                let rows_above = self.display_rows(&lines[top_line - 1]).len();
+                if rows + rows_above > text_height {
+                    break;
+                }
+                rows += rows_above;
+                top_line -= 1;
+            }
+            self.viewport.top_line = top_line;
+        } else {
+            if pos.y >= self.viewport.top_line + text_height {
+                self.viewport.top_line = pos.y + 1 - text_height;
+            }
+            if cursor_column < self.viewport.left_column {
+                self.viewport.left_column = cursor_column;
+            } else if cursor_column >= self.viewport.left_column + text_width {
+                self.viewport.left_column = cursor_column + 1 - text_width;
+            }
+        }
+    }
+
+    // Replaces `length` chars starting at `index` with `text`.
+    pub fn replace_text(&mut self, index: usize, length: usize, text: &str) {
+        self.piece_table.delete(index, length);
+        self.piece_table.insert(index, text);
+        self.text_edited(index, length, text.chars().count());
+    }
+
+    // Keeps the marks on the text they were set on once `length` chars at `index`
+    // have been replaced by `inserted` chars, and moves `'.` to the change.
+    fn text_edited(&mut self, index: usize, length: usize, inserted: usize) {
+        if length == 0 && inserted == 0 {
+            return;
+        }
+        self.marks.adjust(index, length, inserted, &self.file_name);
+        self.marks.set_automatic('.', index);
+    }
+
+    // Recalculates every run of whitespace containing a tab for a new tabstop, so
+    // the text looks the same afterwards. With `expandtab` the tabs become spaces.
+    // With `bang`, runs made only of spaces are converted to tabs as well.
+    pub fn retab(&mut self, new_tabstop: Option<usize>, bang: bool) {
+        let old_tabstop = self.settings.tabstop();
+        let new_tabstop = new_tabstop.unwrap_or(old_tabstop);
+        let expandtab = self.settings.expandtab();
+        let lines = self.get_editor_lines();
+        let cursor_line = self.get_cursor_position().y;
+
+        for y in (0..lines.len()).rev() {
+            let chars: Vec<char> = lines[y].trim_right_matches('\n').chars().collect();
+            let columns = char_columns(&chars, old_tabstop);
+            let mut retabbed = String::new();
+            let mut index = 0;
+            while index < chars.len() {
+                if chars[index] != ' ' && chars[index] != '\t' {
+                    retabbed.push(chars[index]);
+                    index += 1;
+                    continue;
+                }
+
+                let run_end = index + chars[index..].iter().take_while(|&&ch| ch == ' ' || ch == '\t').count();
+                let run = &chars[index..run_end];
+                let has_tab = run.contains(&'\t');
+                if has_tab || (bang && !expandtab && run.len() > 1) {
+                    retabbed.push_str(&whitespace_between(columns[index], columns[run_end], new_tabstop, expandtab));
+                } else {
+                    retabbed.extend(run.iter());
+                }
+                index = run_end;
+            }
+
+            if retabbed.chars().collect::<Vec<char>>() != chars {
+                let line_start = EditorState::line_start_index(&lines, y);
+                self.replace_text(line_start, chars.len(), &retabbed);
+            }
+        }
+
+        self.settings.set("tabstop", OptionValue::Int(new_tabstop), true).expect("A tabstop of at least 1");
+        let lines = self.get_editor_lines();
+        self.cursor_index = EditorState::line_start_index(&lines, cmp::min(cursor_line, lines.len().saturating_sub(1)));
+    }
+
+    pub fn line_start_index(lines: &[String], y: usize) -> usize {
+        lines[..y].iter().map(|line| line.chars().count()).sum()
+    }
+
+    // Moves to the given line, at the column the cursor had before a run of `j` and
+    // `k` began, or as near as the line's length allows.
+    fn move_to_line_keeping_column(&mut self, y: usize) {
+        let column = match self.sticky_column {
+            Some((index, column)) if index == self.cursor_index => column,
+            _ => self.get_cursor_column(),
+        };
+        let lines = self.get_editor_lines();
+        let x = if column == usize::MAX {
+            last_char_of_line(&lines[y])
+        } else {
+            char_at_column(&self.line_columns(&lines[y]), column)
+        };
+        self.cursor_index = EditorState::line_start_index(&lines, y) + x;
+        self.sticky_column = Some((self.cursor_index, column));
+    }
+
+    fn move_by_word(&mut self, motion: fn(&[char], usize, bool) -> usize, big: bool) {
+        let text: Vec<char> = self.get_text().chars().collect();
+        if !text.is_empty() {
+            self.cursor_index = motion(&text, cmp::min(self.cursor_index, text.len() - 1), big);
+        }
+    }
+
+    // The first and last lines on screen, as last laid out
+    pub fn visible_lines(&self) -> (usize, usize) {
+        let lines = self.get_editor_lines();
+        let top = cmp::min(self.viewport.top_line, lines.len().saturating_sub(1));
+        let mut rows = 0;
+        let mut bottom = top;
+        for y in top..lines.len() {
+            rows += if self.settings.wrap() { self.display_rows(&lines[y]).len() } else { 1 };
+            if rows > cmp::max(self.viewport.text_height, 1) {
+                break;
+            }
+            bottom = y;
+        }
+        (top, bottom)
+    }
+
+    // The chars selected on each line in the Visual modes, from the first line to the last
+    pub fn selected_spans(&self) -> Vec<SelectedSpan> {
+        let lines = self.get_editor_lines();
+        if !self.mode.is_visual() || lines.is_empty() {
+            return vec![];
+        }
+        let (start, end) = (cmp::min(self.selection_anchor, self.cursor_index), cmp::max(self.selection_anchor, self.cursor_index));
+        let (first_line, start_offset) = line_and_offset(&lines, start);
+        let (last_line, end_offset) = line_and_offset(&lines, end);
+        let (left, right) = self.block_columns();
+
+        (first_line..last_line + 1).map(|line| {
+            let length = lines[line].chars().count();
+            let (start, end) = match self.mode {
+                Mode::VisualLine => (0, length),
+                Mode::VisualBlock => {
+                    let columns = self.line_columns(&lines[line]);
+                    let chars = columns.len() - 1;
+                    let start = (0..chars).find(|&x| columns[x + 1] > left).unwrap_or(chars);
+                    let end = (0..chars).rev().find(|&x| columns[x] <= right).map_or(0, |x| x + 1);
+                    (start, cmp::max(start, end))
+                },
+                _ => (
+                    if line == first_line { start_offset } else { 0 },
+                    if line == last_line { cmp::min(end_offset + 1, length) } else { length }
+                ),
+            };
+            SelectedSpan { line, start, end }
+        }).collect()
+    }
+
+    // The leftmost and rightmost display columns of a Visual-block selection. After
+    // `$` the right is usize::MAX, as the block reaches the end of every line.
+    pub fn block_columns(&self) -> (usize, usize) {
+        let lines = self.get_editor_lines();
+        if lines.is_empty() {
+            return (0, 0);
+        }
+        let column_of = |index: usize| {
+            let (y, x) = line_and_offset(&lines, index);
+            let columns = self.line_columns(&lines[y]);
+            columns[cmp::min(x, columns.len() - 1)]
+        };
+        let (anchor, cursor) = (column_of(self.selection_anchor), column_of(self.cursor_index));
+        let right = match self.sticky_column {
+            Some((index, usize::MAX)) if index == self.cursor_index => usize::MAX,
+            _ => cmp::max(anchor, cursor),
+        };
+        (cmp::min(anchor, cursor), right)
+    }
+
+    // Moves the cursor one display row up or down, keeping its screen column.
+    fn move_by_display_row(&mut self, down: bool) {
+        let pos = self.get_cursor_position();
+        let lines = self.get_editor_lines();
+        let indent = self.settings.showbreak().chars().count();
+        let cursor_column = self.get_cursor_column();
+        let rows = self.display_rows(&lines[pos.y]);
+        let row = row_containing(&rows, cursor_column);
+        let screen_col = cursor_column - rows[row].0 + if row > 0 { indent } else { 0 };
+
+        let (target_y, target_rows, target_row) = if down {
+            if row + 1 < rows.len() {
+                (pos.y, rows, row + 1)
+            } else if pos.y + 1 < lines.len() {
+                (pos.y + 1, self.display_rows(&lines[pos.y + 1]), 0)
+            } else {
+                return;
+            }
+        } else {
+            if row > 0 {
+                (pos.y, rows, row - 1)
+            } else if pos.y > 0 {
+                let rows_above = self.display_rows(&lines[pos.y - 1]);
+                let last_row = rows_above.len() - 1;
+                (pos.y - 1, rows_above, last_row)
+            } else {
+                return;
+            }
+        };
+
+        let (start, end) = target_rows[target_row];
+        let col = screen_col.saturating_sub(if target_row > 0 { indent } else { 0 });
+        let column = start + cmp::min(col, cmp::max(end - start, 1) - 1);
+        let x = char_at_column(&self.line_columns(&lines[target_y]), column);
+        self.cursor_index = EditorState::line_start_index(&lines, target_y) + x;
+    }
 }
 
 
@@ -47,12 +614,117 @@ pub trait StateApi {
     fn dec_cursor(&mut self);
     fn cursor_line_down(&mut self);
     fn cursor_line_up(&mut self);
+    fn cursor_display_row_down(&mut self);
+    fn cursor_display_row_up(&mut self);
+    fn cursor_next_hunk(&mut self);
+    fn cursor_prev_hunk(&mut self);
+    fn insert_at_cursor(&mut self, text: &str);
+    fn insert_tab(&mut self);
+    fn delete_before_cursor(&mut self);
+    fn soft_backspace(&mut self);
+    fn shift_lines(&mut self, count: usize, right: bool);
     fn cursor_start_next_word(&mut self);
-    fn cursor_start_prev_word(&mut self);  // See note about common logic @ implementations
+    fn cursor_start_prev_word(&mut self);
+    fn cursor_end_of_word(&mut self);
+    fn cursor_end_of_prev_word(&mut self);
+    fn cursor_start_next_big_word(&mut self);
+    fn cursor_start_prev_big_word(&mut self);
+    fn cursor_end_of_big_word(&mut self);
+    fn cursor_end_of_prev_big_word(&mut self);
     fn cursor_start_of_line(&mut self);
+    fn cursor_first_non_blank(&mut self);
     fn cursor_end_of_line(&mut self);
-    // Add function for going to first match before, first match after index given a Predicate
-    // Add function for going to matching parens
+    fn cursor_to_line(&mut self, y: usize);
+    fn cursor_to_column(&mut self, column: usize);
+    fn cursor_next_sentence(&mut self);
+    fn cursor_prev_sentence(&mut self);
+    fn cursor_next_paragraph(&mut self);
+    fn cursor_prev_paragraph(&mut self);
+    fn cursor_matching_bracket(&mut self);
+    fn find_char(&mut self, search: CharSearch, count: usize, repeating: bool) -> bool;
+    fn first_match_after(&self, index: usize, predicate: &Fn(char) -> bool) -> Option<usize>;
+    fn first_match_before(&self, index: usize, predicate: &Fn(char) -> bool) -> Option<usize>;
+    fn text_object_range(&self, object: TextObject, count: usize) -> Option<TextRange>;
+}
+
+// A search for a char within the cursor line, as made by `f`, `F`, `t` and `T`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CharSearch {
+    pub target: char,
+    pub forward: bool,
+    pub till: bool,  // Stop next to the char, rather than on it
+}
+
+impl CharSearch {
+    // The same search in the other direction, for `,`
+    pub fn reversed(&self) -> Self {
+        CharSearch { forward: !self.forward, ..*self }
+    }
+}
+
+// Selections of the text around the cursor, for a verb to act on or for Visual
+// mode to select. The inner form of each (`iw`) leaves out the surrounding
+// whitespace or delimiters which the outer form (`aw`) takes in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextObject {
+    Word { around: bool, big: bool },  // `iw` and `aw`, or with `big`, the WORDs of `iW` and `aW`
+    Sentence { around: bool },
+    Paragraph { around: bool },
+    Quoted { quote: char, around: bool },
+    Bracketed { open: char, close: char, around: bool },
+    Tag { around: bool },  // An XML element, `it` and `at`
+    IndentBlock { around: bool },  // Lines indented at least as far as the cursor line, plus the one introducing them for `ai`
+}
+
+// A span of the text, from `start` up to but not including `end`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextRange {
+    pub start: usize,
+    pub end: usize,
+    pub linewise: bool,
+}
+
+// A selection made in one of the Visual modes, between the `anchor` and `cursor` indices
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Selection {
+    pub mode: Mode,
+    pub anchor: usize,
+    pub cursor: usize,
+}
+
+// The chars of one line inside a selection, as a [start, end) range within the
+// line. The end is past the last char when the line break is selected too.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SelectedSpan {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+// Where the text typed after a Visual-block `I` or `A` is copied to once Insert
+// mode is left.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlockInsert {
+    pub start: usize,  // Index of the first char typed, on the first line of the block
+    pub first_line: usize,
+    pub last_line: usize,
+    pub column: usize,  // Display column typed at, or usize::MAX for the end of each line
+    pub pad: bool,  // Whether lines too short to reach the column are padded with spaces
+}
+
+// The part of the text currently visible on screen, updated whenever the view is laid out.
+#[derive(Debug)]
+pub struct Viewport {
+    pub text_width: usize,  // Columns available for text, i.e. excluding the gutter
+    pub text_height: usize,
+    pub top_line: usize,  // Index of the first line on screen
+    pub left_column: usize,  // Horizontal scroll, only used with nowrap
+}
+
+impl Viewport {
+    pub fn new() -> Self {
+        Viewport { text_width: 0, text_height: 0, top_line: 0, left_column: 0 }
+    }
 }
 
 // Coordinates of the cursor WITHIN the text (not the screen),
@@ -87,23 +759,14 @@ impl StateApi for EditorState {
             .sum()
     }
 
+    // Lines are measured in chars, as the cursor index is. A cursor past the end of
+    // the text, as when typing at the end of a file without a final line break, is
+    // after the last char of the last line.
     fn get_cursor_position(&self) -> CursorPosition {
-        let mut cursor_pos = CursorPosition { x: 0, y: 0 };
         let lines = self.get_editor_lines();
-        let mut chars_seen = 0;
-        for (y, line) in lines.iter().enumerate() {
-            // Keep looking at lines, taking note of how many characters we look at
-            let line_len = line.len();
-            chars_seen += line_len;
-            // If we've seen beyond the cursor index
-            if chars_seen > self.cursor_index {
-                let start_of_line = chars_seen - line_len;
-                cursor_pos.x = self.cursor_index - start_of_line;
-                cursor_pos.y = y;
-                return cursor_pos;
-            }
-        }
-        cursor_pos
+        let y = line_and_offset(&lines, self.cursor_index).0;
+        let x = self.cursor_index.saturating_sub(EditorState::line_start_index(&lines, y));
+        CursorPosition { x, y }
     }
 
     fn set_cursor_index(&mut self, new_index: usize) {
@@ -115,7 +778,7 @@ impl StateApi for EditorState {
     }
 
     fn inc_cursor(&mut self) {
-        if self.cursor_index < self.get_file_length_in_chars() - 1 {
+        if self.cursor_index + 1 < self.get_file_length_in_chars() {
             self.cursor_index += 1;
         }
     }
@@ -127,117 +790,798 @@ impl StateApi for EditorState {
     }
 
     fn cursor_line_down(&mut self) {
-        let pos = self.get_cursor_position();
-        let (x, y) = (pos.x, pos.y);
-        let (num_lines, chars_left_on_line, next_line_len) = {
-            let lines = self.get_editor_lines();
-            let next_line_len = if y + 1 < lines.len() {
-                lines[y+1].len()
-            } else {
-                0
-            };
-            (lines.len(), lines[y].len() - x, next_line_len)
-        };
-        let is_last_line = num_lines > 0 &&  y == num_lines - 1;
-        if is_last_line {
-            self.cursor_to_eof();
-        } else {
-            let this_line_len = x + chars_left_on_line;
-            if x > next_line_len && next_line_len < this_line_len {
-                self.cursor_index += chars_left_on_line + next_line_len - 1;
-            } else {
-                self.cursor_index +=  chars_left_on_line + x;
-            }
+        let y = self.get_cursor_position().y;
+        if y + 1 < self.get_editor_lines().len() {
+            self.move_to_line_keeping_column(y + 1);
         }
     }
 
     fn cursor_line_up(&mut self) {
-        if self.cursor_index == 0 {
+        let y = self.get_cursor_position().y;
+        if y > 0 {
+            self.move_to_line_keeping_column(y - 1);
+        }
+    }
+
+    fn cursor_display_row_down(&mut self) {
+        if self.settings.wrap() {
+            self.move_by_display_row(true);
+        } else {
+            self.cursor_line_down();
+        }
+    }
+
+    fn cursor_display_row_up(&mut self) {
+        if self.settings.wrap() {
+            self.move_by_display_row(false);
+        } else {
+            self.cursor_line_up();
+        }
+    }
+
+    fn cursor_next_hunk(&mut self) {
+        let y = self.get_cursor_position().y;
+        if let Some(target) = self.hunk_lines().into_iter().find(|&line| line > y) {
+            self.cursor_index = EditorState::line_start_index(&self.get_editor_lines(), target);
+        }
+    }
+
+    fn cursor_prev_hunk(&mut self) {
+        let y = self.get_cursor_position().y;
+        if let Some(target) = self.hunk_lines().into_iter().filter(|&line| line < y).last() {
+            self.cursor_index = EditorState::line_start_index(&self.get_editor_lines(), target);
+        }
+    }
+
+    fn insert_at_cursor(&mut self, text: &str) {
+        let (index, length) = (self.cursor_index, text.chars().count());
+        self.piece_table.insert(index, text);
+        self.text_edited(index, 0, length);
+        self.cursor_index += length;
+    }
+
+    fn delete_before_cursor(&mut self) {
+        if self.cursor_index > 0 {
+            self.cursor_index -= 1;
+            let index = self.cursor_index;
+            self.piece_table.delete(index, 1);
+            self.text_edited(index, 1, 0);
+        }
+    }
+
+    // Inserts a tab, or the whitespace reaching the next stop when `expandtab` or
+    // `softtabstop` are set.
+    fn insert_tab(&mut self) {
+        let (tabstop, softtabstop, expandtab) = (self.settings.tabstop(), self.settings.softtabstop(), self.settings.expandtab());
+        if softtabstop == 0 && !expandtab {
+            self.insert_at_cursor("\t");
             return;
         }
-        
-        // The indices of newlines before the cursor position
-        let newline_indices: Vec<usize> = self.piece_table.iter()
-                                              .take(self.cursor_index)
-                                              .enumerate()
-                                              .filter(|&(_, ch)| ch == '\n')
-                                              .map(|(index, _)| index)
-                                              .collect();
-        
-        let num_newlines = newline_indices.len();
-        if num_newlines == 0 {
-            self.cursor_index = 0;
+
+        let step = if softtabstop > 0 { softtabstop } else { tabstop };
+        let column = self.get_cursor_column();
+        let target = (column / step + 1) * step;
+        if expandtab {
+            self.insert_at_cursor(&whitespace_between(column, target, tabstop, true));
             return;
-        } 
-        
-        let most_recent_newline = newline_indices.last().unwrap();
-        let x = self.cursor_index - most_recent_newline - 1;
-        if num_newlines == 1 {
-            // If we're on the second line
-            self.cursor_index = x;
-        } else {
-            let second_most_recent_newline = newline_indices[num_newlines - 2];
-            if x > most_recent_newline - second_most_recent_newline {
-                // If our x coord is greater than the length of the line above, go to end of above line
-                self.cursor_index -= x + 1;
-            } else {
-                // If there's characters to move to directly above the cursor, move to char directly above
-                self.cursor_index = second_most_recent_newline + x + 1;
+        }
+
+        // Without expandtab, spaces directly before the cursor are merged with the new
+        // whitespace, so that they become a tab once they reach a tab stop.
+        let pos = self.get_cursor_position();
+        let lines = self.get_editor_lines();
+        let chars: Vec<char> = lines[pos.y].chars().collect();
+        let columns = self.line_columns(&lines[pos.y]);
+        let run_start = pos.x - chars[..pos.x].iter().rev().take_while(|&&ch| ch == ' ').count();
+        let whitespace = whitespace_between(columns[run_start], target, tabstop, false);
+        let line_start = EditorState::line_start_index(&lines, pos.y);
+        self.replace_text(line_start + run_start, pos.x - run_start, &whitespace);
+        self.cursor_index = line_start + run_start + whitespace.chars().count();
+    }
+
+    // Backspace which, with `softtabstop`, removes whitespace back to the previous
+    // stop, re-rendering any tab it cuts through as spaces.
+    fn soft_backspace(&mut self) {
+        let softtabstop = self.settings.softtabstop();
+        let column = self.get_cursor_column();
+        if softtabstop == 0 || column == 0 {
+            self.delete_before_cursor();
+            return;
+        }
+
+        let pos = self.get_cursor_position();
+        let lines = self.get_editor_lines();
+        let chars: Vec<char> = lines[pos.y].chars().collect();
+        let columns = self.line_columns(&lines[pos.y]);
+        let run_start = pos.x - chars[..pos.x].iter().rev().take_while(|&&ch| ch == ' ' || ch == '\t').count();
+        let target = (column - 1) / softtabstop * softtabstop;
+        if run_start == pos.x || columns[run_start] > target {
+            self.delete_before_cursor();
+            return;
+        }
+
+        let whitespace = whitespace_between(columns[run_start], target, self.settings.tabstop(), self.settings.expandtab());
+        let line_start = EditorState::line_start_index(&lines, pos.y);
+        self.replace_text(line_start + run_start, pos.x - run_start, &whitespace);
+        self.cursor_index = line_start + run_start + whitespace.chars().count();
+    }
+
+    // Shifts `count` lines from the cursor line by one `shiftwidth`, leaving the
+    // cursor on the first non-blank of the first line. Empty lines are left alone.
+    fn shift_lines(&mut self, count: usize, right: bool) {
+        let shiftwidth = self.settings.effective_shiftwidth();
+        let first_line = self.get_cursor_position().y;
+        let lines = self.get_editor_lines();
+        let last_line = cmp::min(first_line + count, lines.len());
+
+        for y in (first_line..last_line).rev() {
+            let chars: Vec<char> = lines[y].trim_right_matches('\n').chars().collect();
+            if chars.is_empty() {
+                continue;
             }
+            let indent_length = chars.iter().take_while(|&&ch| ch == ' ' || ch == '\t').count();
+            let indent_width = self.line_columns(&lines[y])[indent_length];
+            let new_width = if right { indent_width + shiftwidth } else { indent_width.saturating_sub(shiftwidth) };
+            let indent = whitespace_between(0, new_width, self.settings.tabstop(), self.settings.expandtab());
+            self.replace_text(EditorState::line_start_index(&lines, y), indent_length, &indent);
         }
+
+        let lines = self.get_editor_lines();
+        let first_non_blank = lines[first_line].chars().take_while(|&ch| ch == ' ' || ch == '\t').count();
+        self.cursor_index = EditorState::line_start_index(&lines, first_line) + first_non_blank;
     }
 
     fn cursor_start_next_word(&mut self) {
-        let increment_by = self.piece_table.iter()
-                                           .skip(self.cursor_index)
-                                           .take_while(|ch| !ch.is_whitespace())
-                                           .count() + 1;
-                                           
-        let last_index = self.get_file_length_in_chars() - 1;
-        let indices_to_eof = last_index - self.cursor_index;
-        self.cursor_index += cmp::min(increment_by, indices_to_eof);
-    }
-
-    // TODO: Extract logic from following 2 functions into a new function that takes
-    // a Predicate argument (FnMut(Self::Item) -> Option<B>), and finds the first match
-    // of the predicate before the cursor index. Do the same for the first match after
-    // the cursor index.
+        self.move_by_word(next_word_start, false);
+    }
+
     fn cursor_start_prev_word(&mut self) {
-        let chars_to_take = if self.cursor_index > 0 { self.cursor_index - 1 } else { 0 };
-        self.cursor_index = self.piece_table.iter()
-                                            .take(chars_to_take)
-                                            .enumerate()
-                                            .filter(|&(_, ch)| ch.is_whitespace())
-                                            .last()
-                                            .map(|(whitespace_idx, _)| whitespace_idx + 1)
-                                            .unwrap_or(0);
+        self.move_by_word(prev_word_start, false);
+    }
+
+    fn cursor_end_of_word(&mut self) {
+        self.move_by_word(word_end, false);
+    }
+
+    fn cursor_end_of_prev_word(&mut self) {
+        self.move_by_word(prev_word_end, false);
+    }
+
+    fn cursor_start_next_big_word(&mut self) {
+        self.move_by_word(next_word_start, true);
+    }
+
+    fn cursor_start_prev_big_word(&mut self) {
+        self.move_by_word(prev_word_start, true);
+    }
+
+    fn cursor_end_of_big_word(&mut self) {
+        self.move_by_word(word_end, true);
+    }
+
+    fn cursor_end_of_prev_big_word(&mut self) {
+        self.move_by_word(prev_word_end, true);
     }
 
     fn cursor_start_of_line(&mut self) {
-        let chars_to_take = if self.cursor_index > 0 { self.cursor_index - 1 } else { 0 };
-        self.piece_table.iter()
-                        .take(chars_to_take)
-                        .enumerate()
-                        .filter(|&(_, ch)| ch == '\n')
-                        .last()
-                        .map(|(newline_idx, _)| newline_idx + 1)
-                        .unwrap_or(0);
+        self.cursor_index = self.first_match_before(self.cursor_index, &|ch| ch == '\n')
+                                .map(|newline_idx| newline_idx + 1)
+                                .unwrap_or(0);
     }
 
+    fn cursor_first_non_blank(&mut self) {
+        let y = self.get_cursor_position().y;
+        self.cursor_to_line(y);
+    }
+
+    // Moves onto the last char of the line, or the line break of an empty one, and
+    // has `j` and `k` keep to the end of the line
     fn cursor_end_of_line(&mut self) {
-        self.cursor_index += self.piece_table.iter()
-                                             .skip(self.cursor_index)
-                                             .take_while(|ch| *ch != '\n')
-                                             .count();
+        let y = self.get_cursor_position().y;
+        let lines = self.get_editor_lines();
+        if lines.is_empty() {
+            return;
+        }
+        let y = cmp::min(y, lines.len() - 1);
+        self.cursor_index = EditorState::line_start_index(&lines, y) + last_char_of_line(&lines[y]);
+        self.sticky_column = Some((self.cursor_index, usize::MAX));
+    }
+
+    // Moves to the first non-blank of the line, or the last line if there aren't that many
+    fn cursor_to_line(&mut self, y: usize) {
+        let lines = self.get_editor_lines();
+        if lines.is_empty() {
+            return;
+        }
+        let y = cmp::min(y, lines.len() - 1);
+        let first_non_blank = lines[y].chars().take_while(|&ch| ch == ' ' || ch == '\t').count();
+        self.cursor_index = EditorState::line_start_index(&lines, y) + first_non_blank;
     }
 
+    // Moves to the char at the display column of the cursor line, or the last one
+    fn cursor_to_column(&mut self, column: usize) {
+        let lines = self.get_editor_lines();
+        if lines.is_empty() {
+            return;
+        }
+        let y = cmp::min(self.get_cursor_position().y, lines.len() - 1);
+        let x = char_at_column(&self.line_columns(&lines[y]), column);
+        self.cursor_index = EditorState::line_start_index(&lines, y) + x;
+    }
+
+    fn cursor_next_sentence(&mut self) {
+        let text: Vec<char> = self.get_text().chars().collect();
+        let cursor = self.cursor_index;
+        self.cursor_index = sentences(&text).into_iter()
+                                            .map(|(start, _)| start)
+                                            .find(|&start| start > cursor)
+                                            .unwrap_or(text.len().saturating_sub(1));
+    }
+
+    fn cursor_prev_sentence(&mut self) {
+        let text: Vec<char> = self.get_text().chars().collect();
+        let cursor = self.cursor_index;
+        self.cursor_index = sentences(&text).into_iter()
+                                            .map(|(start, _)| start)
+                                            .filter(|&start| start < cursor)
+                                            .last()
+                                            .unwrap_or(0);
+    }
+
+    // Moves to the next empty line after a paragraph, or the end of the text
+    fn cursor_next_paragraph(&mut self) {
+        let lines = self.get_editor_lines();
+        let empty = |y: usize| lines[y] == "\n";
+        let mut y = self.get_cursor_position().y;
+        while y < lines.len() && empty(y) {
+            y += 1;
+        }
+        while y < lines.len() && !empty(y) {
+            y += 1;
+        }
+        self.cursor_index = if y < lines.len() {
+            EditorState::line_start_index(&lines, y)
+        } else {
+            self.get_file_length_in_chars().saturating_sub(1)
+        };
+    }
+
+    fn cursor_prev_paragraph(&mut self) {
+        let lines = self.get_editor_lines();
+        let empty = |y: usize| lines[y] == "\n";
+        let mut y = self.get_cursor_position().y;
+        while y > 0 && empty(y) {
+            y -= 1;
+        }
+        while y > 0 && !empty(y) {
+            y -= 1;
+        }
+        self.cursor_index = EditorState::line_start_index(&lines, y);
+    }
+
+    // Jumps from the next bracket on the line, from the cursor on, to its match
+    fn cursor_matching_bracket(&mut self) {
+        let text: Vec<char> = self.get_text().chars().collect();
+        let pairs = [('(', ')'), ('[', ']'), ('{', '}')];
+        let bracket = text[self.cursor_index..].iter()
+                                               .take_while(|&&ch| ch != '\n')
+                                               .position(|&ch| pairs.iter().any(|&(open, close)| ch == open || ch == close))
+                                               .map(|offset| self.cursor_index + offset);
+        let index = match bracket {
+            Some(index) => index,
+            None => return,
+        };
+
+        let (open, close) = *pairs.iter().find(|&&(open, close)| text[index] == open || text[index] == close).unwrap();
+        let forward = text[index] == open;
+        let mut depth = 0;
+        let mut position = index;
+        loop {
+            if forward {
+                position += 1;
+                if position >= text.len() {
+                    return;
+                }
+            } else {
+                if position == 0 {
+                    return;
+                }
+                position -= 1;
+            }
+            if text[position] == text[index] {
+                depth += 1;
+            } else if text[position] == open || text[position] == close {
+                if depth == 0 {
+                    self.cursor_index = position;
+                    return;
+                }
+                depth -= 1;
+            }
+        }
+    }
+
+    // Moves to the `count`th occurrence of the char in the search's direction, without
+    // leaving the line. Returns false, leaving the cursor where it is, if there
+    // aren't that many.
+    fn find_char(&mut self, search: CharSearch, count: usize, repeating: bool) -> bool {
+        let target = search.target;
+        let text: Vec<char> = self.get_text().chars().collect();
+        let char_at = |index: usize| text.get(index).cloned();
+        let neighbour = if search.forward { Some(self.cursor_index + 1) } else { self.cursor_index.checked_sub(1) };
+
+        // Repeating a `t` search from next to the char would find the same one again,
+        // so it's stepped over
+        let mut index = self.cursor_index;
+        if search.till && repeating && neighbour.and_then(&char_at) == Some(target) {
+            index = neighbour.unwrap();
+        }
+
+        let target_or_newline = |ch| ch == target || ch == '\n';
+        for _ in 0..cmp::max(count, 1) {
+            let found = if search.forward {
+                self.first_match_after(index, &target_or_newline)
+            } else {
+                self.first_match_before(index, &target_or_newline)
+            };
+            match found {
+                Some(found) if char_at(found) == Some(target) => index = found,
+                _ => return false,
+            }
+        }
+
+        self.cursor_index = match (search.till, search.forward) {
+            (true, true) => index - 1,
+            (true, false) => index + 1,
+            (false, _) => index,
+        };
+        true
+    }
+
+    // The index of the first char after `index` which satisfies the predicate
+    fn first_match_after(&self, index: usize, predicate: &Fn(char) -> bool) -> Option<usize> {
+        self.get_text().chars()
+                       .enumerate()
+                       .skip(index + 1)
+                       .find(|&(_, ch)| predicate(ch))
+                       .map(|(found, _)| found)
+    }
+
+    // The index of the last char before `index` which satisfies the predicate
+    fn first_match_before(&self, index: usize, predicate: &Fn(char) -> bool) -> Option<usize> {
+        self.get_text().chars()
+                       .take(index)
+                       .enumerate()
+                       .filter(|&(_, ch)| predicate(ch))
+                       .last()
+                       .map(|(found, _)| found)
+    }
+
+    fn text_object_range(&self, object: TextObject, count: usize) -> Option<TextRange> {
+        let text: Vec<char> = self.get_text().chars().collect();
+        let cursor = self.cursor_index;
+        if cursor >= text.len() {
+            return None;
+        }
+        let count = cmp::max(count, 1);
+        let lines = self.get_editor_lines();
+        let y = self.get_cursor_position().y;
+
+        let char_range = |(start, end): (usize, usize)| TextRange { start, end, linewise: false };
+        let line_range = |(first, last): (usize, usize)| TextRange {
+            start: EditorState::line_start_index(&lines, first),
+            end: cmp::min(EditorState::line_start_index(&lines, last), text.len()),
+            linewise: true,
+        };
+        match object {
+            TextObject::Word { around, big } => word_object(&text, cursor, count, around, big).map(char_range),
+            TextObject::Sentence { around } => sentence_object(&text, cursor, count, around).map(char_range),
+            TextObject::Paragraph { around } => paragraph_object(&lines, y, count, around).map(line_range),
+            TextObject::Quoted { quote, around } => quoted_object(&text, cursor, quote, around).map(char_range),
+            TextObject::Bracketed { open, close, around } => bracketed_object(&text, cursor, open, close, count, around).map(char_range),
+            TextObject::Tag { around } => tag_object(&text, cursor, count, around).map(char_range),
+            TextObject::IndentBlock { around } => indent_block(&lines, y, count, around, self.settings.tabstop()).map(line_range),
+        }
+    }
+
+}
+
+// The line containing the char at `index`, and the offset of the char within it
+pub fn line_and_offset(lines: &[String], index: usize) -> (usize, usize) {
+    let mut line_start = 0;
+    for (y, line) in lines.iter().enumerate() {
+        let length = line.chars().count();
+        if index < line_start + length || y == lines.len() - 1 {
+            return (y, cmp::min(index - line_start, length.saturating_sub(1)));
+        }
+        line_start += length;
+    }
+    (0, 0)
 }
 
-#[derive(Eq, PartialEq, Hash, Debug)]
+// The offset of the last char of a line before its line break, which is where `$`
+// stops, or 0 on an empty line
+fn last_char_of_line(line: &str) -> usize {
+    line.chars().count().saturating_sub(2)
+}
+
+// Chars which make up words: whitespace, keyword chars and other punctuation. For
+// WORDs, everything but whitespace is alike.
+pub fn char_class(ch: char, big: bool) -> u8 {
+    if ch.is_whitespace() {
+        0
+    } else if big || ch.is_alphanumeric() || ch == '_' {
+        1
+    } else {
+        2
+    }
+}
+
+// The run of chars, within the line, of the same class as the one at `index`
+fn class_run(text: &[char], index: usize, big: bool) -> (usize, usize) {
+    let class = char_class(text[index], big);
+    let same = |ch: &&char| **ch != '\n' && char_class(**ch, big) == class;
+    let start = index - text[..index].iter().rev().take_while(same).count();
+    let end = index + text[index..].iter().take_while(same).count();
+    (start, end)
+}
+
+// An empty line is taken as a word by `w` and `b`, so they stop at it
+fn is_empty_line(text: &[char], index: usize) -> bool {
+    text[index] == '\n' && (index == 0 || text[index - 1] == '\n')
+}
+
+// Where `w` goes: the start of the next word
+fn next_word_start(text: &[char], index: usize, big: bool) -> usize {
+    let mut index = index;
+    let class = char_class(text[index], big);
+    if class != 0 {
+        while index < text.len() && text[index] != '\n' && char_class(text[index], big) == class {
+            index += 1;
+        }
+    }
+    while index < text.len() && char_class(text[index], big) == 0 {
+        index += 1;
+        if index < text.len() && is_empty_line(text, index) {
+            break;
+        }
+    }
+    cmp::min(index, text.len() - 1)
+}
+
+// Where `b` goes: the start of this word, or the one before if already there
+fn prev_word_start(text: &[char], index: usize, big: bool) -> usize {
+    if index == 0 {
+        return 0;
+    }
+    let mut index = index - 1;
+    while index > 0 && char_class(text[index], big) == 0 && !is_empty_line(text, index) {
+        index -= 1;
+    }
+    let class = char_class(text[index], big);
+    while class != 0 && index > 0 && text[index - 1] != '\n' && char_class(text[index - 1], big) == class {
+        index -= 1;
+    }
+    index
+}
+
+// Where `e` goes: the end of this word, or the one after if already there
+fn word_end(text: &[char], index: usize, big: bool) -> usize {
+    let mut index = index + 1;
+    while index < text.len() && char_class(text[index], big) == 0 {
+        index += 1;
+    }
+    if index >= text.len() {
+        return text.len() - 1;
+    }
+    let class = char_class(text[index], big);
+    while index + 1 < text.len() && text[index + 1] != '\n' && char_class(text[index + 1], big) == class {
+        index += 1;
+    }
+    index
+}
+
+// Where `ge` goes: the end of the word before this one
+fn prev_word_end(text: &[char], index: usize, big: bool) -> usize {
+    let mut index = index;
+    let class = char_class(text[index], big);
+    while class != 0 && index > 0 && text[index - 1] != '\n' && char_class(text[index - 1], big) == class {
+        index -= 1;
+    }
+    if index == 0 {
+        return 0;
+    }
+    index -= 1;
+    while index > 0 && char_class(text[index], big) == 0 && !is_empty_line(text, index) {
+        index -= 1;
+    }
+    index
+}
+
+fn is_blank(ch: char) -> bool {
+    ch == ' ' || ch == '\t'
+}
+
+// Widens the range to take in the blanks after it or, without any, those before it.
+fn with_surrounding_blanks(text: &[char], (start, end): (usize, usize)) -> (usize, usize) {
+    let after = text[end..].iter().take_while(|&&ch| is_blank(ch)).count();
+    if after > 0 {
+        return (start, end + after);
+    }
+    let before = text[..start].iter().rev().take_while(|&&ch| is_blank(ch)).count();
+    (start - before, end)
+}
+
+// Each count takes in another run of word chars or whitespace. The outer form
+// pairs each word with the whitespace after it, or when starting on whitespace,
+// each run of whitespace with the word after it.
+fn word_object(text: &[char], cursor: usize, count: usize, around: bool, big: bool) -> Option<(usize, usize)> {
+    let in_line = |index: usize| index < text.len() && text[index] != '\n';
+    if !in_line(cursor) {
+        return None;
+    }
+    let next_run = |index: usize| if in_line(index) { class_run(text, index, big).1 } else { index };
+    let is_space = |index: usize| char_class(text[index], big) == 0;
+
+    let (mut start, mut end) = class_run(text, cursor, big);
+    if !around {
+        for _ in 1..count {
+            end = next_run(end);
+        }
+        return Some((start, end));
+    }
+
+    let on_space = is_space(cursor);
+    for number in 0..count {
+        if number > 0 {
+            end = next_run(end);
+        }
+        if on_space || (in_line(end) && is_space(end)) {
+            end = next_run(end);
+        }
+    }
+    // Without whitespace after the words, the whitespace before them is taken instead
+    if !on_space && !is_space(end - 1) && start > 0 && in_line(start - 1) && is_space(start - 1) {
+        start = class_run(text, start - 1, big).0;
+    }
+    Some((start, end))
+}
+
+// Every sentence in the text. A sentence ends with `.`, `!` or `?`, optionally
+// followed by closing brackets or quotes, then whitespace. Paragraphs end them too.
+fn sentences(text: &[char]) -> Vec<(usize, usize)> {
+    let mut spans = vec![];
+    let mut index = 0;
+    loop {
+        index += text[index..].iter().take_while(|ch| ch.is_whitespace()).count();
+        if index >= text.len() {
+            return spans;
+        }
+        let start = index;
+        let end = loop {
+            if index >= text.len() {
+                break text.len();
+            }
+            let ch = text[index];
+            if ch == '\n' && (index + 1 == text.len() || text[index + 1] == '\n') {
+                break index;
+            }
+            index += 1;
+            if ch == '.' || ch == '!' || ch == '?' {
+                index += text[index..].iter().take_while(|&&ch| ch == ')' || ch == ']' || ch == '"' || ch == '\'').count();
+                if index == text.len() || text[index].is_whitespace() {
+                    break index;
+                }
+            }
+        };
+        spans.push((start, end));
+        index = end;
+    }
+}
+
+fn sentence_object(text: &[char], cursor: usize, count: usize, around: bool) -> Option<(usize, usize)> {
+    let spans = sentences(text);
+    let first = spans.iter().rposition(|&(start, _)| start <= cursor).unwrap_or(0);
+    let last = cmp::min(first + count, spans.len()).checked_sub(1)?;
+    let range = (spans.get(first)?.0, spans[last].1);
+    Some(if around { with_surrounding_blanks(text, range) } else { range })
+}
+
+// Paragraphs are runs of non-blank lines, separated by runs of blank ones. Each
+// count takes in another run, with the outer form pairing them up as `aw` does.
+fn paragraph_object(lines: &[String], y: usize, count: usize, around: bool) -> Option<(usize, usize)> {
+    if y >= lines.len() {
+        return None;
+    }
+    let blank = |y: usize| lines[y].trim().is_empty();
+    let run_end = |y: usize| if y < lines.len() { (y..lines.len()).find(|&end| blank(end) != blank(y)).unwrap_or(lines.len()) } else { y };
+
+    let mut first = (0..y).rev().find(|&start| blank(start) != blank(y)).map_or(0, |start| start + 1);
+    let mut last = run_end(y);
+    if !around {
+        for _ in 1..count {
+            last = run_end(last);
+        }
+        return Some((first, last));
+    }
+
+    let on_blank = blank(y);
+    for number in 0..count {
+        if number > 0 {
+            last = run_end(last);
+        }
+        if on_blank || (last < lines.len() && blank(last)) {
+            last = run_end(last);
+        }
+    }
+    // Without blank lines after the paragraphs, those before them are taken instead
+    if !on_blank && !blank(last - 1) {
+        first = (0..first).rev().find(|&start| !blank(start)).map_or(0, |start| start + 1);
+    }
+    Some((first, last))
+}
+
+// Quotes pair up from the start of the line, ignoring any escaped with a
+// backslash. Before the first pair, the cursor selects the next one on the line.
+fn quoted_object(text: &[char], cursor: usize, quote: char, around: bool) -> Option<(usize, usize)> {
+    let line_start = text[..cursor].iter().rposition(|&ch| ch == '\n').map_or(0, |newline| newline + 1);
+    let line_end = cursor + text[cursor..].iter().take_while(|&&ch| ch != '\n').count();
+    let quotes: Vec<usize> = (line_start..line_end)
+        .filter(|&index| text[index] == quote && (index == line_start || text[index - 1] != '\\'))
+        .collect();
+    let (open, close) = quotes.chunks(2)
+                              .filter(|pair| pair.len() == 2)
+                              .map(|pair| (pair[0], pair[1]))
+                              .find(|&(_, close)| cursor <= close)?;
+    Some(if around { with_surrounding_blanks(text, (open, close + 1)) } else { (open + 1, close) })
+}
+
+// The `count`th pair of brackets enclosing the cursor, or those the cursor is on.
+fn bracketed_object(text: &[char], cursor: usize, open: char, close: char, count: usize, around: bool) -> Option<(usize, usize)> {
+    let mut depth = 0;
+    let mut found = 0;
+    let mut open_index = None;
+    for index in (0..cursor + 1).rev() {
+        if text[index] == close && index != cursor {
+            depth += 1;
+        } else if text[index] == open {
+            if depth == 0 {
+                found += 1;
+                if found == count {
+                    open_index = Some(index);
+                    break;
+                }
+            } else {
+                depth -= 1;
+            }
+        }
+    }
+    let open_index = open_index?;
+
+    let mut depth = 0;
+    let mut close_index = None;
+    for index in open_index + 1..text.len() {
+        if text[index] == open {
+            depth += 1;
+        } else if text[index] == close {
+            if depth == 0 {
+                close_index = Some(index);
+                break;
+            }
+            depth -= 1;
+        }
+    }
+    let close_index = close_index?;
+
+    if around {
+        return Some((open_index, close_index + 1));
+    }
+    // When the brackets are on lines of their own, the inner form is just the lines between
+    let mut start = open_index + 1;
+    if text.get(start) == Some(&'\n') {
+        start += 1;
+    }
+    let close_line_start = close_index - text[..close_index].iter().rev().take_while(|&&ch| is_blank(ch)).count();
+    let end = if close_line_start > start && text[close_line_start - 1] == '\n' { close_line_start } else { close_index };
+    Some((start, cmp::max(start, end)))
+}
+
+// The `count`th XML element enclosing the cursor. Tags are matched by name, so
+// that unclosed ones (like HTML's `<br>`) are skipped over.
+fn tag_object(text: &[char], cursor: usize, count: usize, around: bool) -> Option<(usize, usize)> {
+    let mut open_tags: Vec<(String, usize, usize)> = vec![];
+    let mut elements = vec![];  // The start and end of each element's opening and closing tags
+    let mut index = 0;
+    while index < text.len() {
+        if text[index] != '<' {
+            index += 1;
+            continue;
+        }
+        let end = match text[index..].iter().position(|&ch| ch == '>') {
+            Some(length) => index + length + 1,
+            None => break,
+        };
+        let inside: String = text[index + 1..end - 1].iter().collect();
+        let name: String = inside.trim_left_matches('/')
+                                 .chars()
+                                 .take_while(|&ch| ch.is_alphanumeric() || ch == '_' || ch == ':' || ch == '-' || ch == '.')
+                                 .collect();
+        // Comments, declarations and self-closing tags don't enclose anything
+        if !name.is_empty() && !inside.ends_with('/') {
+            if !inside.starts_with('/') {
+                open_tags.push((name, index, end));
+            } else if let Some(position) = open_tags.iter().rposition(|tag| tag.0 == name) {
+                elements.push((open_tags[position].1, open_tags[position].2, index, end));
+                open_tags.truncate(position);
+            }
+        }
+        index = end;
+    }
+
+    let mut enclosing: Vec<(usize, usize, usize, usize)> = elements.into_iter()
+        .filter(|&(open_start, _, _, close_end)| open_start <= cursor && cursor < close_end)
+        .collect();
+    enclosing.sort_by_key(|&(open_start, _, _, close_end)| close_end - open_start);
+    let &(open_start, open_end, close_start, close_end) = enclosing.get(count - 1)?;
+    Some(if around { (open_start, close_end) } else { (open_end, close_start) })
+}
+
+// The lines around the cursor line indented at least as far as it, like a Python
+// block. Blank lines inside the block are included, and a blank cursor line goes
+// with the block below it. Each count moves out to the enclosing block.
+fn indent_block(lines: &[String], y: usize, count: usize, around: bool, tabstop: usize) -> Option<(usize, usize)> {
+    let blank = |y: usize| lines[y].trim().is_empty();
+    let indent = |y: usize| {
+        let chars: Vec<char> = lines[y].trim_right_matches('\n').chars().collect();
+        char_columns(&chars, tabstop)[chars.iter().take_while(|&&ch| is_blank(ch)).count()]
+    };
+
+    let mut y = (y..lines.len()).find(|&y| !blank(y))?;
+    let (mut first, mut last) = (y, y);
+    for number in 0..count {
+        if number > 0 {
+            y = match (0..first).rev().find(|&y| !blank(y)) {
+                Some(header) => header,
+                None => break,
+            };
+        }
+        let width = indent(y);
+        first = y;
+        while first > 0 && (blank(first - 1) || indent(first - 1) >= width) {
+            first -= 1;
+        }
+        last = y;
+        while last + 1 < lines.len() && (blank(last + 1) || indent(last + 1) >= width) {
+            last += 1;
+        }
+        while blank(first) {
+            first += 1;
+        }
+        while blank(last) {
+            last -= 1;
+        }
+    }
+    if around && first > 0 {
+        first = (0..first).rev().find(|&y| !blank(y)).unwrap_or(first);
+    }
+    Some((first, last + 1))
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
 pub enum Mode {
     Navigate,  // Designed for quick navigation of documents.
     Command,  // Press ':' to enter this mode, enter string to perform command.
     Insert,  // Press 'i' while in Navigate mode to enter insert mode, for updating files. 
+    Visual,  // Press 'v' to select chars, from where it was pressed to the cursor.
+    VisualLine,  // Press 'V' to select whole lines.
+    VisualBlock,  // Press Ctrl-v to select the same columns of each line.
+}
+
+pub const VISUAL_MODES: &[Mode] = &[Mode::Visual, Mode::VisualLine, Mode::VisualBlock];
+
+impl Mode {
+    pub fn is_visual(&self) -> bool {
+        VISUAL_MODES.contains(self)
+    }
 }
 
 impl fmt::Display for Mode {
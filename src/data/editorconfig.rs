@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use data::settings::{Settings, OptionValue};
+
+// Support for `.editorconfig` files (https://editorconfig.org). The files are
+// looked for from the edited file's directory upwards, stopping at one marked
+// `root = true`. Files nearer the edited file take precedence, as do later
+// sections within a file.
+
+struct EditorConfigFile {
+    directory: PathBuf,
+    root: bool,
+    sections: Vec<(String, Vec<(String, String)>)>,  // Glob, then the properties in order
+}
+
+// The properties which apply to the file at `path`, with keys lowercased.
+pub fn properties_for(path: &Path) -> Result<HashMap<String, String>, String> {
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        env::current_dir().map_err(|error| error.to_string())?.join(path)
+    };
+
+    let mut files = vec![];
+    let mut directory = path.parent();
+    while let Some(dir) = directory {
+        let config_path = dir.join(".editorconfig");
+        if config_path.is_file() {
+            let mut text = String::new();
+            File::open(&config_path).and_then(|mut file| file.read_to_string(&mut text))
+                                    .map_err(|error| format!("{}: {}", config_path.display(), error))?;
+            let file = parse(&text, dir.to_path_buf());
+            let root = file.root;
+            files.push(file);
+            if root {
+                break;
+            }
+        }
+        directory = dir.parent();
+    }
+
+    let mut properties = HashMap::new();
+    for file in files.iter().rev() {
+        let relative_path = match path.strip_prefix(&file.directory) {
+            Ok(relative_path) => relative_path.to_string_lossy().into_owned(),
+            Err(_) => continue,
+        };
+        let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+
+        for &(ref glob, ref section) in file.sections.iter() {
+            // Globs without a slash match the file name in any directory, the rest
+            // are relative to the directory of the .editorconfig
+            let matched = if glob.contains('/') {
+                glob_matches(glob.trim_left_matches('/'), &relative_path)
+            } else {
+                glob_matches(glob, &file_name)
+            };
+            if matched {
+                for &(ref key, ref value) in section.iter() {
+                    properties.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+    Ok(properties)
+}
+
+fn parse(text: &str, directory: PathBuf) -> EditorConfigFile {
+    let mut file = EditorConfigFile { directory, root: false, sections: vec![] };
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if line.starts_with('[') && line.ends_with(']') {
+            file.sections.push((line[1..line.len() - 1].to_string(), vec![]));
+            continue;
+        }
+
+        if let Some(eq_index) = line.find('=') {
+            let key = line[..eq_index].trim().to_lowercase();
+            let value = line[eq_index + 1..].trim().to_string();
+            match file.sections.last_mut() {
+                Some(&mut (_, ref mut section)) => section.push((key, value)),
+                None if key == "root" => file.root = value.to_lowercase() == "true",
+                None => (),
+            }
+        }
+    }
+    file
+}
+
+// Matches a path against an EditorConfig glob: `*` matches anything but a slash,
+// `**` anything at all, `?` any one char, `[abc]`/`[!abc]` a set of chars,
+// `{a,b}` any of the alternatives and `{1..3}` a number in the range.
+pub fn glob_matches(glob: &str, path: &str) -> bool {
+    let path: Vec<char> = path.chars().collect();
+    expand_braces(glob).iter().any(|pattern| {
+        let pattern: Vec<char> = pattern.chars().collect();
+        matches_from(&pattern, &path)
+    })
+}
+
+// Above this many alternatives a glob is treated as matching nothing, rather
+// than being expanded.
+const MAX_EXPANSIONS: usize = 1024;
+
+// Expands the first brace group in the glob, recursively. Braces without a comma
+// or range inside are kept as they are.
+fn expand_braces(glob: &str) -> Vec<String> {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut index = 0;
+    while index < chars.len() {
+        match chars[index] {
+            '\\' => index += 2,
+            '{' => {
+                let mut depth = 0;
+                let mut close = None;
+                let mut separators = vec![];
+                let mut inner = index + 1;
+                while inner < chars.len() {
+                    match chars[inner] {
+                        '\\' => inner += 1,
+                        '{' => depth += 1,
+                        '}' if depth == 0 => {
+                            close = Some(inner);
+                            break;
+                        },
+                        '}' => depth -= 1,
+                        ',' if depth == 0 => separators.push(inner),
+                        _ => (),
+                    }
+                    inner += 1;
+                }
+
+                let close = match close {
+                    Some(close) => close,
+                    None => break,
+                };
+                let prefix: String = chars[..index].iter().collect();
+                let suffix: String = chars[close + 1..].iter().collect();
+                let body: String = chars[index + 1..close].iter().collect();
+
+                let alternatives: Vec<String> = if separators.is_empty() {
+                    match numeric_range(&body) {
+                        Some((low, high)) if high - low < MAX_EXPANSIONS as i64 => (low..high + 1).map(|number| number.to_string()).collect(),
+                        Some(_) => return vec![],
+                        None => vec![format!("\\{{{}\\}}", body)],
+                    }
+                } else {
+                    let mut bounds = vec![index];
+                    bounds.extend(separators);
+                    bounds.push(close);
+                    bounds.windows(2).map(|pair| chars[pair[0] + 1..pair[1]].iter().collect()).collect()
+                };
+
+                let mut expanded = vec![];
+                for alternative in alternatives {
+                    for rest in expand_braces(&format!("{}{}", alternative, suffix)) {
+                        expanded.push(format!("{}{}", prefix, rest));
+                        if expanded.len() > MAX_EXPANSIONS {
+                            return vec![];
+                        }
+                    }
+                }
+                return expanded;
+            },
+            _ => index += 1,
+        }
+    }
+    vec![glob.to_string()]
+}
+
+fn numeric_range(body: &str) -> Option<(i64, i64)> {
+    let dots = body.find("..")?;
+    let low = body[..dots].parse::<i64>().ok()?;
+    let high = body[dots + 2..].parse::<i64>().ok()?;
+    if low <= high { Some((low, high)) } else { None }
+}
+
+fn matches_from(pattern: &[char], path: &[char]) -> bool {
+    if pattern.is_empty() {
+        return path.is_empty();
+    }
+
+    match pattern[0] {
+        '*' if pattern.get(1) == Some(&'*') => {
+            (0..path.len() + 1).any(|skip| matches_from(&pattern[2..], &path[skip..]))
+        },
+        '*' => {
+            let segment_end = path.iter().position(|&ch| ch == '/').unwrap_or(path.len());
+            (0..segment_end + 1).any(|skip| matches_from(&pattern[1..], &path[skip..]))
+        },
+        '?' => !path.is_empty() && path[0] != '/' && matches_from(&pattern[1..], &path[1..]),
+        '[' => match pattern.iter().position(|&ch| ch == ']') {
+            Some(close) if close > 1 && !path.is_empty() => {
+                let (negated, set) = if pattern[1] == '!' { (true, &pattern[2..close]) } else { (false, &pattern[1..close]) };
+                in_set(set, path[0]) != negated && path[0] != '/' && matches_from(&pattern[close + 1..], &path[1..])
+            },
+            Some(_) => false,
+            None => !path.is_empty() && path[0] == '[' && matches_from(&pattern[1..], &path[1..]),
+        },
+        '\\' if pattern.len() > 1 => !path.is_empty() && path[0] == pattern[1] && matches_from(&pattern[2..], &path[1..]),
+        literal => !path.is_empty() && path[0] == literal && matches_from(&pattern[1..], &path[1..]),
+    }
+}
+
+fn in_set(set: &[char], ch: char) -> bool {
+    let mut index = 0;
+    while index < set.len() {
+        if index + 2 < set.len() && set[index + 1] == '-' {
+            if set[index] <= ch && ch <= set[index + 2] {
+                return true;
+            }
+            index += 3;
+        } else {
+            if set[index] == ch {
+                return true;
+            }
+            index += 1;
+        }
+    }
+    false
+}
+
+// Applies the properties to the buffer's local settings, returning a message for
+// each property which couldn't be used. Properties set to `unset`, and ones this
+// editor doesn't know about, are ignored.
+pub fn apply_properties(properties: &HashMap<String, String>, settings: &mut Settings) -> Vec<String> {
+    let mut errors = vec![];
+    let mut set = |name: &str, value: OptionValue| {
+        if let Err(error) = settings.set(name, value, true) {
+            errors.push(format!(".editorconfig: {}", error));
+        }
+    };
+    let get = |key: &str| properties.get(key).map(|value| value.to_lowercase()).filter(|value| value != "unset");
+    let invalid = |key: &str, value: &str| format!(".editorconfig: invalid {}: {}", key, value);
+    let mut invalid_values = vec![];
+
+    match get("indent_style").as_ref().map(|value| value.as_str()) {
+        Some("space") => set("expandtab", OptionValue::Bool(true)),
+        Some("tab") => set("expandtab", OptionValue::Bool(false)),
+        Some(value) => invalid_values.push(invalid("indent_style", value)),
+        None => (),
+    }
+
+    let tab_width = get("tab_width");
+    match get("indent_size") {
+        Some(ref value) if value == "tab" => set("shiftwidth", OptionValue::Int(0)),
+        Some(value) => match value.parse::<usize>() {
+            Ok(size) => {
+                set("shiftwidth", OptionValue::Int(size));
+                // tab_width defaults to indent_size
+                if tab_width.is_none() {
+                    set("tabstop", OptionValue::Int(size));
+                }
+            },
+            Err(_) => invalid_values.push(invalid("indent_size", &value)),
+        },
+        None => (),
+    }
+    if let Some(value) = tab_width {
+        match value.parse::<usize>() {
+            Ok(width) => set("tabstop", OptionValue::Int(width)),
+            Err(_) => invalid_values.push(invalid("tab_width", &value)),
+        }
+    }
+
+    match get("end_of_line").as_ref().map(|value| value.as_str()) {
+        Some("lf") => set("fileformat", OptionValue::Str(String::from("unix"))),
+        Some("crlf") => set("fileformat", OptionValue::Str(String::from("dos"))),
+        Some("cr") => set("fileformat", OptionValue::Str(String::from("mac"))),
+        Some(value) => invalid_values.push(invalid("end_of_line", value)),
+        None => (),
+    }
+
+    match get("charset").as_ref().map(|value| value.as_str()) {
+        Some("utf-8-bom") => {
+            set("fileencoding", OptionValue::Str(String::from("utf-8")));
+            set("bomb", OptionValue::Bool(true));
+        },
+        Some(charset @ "utf-8") | Some(charset @ "latin1") | Some(charset @ "utf-16be") | Some(charset @ "utf-16le") => {
+            set("fileencoding", OptionValue::Str(charset.to_string()));
+            set("bomb", OptionValue::Bool(false));
+        },
+        Some(value) => invalid_values.push(invalid("charset", value)),
+        None => (),
+    }
+
+    for &(key, option) in [("trim_trailing_whitespace", "trimtrailingwhitespace"), ("insert_final_newline", "fixendofline")].iter() {
+        match get(key).as_ref().map(|value| value.as_str()) {
+            Some("true") => set(option, OptionValue::Bool(true)),
+            Some("false") => set(option, OptionValue::Bool(false)),
+            Some(value) => invalid_values.push(invalid(key, value)),
+            None => (),
+        }
+    }
+
+    errors.extend(invalid_values);
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{glob_matches, expand_braces};
+
+    #[test]
+    fn globs() {
+        let table = &[
+            // Glob, path, whether it matches
+            ("*.py", "main.py", true),
+            ("*.py", "src/main.py", false),
+            ("*", "Makefile", true),
+            ("src/*.rs", "src/main.rs", true),
+            ("src/*.rs", "src/data/io.rs", false),
+            ("**.rs", "src/data/io.rs", true),
+            ("src/**/io.rs", "src/data/io.rs", true),
+            ("src/**", "src/data/io.rs", true),
+            ("docs/**", "src/data/io.rs", false),
+            ("?.c", "a.c", true),
+            ("?.c", "ab.c", false),
+            ("a?c", "a/c", false),
+            ("[abc].c", "b.c", true),
+            ("[abc].c", "d.c", false),
+            ("[a-c].c", "b.c", true),
+            ("[!abc].c", "d.c", true),
+            ("[!abc].c", "a.c", false),
+            ("[abc", "[abc", true),
+            ("*.{py,rs}", "main.rs", true),
+            ("*.{py,rs}", "main.py", true),
+            ("*.{py,rs}", "main.c", false),
+            ("{a,{b,c}}.txt", "c.txt", true),
+            ("{a,}.txt", ".txt", true),
+            ("{single}.txt", "{single}.txt", true),
+            ("{single}.txt", "single.txt", false),
+            ("file{1..3}", "file2", true),
+            ("file{1..3}", "file4", false),
+            ("file{-1..1}", "file-1", true),
+            ("file{3..1}", "file{3..1}", true),
+            ("\\*.txt", "*.txt", true),
+            ("\\*.txt", "a.txt", false),
+            ("\\{a,b}", "{a,b}", true),
+            ("\\[a]", "[a]", true),
+        ];
+        for &(glob, path, matches) in table.iter() {
+            assert_eq!(glob_matches(glob, path), matches, "{} against {}", glob, path);
+        }
+    }
+
+    #[test]
+    fn expanding_braces() {
+        assert_eq!(expand_braces("{a,b}{1..2}"), vec!["a1", "a2", "b1", "b2"]);
+        assert_eq!(expand_braces("x{a,b{c,d}}"), vec!["xa", "xbc", "xbd"]);
+        assert_eq!(expand_braces("{unclosed,"), vec!["{unclosed,"]);
+        // Too many alternatives match nothing, rather than taking forever
+        assert!(expand_braces("{1..100000}").is_empty());
+        assert!(expand_braces("{1..40}{1..40}").is_empty());
+    }
+}
@@ -1,18 +1,312 @@
-use std::io::BufWriter;
 use std::io::prelude::*;
-use std::fs::File;
+use std::fs::{self, File};
 
 use data::editor_state::{StateApi, EditorState};
+use data::settings::{Settings, OptionValue};
 
+// Byte order marks, and the encodings they mark
+const BOMS: &[(&[u8], &str)] = &[
+    (&[0xEF, 0xBB, 0xBF], "utf-8"),
+    (&[0xFE, 0xFF], "utf-16be"),
+    (&[0xFF, 0xFE], "utf-16le"),
+];
+
+// Loads the file being edited into the buffer, decoding it with `fileencoding`.
+// A byte order mark says what the encoding is whatever the option, and is kept
+// out of the text with `bomb` set, for it to be written back. Bytes which aren't
+// valid in the encoding are read as latin1, which any bytes are, so nothing is
+// lost by saving them again.
+pub fn read_file(state: &mut EditorState) -> Result<(), String> {
+    let mut bytes = vec![];
+    File::open(state.get_active_file_name())
+         .and_then(|mut file| file.read_to_end(&mut bytes))
+         .map_err(|error| format!("Unable to read file: {}", error))?;
+
+    let mut result = Ok(());
+    let text = match BOMS.iter().find(|&&(bom, _)| bytes.starts_with(bom)) {
+        Some(&(bom, encoding)) => {
+            state.settings.set("fileencoding", OptionValue::Str(encoding.to_string()), true).expect("A known encoding");
+            state.settings.set("bomb", OptionValue::Bool(true), true).expect("A bool option");
+            decode(&bytes[bom.len()..], encoding)
+        },
+        None => decode(&bytes, state.settings.fileencoding()),
+    };
+    let text = text.unwrap_or_else(|error| {
+        result = Err(format!("{}, so it was read as latin1", error));
+        state.settings.set("fileencoding", OptionValue::Str(String::from("latin1")), true).expect("A known encoding");
+        decode(&bytes, "latin1").expect("Any bytes are latin1")
+    });
+    state.load_text(text);
+    result
+}
 
 pub fn write_file(state: &mut EditorState) {
-    let mut buffer = BufWriter::new(
-        File::create("saved_file.txt")
-             .expect("Unable to create file")
-    );
+    let text = file_text(&state.get_text(), &state.settings);
+    let result = encode(&text, &state.settings).and_then(|bytes| {
+        File::create(state.get_active_file_name())
+             .and_then(|mut file| file.write_all(&bytes))
+             .map_err(|error| error.to_string())
+    });
+
+    match result {
+        Ok(()) => {
+            let modified_time = fs::metadata(state.get_active_file_name()).and_then(|meta| meta.modified()).ok();
+            state.mark_saved(modified_time);
+        },
+        Err(error) => {
+            state.set_status_message(format!("Unable to write file: {}", error));
+        }
+    }
+}
+
+// The `fileformat` of text read from a file, going by its line endings as Vim
+// does: `dos` when every line ends in CR LF, `mac` when there are CRs but no LFs,
+// and `unix` otherwise.
+pub fn detect_fileformat(text: &str) -> &'static str {
+    let line_feeds = text.matches('\n').count();
+    if line_feeds == 0 {
+        if text.contains('\r') { "mac" } else { "unix" }
+    } else if text.matches("\r\n").count() == line_feeds {
+        "dos"
+    } else {
+        "unix"
+    }
+}
+
+// The text with the line endings of `fileformat` made plain LFs, as it's edited.
+// `file_text` puts them back.
+pub fn strip_line_endings(text: &str, fileformat: &str) -> String {
+    match fileformat {
+        "dos" => text.replace("\r\n", "\n"),
+        "mac" => text.replace('\r', "\n"),
+        _ => text.to_string(),
+    }
+}
+
+// The text as it should be saved: with the line endings given by `fileformat`,
+// trailing whitespace removed if `trimtrailingwhitespace` is set, and a final
+// newline added if `fixendofline` is set.
+fn file_text(text: &str, settings: &Settings) -> String {
+    if text.is_empty() {
+        return String::new();
+    }
+
+    let line_ending = match settings.fileformat() {
+        "dos" => "\r\n",
+        "mac" => "\r",
+        _ => "\n",
+    };
+    let ends_with_newline = text.ends_with('\n');
+    let body = if ends_with_newline { &text[..text.len() - 1] } else { text };
+
+    let lines: Vec<&str> = body.split('\n').map(|line| {
+        if settings.trimtrailingwhitespace() {
+            line.trim_right_matches(|ch| ch == ' ' || ch == '\t')
+        } else {
+            line
+        }
+    }).collect();
+
+    let mut saved = lines.join(line_ending);
+    if ends_with_newline || settings.fixendofline() {
+        saved.push_str(line_ending);
+    }
+    saved
+}
+
+// Encodes the text using `fileencoding`, with a byte order mark if `bomb` is set.
+fn encode(text: &str, settings: &Settings) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    match settings.fileencoding() {
+        "latin1" => for ch in text.chars() {
+            if ch as u32 > 0xFF {
+                return Err(format!("{} can't be written as latin1", ch));
+            }
+            bytes.push(ch as u8);
+        },
+        "utf-16be" => {
+            if settings.bomb() {
+                bytes.extend_from_slice(&[0xFE, 0xFF]);
+            }
+            for unit in text.encode_utf16() {
+                bytes.push((unit >> 8) as u8);
+                bytes.push(unit as u8);
+            }
+        },
+        "utf-16le" => {
+            if settings.bomb() {
+                bytes.extend_from_slice(&[0xFF, 0xFE]);
+            }
+            for unit in text.encode_utf16() {
+                bytes.push(unit as u8);
+                bytes.push((unit >> 8) as u8);
+            }
+        },
+        _ => {
+            if settings.bomb() {
+                bytes.extend_from_slice(&[0xEF, 0xBB, 0xBF]);
+            }
+            bytes.extend_from_slice(text.as_bytes());
+        }
+    }
+    Ok(bytes)
+}
+
+// Decodes text in one of the encodings `fileencoding` allows, which `encode`
+// writes. Any byte order mark has already been taken off.
+fn decode(bytes: &[u8], encoding: &str) -> Result<String, String> {
+    match encoding {
+        "latin1" => Ok(bytes.iter().map(|&byte| byte as char).collect()),
+        "utf-16be" | "utf-16le" => {
+            if bytes.len() % 2 != 0 {
+                return Err(format!("The file isn't valid {}", encoding));
+            }
+            let units: Vec<u16> = bytes.chunks(2).map(|pair| if encoding == "utf-16be" {
+                (pair[0] as u16) << 8 | pair[1] as u16
+            } else {
+                (pair[1] as u16) << 8 | pair[0] as u16
+            }).collect();
+            String::from_utf16(&units).map_err(|_| format!("The file isn't valid {}", encoding))
+        },
+        _ => String::from_utf8(bytes.to_vec()).map_err(|_| String::from("The file isn't valid utf-8")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use termion::event::Event;
+
+    use controller::events::InputModeMultiplexer;
+    use controller::keymap::parse_keys;
+    use data::editor_state::{StateApi, EditorState, Mode};
+    use data::piece_table::PieceTable;
+    use data::settings::OptionValue;
+    use super::{detect_fileformat, file_text, read_file, write_file};
+
+    fn load(file_name: &str, text: &str) -> EditorState {
+        EditorState::new(file_name.to_string(), Mode::Navigate, 0, PieceTable::from_text(text.to_string()), vec![])
+    }
+
+    #[test]
+    fn detecting_line_endings() {
+        assert_eq!(detect_fileformat("ab\ncd\n"), "unix");
+        assert_eq!(detect_fileformat("ab\r\ncd\r\n"), "dos");
+        assert_eq!(detect_fileformat("ab\r\ncd"), "dos");
+        assert_eq!(detect_fileformat("ab\r\ncd\n"), "unix");
+        assert_eq!(detect_fileformat("ab\rcd\r"), "mac");
+        assert_eq!(detect_fileformat(""), "unix");
+    }
+
+    #[test]
+    fn line_endings_are_kept_out_of_the_text() {
+        let mut state = load("test.txt", "ab\r\ncd\r\nef\r\n");
+        assert_eq!(state.settings.fileformat(), "dos");
+        assert_eq!(state.get_editor_lines(), vec!["ab\n", "cd\n", "ef\n"]);
+
+        let mut input = InputModeMultiplexer::new();
+        for key in parse_keys("jjdd", "").unwrap() {
+            input.do_action_for_input(Event::Key(key), &mut state);
+        }
+        assert_eq!(state.get_text(), "ab\ncd\n");
+        assert_eq!(file_text(&state.get_text(), &state.settings), "ab\r\ncd\r\n");
+
+        let state = load("test.txt", "ab\rcd\r");
+        assert_eq!(state.get_text(), "ab\ncd\n");
+        assert_eq!(file_text(&state.get_text(), &state.settings), "ab\rcd\r");
+
+        // A `fileformat` given to the buffer, as by .editorconfig, is kept
+        let mut state = load("test.txt", "");
+        state.settings.set("fileformat", OptionValue::Str(String::from("unix")), true).unwrap();
+        state.load_text(String::from("ab\r\ncd\r\n"));
+        assert_eq!(state.get_text(), "ab\ncd\n");
+        assert_eq!(file_text(&state.get_text(), &state.settings), "ab\ncd\n");
+    }
+
+    #[test]
+    fn saving_writes_the_file_being_edited() {
+        let path = env::temp_dir().join(format!("bullet-io-{}.txt", process::id()));
+        let mut state = load(path.to_str().unwrap(), "one\r\ntwo\r\n");
+        state.replace_text(0, 3, "three");
+        assert!(!state.change_hunks().is_empty());
+        write_file(&mut state);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "three\r\ntwo\r\n");
+        // Changes are marked against what was saved
+        assert!(state.change_hunks().is_empty());
+        assert_eq!(state.saved_modified_time(), fs::metadata(&path).and_then(|meta| meta.modified()).ok());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn files_are_read_and_written_in_their_encoding() {
+        let path = env::temp_dir().join(format!("bullet-encoding-{}.txt", process::id()));
+        let cases: &[(&str, &[u8], &[u8])] = &[
+            ("latin1", b"h\xe9\n", b"\xe9\xe9\n"),
+            ("utf-16le", b"h\x00\xe9\x00\n\x00", b"\xe9\x00\xe9\x00\n\x00"),
+            ("utf-16be", b"\x00h\x00\xe9\x00\n", b"\x00\xe9\x00\xe9\x00\n"),
+            ("utf-8", b"h\xc3\xa9\n", b"\xc3\xa9\xc3\xa9\n"),
+        ];
+        for &(encoding, bytes, saved) in cases {
+            fs::write(&path, bytes).unwrap();
+            let mut state = load(path.to_str().unwrap(), "");
+            state.settings.set("fileencoding", OptionValue::Str(encoding.to_string()), true).unwrap();
+            read_file(&mut state).unwrap();
+            assert_eq!(state.get_text(), "h\u{e9}\n", "{}", encoding);
+
+            state.replace_text(0, 1, "\u{e9}");
+            write_file(&mut state);
+            assert_eq!(fs::read(&path).unwrap(), saved, "{}", encoding);
+            assert!(!state.settings.bomb());
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn byte_order_marks_are_kept_out_of_the_text() {
+        let path = env::temp_dir().join(format!("bullet-bom-{}.txt", process::id()));
+        let cases: &[(&str, &[u8])] = &[
+            ("utf-8", b"\xef\xbb\xbfh\xc3\xa9\n"),
+            ("utf-16le", b"\xff\xfeh\x00\xe9\x00\n\x00"),
+            ("utf-16be", b"\xfe\xff\x00h\x00\xe9\x00\n"),
+        ];
+        for &(encoding, bytes) in cases {
+            fs::write(&path, bytes).unwrap();
+            // The mark wins over the encoding given by the options
+            let mut state = load(path.to_str().unwrap(), "");
+            state.settings.set("fileencoding", OptionValue::Str(String::from("latin1")), false).unwrap();
+            read_file(&mut state).unwrap();
+            assert_eq!(state.get_text(), "h\u{e9}\n", "{}", encoding);
+            assert_eq!((state.settings.fileencoding(), state.settings.bomb()), (encoding, true));
+
+            // Saving writes the one mark back, however many times it's done
+            write_file(&mut state);
+            write_file(&mut state);
+            assert_eq!(fs::read(&path).unwrap(), bytes, "{}", encoding);
+        }
+
+        // As with `charset = utf-8-bom`, a file without a mark gets one when saved
+        fs::write(&path, b"one\n").unwrap();
+        let mut state = load(path.to_str().unwrap(), "");
+        state.settings.set("bomb", OptionValue::Bool(true), true).unwrap();
+        read_file(&mut state).unwrap();
+        write_file(&mut state);
+        assert_eq!(fs::read(&path).unwrap(), b"\xef\xbb\xbfone\n");
+        fs::remove_file(path).unwrap();
+    }
 
-    for line in state.get_editor_lines().iter() {
-        buffer.write((*line).as_bytes());
-        buffer.write("\n".as_bytes());  // Naive.
+    #[test]
+    fn files_which_arent_valid_in_their_encoding_are_read_as_latin1() {
+        let path = env::temp_dir().join(format!("bullet-invalid-{}.txt", process::id()));
+        fs::write(&path, b"caf\xe9\r\n").unwrap();
+        let mut state = load(path.to_str().unwrap(), "");
+        assert!(read_file(&mut state).is_err());
+        assert_eq!(state.get_text(), "caf\u{e9}\n");
+        assert_eq!((state.settings.fileencoding(), state.settings.fileformat()), ("latin1", "dos"));
+        write_file(&mut state);
+        assert_eq!(fs::read(&path).unwrap(), b"caf\xe9\r\n");
+        fs::remove_file(path).unwrap();
     }
-} 
\ No newline at end of file
+}
@@ -0,0 +1,146 @@
+use std::cmp;
+use std::collections::HashMap;
+
+// Older jumps are forgotten once there are this many
+const MAX_JUMPS: usize = 100;
+
+// Positions in the text which follow it as it's edited. Marks and jumps are kept
+// as char indices, shifted along by every insertion and deletion before them, so
+// each stays on the char it was set on. One inside text which is replaced keeps
+// its offset if the new text is long enough, and otherwise moves to its start.
+//
+// `m{a-z}` marks belong to the file, as do the automatic `''` (where the last jump
+// was made from), `'.` (the last change) and `'^` (where Insert mode was last
+// left). `m{A-Z}` marks also remember which file they're in.
+pub struct Marks {
+    local: HashMap<char, usize>,
+    file: HashMap<char, (String, usize)>,
+    jumps: Vec<usize>,
+    jump_index: usize,  // Where Ctrl-o and Ctrl-i have got to in `jumps`, its length when at the newest
+}
+
+impl Marks {
+    pub fn new() -> Self {
+        Marks {
+            local: HashMap::new(),
+            file: HashMap::new(),
+            jumps: vec![],
+            jump_index: 0,
+        }
+    }
+
+    // Sets a mark with `m`. `m'` and ``m` `` set the mark jumped back to by `''`.
+    pub fn set(&mut self, name: char, index: usize, file_name: &str) -> Result<(), String> {
+        match name {
+            'a'...'z' => self.set_automatic(name, index),
+            '\'' | '`' => self.set_automatic('\'', index),
+            'A'...'Z' => {
+                self.file.insert(name, (file_name.to_string(), index));
+            },
+            _ => return Err(String::from("Argument must be a letter or forward/backward quote")),
+        }
+        Ok(())
+    }
+
+    // Moves one of the marks the editor keeps itself, `''`, `'.` or `'^`
+    pub fn set_automatic(&mut self, name: char, index: usize) {
+        self.local.insert(name, index);
+    }
+
+    // The index of the mark, which has to be in the file being edited
+    pub fn get(&self, name: char, file_name: &str) -> Result<usize, String> {
+        let name = if name == '`' { '\'' } else { name };
+        match self.file.get(&name) {
+            Some(&(ref file, index)) if file == file_name => Ok(index),
+            Some(&(ref file, _)) => Err(format!("Mark {} is in {}", name, file)),
+            None => self.local.get(&name).cloned().ok_or_else(|| String::from("Mark not set")),
+        }
+    }
+
+    // Every mark set in this file, with those in other files, for `:marks`
+    pub fn all(&self) -> Vec<(char, Option<&str>, usize)> {
+        let mut marks: Vec<(char, Option<&str>, usize)> = self.local.iter()
+            .map(|(&name, &index)| (name, None, index))
+            .chain(self.file.iter().map(|(&name, &(ref file, index))| (name, Some(file.as_str()), index)))
+            .collect();
+        // Listed like Vim, with the automatic marks first
+        let order = |name: char| match name {
+            '\'' => 0,
+            'a'...'z' => 1,
+            'A'...'Z' => 2,
+            _ => 3,
+        };
+        marks.sort_by_key(|&(name, _, _)| (order(name), name));
+        marks
+    }
+
+    // Notes a jump from `index`, which becomes the newest in the list. An older
+    // jump from the same line is dropped, as is anything beyond the newest.
+    pub fn push_jump(&mut self, index: usize, line_of: &Fn(usize) -> usize) {
+        self.set_automatic('\'', index);
+        let line = line_of(index);
+        self.jumps.retain(|&jump| line_of(jump) != line);
+        self.jumps.push(index);
+        if self.jumps.len() > MAX_JUMPS {
+            self.jumps.remove(0);
+        }
+        self.jump_index = self.jumps.len();
+    }
+
+    // Goes `count` jumps back from the cursor at `index` for Ctrl-o. Leaving the
+    // newest end of the list keeps the cursor position, so Ctrl-i can return to it.
+    pub fn older_jump(&mut self, index: usize, count: usize, line_of: &Fn(usize) -> usize) -> Option<usize> {
+        if count > self.jump_index {
+            return None;
+        }
+        if self.jump_index == self.jumps.len() {
+            // The position may take the place of an older jump from the same line
+            self.push_jump(index, line_of);
+            self.jump_index = self.jumps.len() - 1;
+            if count > self.jump_index {
+                return None;
+            }
+        }
+        self.jump_index -= count;
+        Some(self.jumps[self.jump_index])
+    }
+
+    // Goes `count` jumps forward again for Ctrl-i
+    pub fn newer_jump(&mut self, count: usize) -> Option<usize> {
+        if self.jump_index + count >= self.jumps.len() {
+            return None;
+        }
+        self.jump_index += count;
+        Some(self.jumps[self.jump_index])
+    }
+
+    // The jumps from oldest to newest, and where Ctrl-o and Ctrl-i have got to
+    pub fn jumps(&self) -> (&[usize], usize) {
+        (&self.jumps, self.jump_index)
+    }
+
+    // Follows the text of the file named after `length` chars at `index` are
+    // replaced by `inserted` chars.
+    pub fn adjust(&mut self, index: usize, length: usize, inserted: usize, file_name: &str) {
+        let moved = |position: usize| {
+            if position < index {
+                position
+            } else if position >= index + length {
+                position + inserted - length
+            } else {
+                index + cmp::min(position - index, inserted.saturating_sub(1))
+            }
+        };
+        for position in self.local.values_mut() {
+            *position = moved(*position);
+        }
+        for &mut (ref file, ref mut position) in self.file.values_mut() {
+            if file == file_name {
+                *position = moved(*position);
+            }
+        }
+        for position in self.jumps.iter_mut() {
+            *position = moved(*position);
+        }
+    }
+}
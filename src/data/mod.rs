@@ -1,3 +1,11 @@
 pub mod piece_table;
+pub mod diff;
 pub mod editor_state;
-pub mod io;
\ No newline at end of file
+pub mod io;
+pub mod registers;
+pub mod marks;
+pub mod clipboard;
+pub mod settings;
+pub mod config;
+pub mod editorconfig;
+pub mod wrap;
\ No newline at end of file
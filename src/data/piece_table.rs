@@ -1,5 +1,5 @@
-use std::io::prelude::*;
-use std::fs::File;
+use std::iter::{Skip, Take};
+use std::str::Chars;
 
 #[derive(Debug)]
 pub struct PieceTable {
@@ -10,24 +10,21 @@ pub struct PieceTable {
 
 #[derive(Debug)]
 pub struct Piece {
-    source: Source,
-    start: usize,
+    pub source: Source,
+    pub start: usize,
     pub length: usize,
 }
 
-#[derive(Debug, PartialEq)]
-enum Source {
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Source {
     Orig,
     Add
 }
 
 impl PieceTable {
 
-    pub fn new(from_file: &mut File) -> PieceTable {
-        let mut orig_buffer = String::new();
-        from_file.read_to_string(&mut orig_buffer)
-                 .expect("Unable to read file contents!");
-
+    // Builds a piece table from the text of a file, as `io::read_file` decodes it.
+    pub fn from_text(orig_buffer: String) -> PieceTable {
         let file_length = orig_buffer.chars().count();
         PieceTable {
             original_file: orig_buffer,
@@ -43,21 +40,10 @@ impl PieceTable {
     }
 
     pub fn as_lines(&self) -> Vec<String> {
-        // Since we're read for now, we'll just load in the
-        // return the lines of the original file for now,
-        // rather than from the piece table buffers.
-
-        // I would like to do something a little more complex here -
-        // return a Vec of characters wrapped in some Struct which
-        // indicates the source buffer of the character. This can then
-        // be read by the client, and we can display characters from
-        // the original file buffer in a different colour from the 
-        // characters from the add buffer.
-
-        // Perhaps it would also make sense to move the logic for 
-        // returning this struct into a dedicated DebugIterator for piece
-        // tables, which returns wrapped chars as opposed to the standard
-        // Iterator implementation.
+        // The lines are assembled from the piece table buffers. When the
+        // client needs to know which buffer each character came from (e.g.
+        // to display characters from the add buffer in a different colour),
+        // it can use `debug_iter` instead.
 
         // It would also make sense to get rid of this method in general
         // and just give the front-end access to the Iterator/DebugIterator,
@@ -71,122 +57,166 @@ impl PieceTable {
 
         // Maybe we return something which implements the Reader trait, and pass that
         // to syntect?
+        self.text().lines()
+            .map(|line| line.to_string() + "\n")
+            .collect()
+    }
+
+    // The lines of the file as it was loaded, in the same form as `as_lines`.
+    pub fn original_lines(&self) -> Vec<String> {
         self.original_file.lines()
             .map(|line| line.to_string() + "\n")
             .collect()
     }
 
+    // The current contents, assembled from the pieces.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for piece in self.pieces.iter() {
+            let buffer = match piece.source {
+                Source::Orig => &self.original_file,
+                Source::Add => &self.add_buffer,
+            };
+            text.extend(buffer.chars().skip(piece.start).take(piece.length));
+        }
+        text
+    }
+
+    // True if nothing has been inserted or deleted since the file was loaded.
+    pub fn is_unmodified(&self) -> bool {
+        let orig_length = self.original_file.chars().count();
+        let orig_pieces: Vec<&Piece> = self.pieces.iter().filter(|p| p.length > 0).collect();
+        match orig_pieces.as_slice() {
+            [] => orig_length == 0,
+            [piece] => piece.source == Source::Orig && piece.start == 0 && piece.length == orig_length,
+            _ => false,
+        }
+    }
+
+    // Inserts text before the char at `index`. Typing at the end of the most
+    // recent insertion extends its piece rather than creating a new one.
+    pub fn insert(&mut self, index: usize, text: &str) {
+        let length = text.chars().count();
+        if length == 0 {
+            return;
+        }
+
+        let add_start = self.add_buffer.chars().count();
+        self.add_buffer.push_str(text);
+        let new_piece = Piece { source: Source::Add, start: add_start, length };
+
+        let mut offset = 0;
+        for piece_index in 0..self.pieces.len() {
+            let piece_length = self.pieces[piece_index].length;
+            if index <= offset + piece_length {
+                let split_at = index - offset;
+                if split_at == 0 {
+                    self.pieces.insert(piece_index, new_piece);
+                } else if split_at == piece_length {
+                    let piece = &mut self.pieces[piece_index];
+                    if piece.source == Source::Add && piece.start + piece.length == add_start {
+                        piece.length += length;
+                    } else {
+                        self.pieces.insert(piece_index + 1, new_piece);
+                    }
+                } else {
+                    let (source, start) = (self.pieces[piece_index].source, self.pieces[piece_index].start);
+                    self.pieces[piece_index].length = split_at;
+                    self.pieces.insert(piece_index + 1, new_piece);
+                    self.pieces.insert(piece_index + 2, Piece {
+                        source,
+                        start: start + split_at,
+                        length: piece_length - split_at
+                    });
+                }
+                return;
+            }
+            offset += piece_length;
+        }
+        self.pieces.push(new_piece);
+    }
+
+    // Removes `length` chars starting at `index`, trimming or splitting any pieces
+    // which overlap the deleted range.
+    pub fn delete(&mut self, index: usize, length: usize) {
+        let end = index + length;
+        let mut offset = 0;
+        let mut remaining_pieces = vec![];
+        for piece in self.pieces.drain(..) {
+            let (piece_start, piece_end) = (offset, offset + piece.length);
+            offset = piece_end;
+            if piece_end <= index || piece_start >= end {
+                remaining_pieces.push(piece);
+                continue;
+            }
+            if piece_start < index {
+                remaining_pieces.push(Piece { source: piece.source, start: piece.start, length: index - piece_start });
+            }
+            if piece_end > end {
+                remaining_pieces.push(Piece {
+                    source: piece.source,
+                    start: piece.start + (end - piece_start),
+                    length: piece_end - end
+                });
+            }
+        }
+        self.pieces = remaining_pieces;
+    }
+
     pub fn get_pieces(&self) -> &Vec<Piece> {
         &self.pieces
     }
 
-    pub fn iter(&self) -> PieceTableIterator {
-        PieceTableIterator {
-            back_offset: 0,
-            char_offset: 0, 
+    // The chars of the text, each with the buffer it was read from, the index of
+    // its piece and its offset within that buffer.
+    pub fn debug_iter(&self) -> DebugIterator {
+        DebugIterator {
             piece_index: 0,
-            back_piece_index: 0, 
+            char_offset: 0,
+            chars: None,
             piece_table: self,
         }
-    } 
+    }
 
 }
 
-#[derive(Debug)]
-pub struct PieceTableIterator<'a> {
-    char_offset: usize,
-    back_offset: usize,  // Backwards offset for DoubleEndedIterator, back_offset=0 means last char in piece
+pub struct DebugIterator<'a> {
     piece_index: usize,
-    back_piece_index: usize,
+    char_offset: usize,  // Offset of the next char within the current piece
+    chars: Option<Take<Skip<Chars<'a>>>>,  // The chars of the current piece
     piece_table: &'a PieceTable
 }
 
-impl<'a> PieceTableIterator<'a> {
+impl<'a> Iterator for DebugIterator<'a> {
 
-    fn iterators_crossed(&self) -> bool {
-        let current_piece = &self.piece_table.get_pieces()[self.piece_index];
-
-        let back_offset_real_index = current_piece.length - self.back_offset - 1;
-
-        self.char_offset >= back_offset_real_index &&
-        self.back_piece_index == self.piece_index
-    } 
-
-}
-
-impl<'a> Iterator for PieceTableIterator<'a> {
-
-    type Item = char;
+    type Item = (char, Source, usize, usize);
 
     fn next(&mut self) -> Option<Self::Item> {
         let pieces = self.piece_table.get_pieces();
-        let current_piece = &pieces[self.piece_index];
-        
-        let is_final_char = self.piece_index == pieces.len() - 1 &&
-                            self.char_offset == current_piece.length;
-
-        if is_final_char || self.iterators_crossed() {
-            return None;
-        }
-
-        let index_in_buffer = current_piece.start + self.char_offset;
-        let ch = match current_piece.source {
-            Source::Orig => self.piece_table.original_file.chars().nth(index_in_buffer),
-            Source::Add => self.piece_table.add_buffer.chars().nth(index_in_buffer)
-        };
+        loop {
+            if self.piece_index >= pieces.len() {
+                return None;
+            }
+            let piece = &pieces[self.piece_index];
+
+            if self.chars.is_none() {
+                let buffer = match piece.source {
+                    Source::Orig => &self.piece_table.original_file,
+                    Source::Add => &self.piece_table.add_buffer
+                };
+                self.chars = Some(buffer.chars().skip(piece.start).take(piece.length));
+                self.char_offset = 0;
+            }
+
+            if let Some(ch) = self.chars.as_mut().and_then(|chars| chars.next()) {
+                let item = (ch, piece.source, self.piece_index, piece.start + self.char_offset);
+                self.char_offset += 1;
+                return Some(item);
+            }
 
-        self.char_offset += 1;
-        if self.char_offset == current_piece.length {
             self.piece_index += 1;
-            self.char_offset = 0;
-        }
-        
-        ch
-    }
-
-    fn count(self) -> usize {
-        self.len()
-    }
-
-}
-
-impl<'a> ExactSizeIterator for PieceTableIterator<'a> {
-
-    fn len(&self) -> usize {
-        let pieces = self.piece_table.get_pieces().iter();
-        pieces.map(|p| p.length)
-              .sum()
-    }
-
-}
-
-impl<'a> DoubleEndedIterator for PieceTableIterator<'a> {
-
-    fn next_back(&mut self) -> Option<Self::Item> {
-        let pieces = self.piece_table.get_pieces();
-        let num_pieces = pieces.len();
-        let current_piece = &pieces[num_pieces - self.back_piece_index - 1];
-        
-        let is_first_char = self.back_piece_index == num_pieces - 1 &&
-                            self.back_offset == current_piece.length - 1;
-
-        if is_first_char || self.iterators_crossed() {
-            return None;
-        }
-
-        let index_in_buffer = current_piece.start + current_piece.length - self.back_offset - 1;
-        let ch = match current_piece.source {
-            Source::Orig => self.piece_table.original_file.chars().nth(index_in_buffer),
-            Source::Add => self.piece_table.add_buffer.chars().nth(index_in_buffer)
-        };
-
-        self.back_offset += 1;
-        if self.back_offset == current_piece.length {
-            self.back_piece_index += 1;
-            self.back_offset = 0;
+            self.chars = None;
         }
-
-        ch
     }
 
 }
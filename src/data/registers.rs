@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+// How the text of a register was taken, which decides how it's put back.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RegisterKind {
+    Charwise,
+    Linewise,  // Whole lines, which are put back as lines of their own
+    Blockwise,  // The lines of a Visual-block selection, put back as a block
+}
+
+// Text which has been deleted or yanked, ready to be put back.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Register {
+    pub text: String,
+    pub kind: RegisterKind,
+}
+
+impl Register {
+    pub fn new(text: String, kind: RegisterKind) -> Self {
+        Register { text, kind }
+    }
+
+    // Adds more text to the end, for the uppercase names like `"A`. Adding whole
+    // lines makes the register linewise.
+    fn append(self, other: Register) -> Register {
+        let mut text = self.text;
+        let kind = if self.kind == RegisterKind::Linewise || other.kind == RegisterKind::Linewise {
+            if !text.ends_with('\n') {
+                text.push('\n');
+            }
+            RegisterKind::Linewise
+        } else {
+            self.kind
+        };
+        text.push_str(&other.text);
+        Register { text, kind }
+    }
+
+    // A line for `:registers`, with the kind and name of the register followed by as
+    // much of the text as fits, its line breaks and tabs shown as `^J` and `^I`.
+    pub fn describe(&self, name: char) -> String {
+        let kind = match self.kind {
+            RegisterKind::Charwise => 'c',
+            RegisterKind::Linewise => 'l',
+            RegisterKind::Blockwise => 'b',
+        };
+        let content: String = self.text.chars()
+            .map(|ch| match ch {
+                '\n' => String::from("^J"),
+                '\t' => String::from("^I"),
+                ch => ch.to_string(),
+            })
+            .collect::<String>()
+            .chars()
+            .take(MAX_DESCRIBED_CHARS)
+            .collect();
+        format!("  {}  \"{}   {}", kind, name, content)
+    }
+}
+
+const MAX_DESCRIBED_CHARS: usize = 70;
+
+// The names of the registers listed by `:registers`, in the order they're listed
+pub const REGISTER_NAMES: &str = "\"0123456789abcdefghijklmnopqrstuvwxyz-*+.:%";
+
+// Vim's registers. Yanks go to `"0`, deletes within a line to `"-`, and larger
+// deletes to `"1`, pushing the older ones along to `"9`. The unnamed register
+// `""` is whichever of them, or of the named `"a` to `"z`, was written last.
+// Nothing is kept of text sent to the black hole `"_`. The system clipboard, `"+`,
+// and primary selection, `"*`, are read and written by EditorState.
+pub struct Registers {
+    unnamed: char,
+    numbered: Vec<Option<Register>>,
+    named: HashMap<char, Register>,
+    small_delete: Option<Register>,
+    clipboard: Option<Register>,  // What was last copied to `"+`, so that it keeps its kind when put back
+    primary: Option<Register>,  // The same for `"*`
+    pub last_inserted: Option<String>,  // Read only, as `".`
+    pub last_command: Option<String>,  // Read only, as `":`
+    pub selected: Option<char>,  // The register named with `"x` for the command being typed
+}
+
+impl Registers {
+    pub fn new() -> Self {
+        Registers {
+            unnamed: '0',
+            numbered: vec![None; 10],
+            named: HashMap::new(),
+            small_delete: None,
+            clipboard: None,
+            primary: None,
+            last_inserted: None,
+            last_command: None,
+            selected: None,
+        }
+    }
+
+    // Keeps text yanked or deleted by a command, in the selected register if one
+    // was named.
+    pub fn store(&mut self, register: Register, deleted: bool) -> Result<(), String> {
+        match self.selected.take() {
+            None | Some('"') => {
+                if !deleted {
+                    self.numbered[0] = Some(register);
+                    self.unnamed = '0';
+                } else if register.kind == RegisterKind::Charwise && !register.text.contains('\n') {
+                    self.small_delete = Some(register);
+                    self.unnamed = '-';
+                } else {
+                    self.numbered.pop();
+                    self.numbered.insert(1, Some(register));
+                    self.unnamed = '1';
+                }
+            },
+            Some('_') => (),
+            Some(name @ 'a'...'z') => {
+                self.named.insert(name, register);
+                self.unnamed = name;
+            },
+            Some(name @ 'A'...'Z') => {
+                let name = name.to_ascii_lowercase();
+                let appended = match self.named.remove(&name) {
+                    Some(existing) => existing.append(register),
+                    None => register,
+                };
+                self.named.insert(name, appended);
+                self.unnamed = name;
+            },
+            Some(name @ '0'...'9') => {
+                self.numbered[name as usize - '0' as usize] = Some(register);
+                self.unnamed = name;
+            },
+            Some('-') => {
+                self.small_delete = Some(register);
+                self.unnamed = '-';
+            },
+            Some(name) => return Err(format!("Invalid register name: \"{}", name)),
+        }
+        Ok(())
+    }
+
+    // The register `""` stands for
+    pub fn unnamed(&self) -> char {
+        self.unnamed
+    }
+
+    // Notes what was copied to the clipboard or primary selection, which `""` now
+    // stands for.
+    pub fn remember_clipboard(&mut self, register: Register, primary: bool) {
+        if primary {
+            self.primary = Some(register);
+            self.unnamed = '*';
+        } else {
+            self.clipboard = Some(register);
+            self.unnamed = '+';
+        }
+    }
+
+    pub fn clipboard(&self, primary: bool) -> Option<&Register> {
+        if primary { self.primary.as_ref() } else { self.clipboard.as_ref() }
+    }
+
+    pub fn get(&self, name: char) -> Option<Register> {
+        let charwise = |text: &Option<String>| text.clone().map(|text| Register::new(text, RegisterKind::Charwise));
+        match name {
+            '"' => self.get(self.unnamed),
+            'a'...'z' | 'A'...'Z' => self.named.get(&name.to_ascii_lowercase()).cloned(),
+            '0'...'9' => self.numbered[name as usize - '0' as usize].clone(),
+            '-' => self.small_delete.clone(),
+            '.' => charwise(&self.last_inserted),
+            ':' => charwise(&self.last_command),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,481 @@
+use std::collections::HashMap;
+use std::fmt;
+
+// Editor options, changed at runtime with `:set` and `:setlocal`. Every option is
+// described once in OPTIONS, values are stored untagged by type, and the rest of
+// the editor reads them back through the typed accessors on Settings.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OptionValue {
+    Bool(bool),
+    Int(usize),
+    Str(String),
+    List(Vec<String>),  // Written as a comma separated string
+}
+
+impl fmt::Display for OptionValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OptionValue::Bool(value) => write!(f, "{}", value),
+            OptionValue::Int(value) => write!(f, "{}", value),
+            OptionValue::Str(ref value) => write!(f, "{}", value),
+            OptionValue::List(ref items) => write!(f, "{}", items.join(",")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    Global,
+    Buffer,  // May be given a value for the current buffer only, with :setlocal
+}
+
+// The type of an option, along with its default value
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Bool(bool),
+    Int { default: usize, minimum: usize },
+    Str(&'static str),
+    List(&'static str),
+}
+
+pub struct OptionDef {
+    pub name: &'static str,
+    pub abbreviation: Option<&'static str>,
+    pub scope: Scope,
+    kind: Kind,
+    validate: Option<fn(&OptionValue) -> Result<(), String>>,
+}
+
+const OPTIONS: &[OptionDef] = &[
+    // Display
+    OptionDef { name: "wrap", abbreviation: None, scope: Scope::Buffer, kind: Kind::Bool(true), validate: None },
+    OptionDef { name: "linebreak", abbreviation: Some("lbr"), scope: Scope::Buffer, kind: Kind::Bool(false), validate: None },
+    OptionDef { name: "showbreak", abbreviation: Some("sbr"), scope: Scope::Global, kind: Kind::Str(""), validate: None },
+    OptionDef { name: "number", abbreviation: Some("nu"), scope: Scope::Global, kind: Kind::Bool(true), validate: None },
+    OptionDef { name: "relativenumber", abbreviation: Some("rnu"), scope: Scope::Global, kind: Kind::Bool(false), validate: None },
+    OptionDef { name: "numberwidth", abbreviation: Some("nuw"), scope: Scope::Global, kind: Kind::Int { default: 4, minimum: 1 }, validate: None },
+    OptionDef { name: "cursorline", abbreviation: Some("cul"), scope: Scope::Global, kind: Kind::Bool(false), validate: None },
+    OptionDef { name: "colorcolumn", abbreviation: Some("cc"), scope: Scope::Buffer, kind: Kind::List(""), validate: Some(validate_columns) },
+    OptionDef { name: "list", abbreviation: None, scope: Scope::Buffer, kind: Kind::Bool(false), validate: None },
+    OptionDef { name: "listchars", abbreviation: Some("lcs"), scope: Scope::Global, kind: Kind::List("tab:> ,trail:-,nbsp:+"), validate: Some(validate_listchars) },
+
+    // Editing
+    OptionDef { name: "tabstop", abbreviation: Some("ts"), scope: Scope::Buffer, kind: Kind::Int { default: 8, minimum: 1 }, validate: None },
+    OptionDef { name: "shiftwidth", abbreviation: Some("sw"), scope: Scope::Buffer, kind: Kind::Int { default: 8, minimum: 0 }, validate: None },
+    OptionDef { name: "softtabstop", abbreviation: Some("sts"), scope: Scope::Buffer, kind: Kind::Int { default: 0, minimum: 0 }, validate: None },
+    OptionDef { name: "expandtab", abbreviation: Some("et"), scope: Scope::Buffer, kind: Kind::Bool(false), validate: None },
+
+    // Key mappings
+    OptionDef { name: "mapleader", abbreviation: None, scope: Scope::Global, kind: Kind::Str("\\"), validate: None },
+    OptionDef { name: "timeout", abbreviation: Some("to"), scope: Scope::Global, kind: Kind::Bool(true), validate: None },
+    OptionDef { name: "timeoutlen", abbreviation: Some("tm"), scope: Scope::Global, kind: Kind::Int { default: 1000, minimum: 0 }, validate: None },
+
+    // How the `"+` and `"*` registers reach the system clipboard
+    OptionDef { name: "clipboardtool", abbreviation: None, scope: Scope::Global, kind: Kind::Str("auto"), validate: Some(validate_clipboardtool) },
+
+    // Saving
+    OptionDef { name: "fileformat", abbreviation: Some("ff"), scope: Scope::Buffer, kind: Kind::Str("unix"), validate: Some(validate_fileformat) },
+    OptionDef { name: "fileencoding", abbreviation: Some("fenc"), scope: Scope::Buffer, kind: Kind::Str("utf-8"), validate: Some(validate_fileencoding) },
+    OptionDef { name: "bomb", abbreviation: None, scope: Scope::Buffer, kind: Kind::Bool(false), validate: None },
+    OptionDef { name: "fixendofline", abbreviation: Some("fixeol"), scope: Scope::Buffer, kind: Kind::Bool(true), validate: None },
+    OptionDef { name: "trimtrailingwhitespace", abbreviation: Some("ttw"), scope: Scope::Buffer, kind: Kind::Bool(false), validate: None },
+    OptionDef { name: "autosave", abbreviation: None, scope: Scope::Global, kind: Kind::Int { default: 0, minimum: 0 }, validate: None },
+];
+
+pub fn find_option(name: &str) -> Option<&'static OptionDef> {
+    OPTIONS.iter().find(|option| option.name == name || option.abbreviation == Some(name))
+}
+
+impl OptionDef {
+    pub fn is_bool(&self) -> bool {
+        match self.kind {
+            Kind::Bool(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn default_value(&self) -> OptionValue {
+        match self.kind {
+            Kind::Bool(default) => OptionValue::Bool(default),
+            Kind::Int { default, .. } => OptionValue::Int(default),
+            Kind::Str(default) => OptionValue::Str(default.to_string()),
+            Kind::List(default) => OptionValue::List(split_list(default)),
+        }
+    }
+
+    // Parses the text following `=` in `:set name=value`
+    pub fn parse(&self, value: &str) -> Result<OptionValue, String> {
+        let parsed = match self.kind {
+            Kind::Bool(_) => return Err(format!("Invalid argument: {}={}", self.name, value)),
+            Kind::Int { .. } => match value.parse::<usize>() {
+                Ok(number) => OptionValue::Int(number),
+                Err(_) => return Err(format!("Number required: {}={}", self.name, value)),
+            },
+            Kind::Str(_) => OptionValue::Str(value.to_string()),
+            Kind::List(_) => OptionValue::List(split_list(value)),
+        };
+        self.check(&parsed)?;
+        Ok(parsed)
+    }
+
+    // Makes sure a value has the right type and is in range for this option
+    pub fn check(&self, value: &OptionValue) -> Result<(), String> {
+        match (self.kind, value) {
+            (Kind::Bool(_), &OptionValue::Bool(_)) => (),
+            (Kind::Int { minimum, .. }, &OptionValue::Int(number)) => if number < minimum {
+                return Err(format!("Invalid {}: must be at least {}", self.name, minimum));
+            },
+            (Kind::Str(_), &OptionValue::Str(_)) => (),
+            (Kind::List(_), &OptionValue::List(_)) => (),
+            _ => return Err(format!("Invalid value for {}: {}", self.name, value)),
+        }
+        match self.validate {
+            Some(validate) => validate(value),
+            None => Ok(()),
+        }
+    }
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value.split(',')
+         .filter(|item| !item.is_empty())
+         .map(|item| item.to_string())
+         .collect()
+}
+
+fn validate_columns(value: &OptionValue) -> Result<(), String> {
+    if let OptionValue::List(ref columns) = *value {
+        if let Some(column) = columns.iter().find(|column| column.parse::<usize>().map_or(true, |column| column == 0)) {
+            return Err(format!("Invalid colorcolumn: {}", column));
+        }
+    }
+    Ok(())
+}
+
+fn validate_listchars(value: &OptionValue) -> Result<(), String> {
+    ListChars::parse(&value.to_string()).map(|_| ())
+}
+
+fn validate_clipboardtool(value: &OptionValue) -> Result<(), String> {
+    match value.to_string().as_str() {
+        "auto" | "wl-copy" | "xclip" | "xsel" | "osc52" => Ok(()),
+        other => Err(format!("Invalid clipboardtool: {}", other)),
+    }
+}
+
+fn validate_fileformat(value: &OptionValue) -> Result<(), String> {
+    match value.to_string().as_str() {
+        "unix" | "dos" | "mac" => Ok(()),
+        other => Err(format!("Invalid fileformat: {}", other)),
+    }
+}
+
+fn validate_fileencoding(value: &OptionValue) -> Result<(), String> {
+    match value.to_string().as_str() {
+        "utf-8" | "latin1" | "utf-16be" | "utf-16le" => Ok(()),
+        other => Err(format!("Invalid fileencoding: {}", other)),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    global: HashMap<&'static str, OptionValue>,
+    local: HashMap<&'static str, OptionValue>,  // Values set with :setlocal, which take precedence
+}
+
+// The characters used to make whitespace visible with `:set list`. Options
+// which are None are shown as they are.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListChars {
+    pub tab: Option<(char, char)>,  // First char, then the char repeated to fill the tab
+    pub trail: Option<char>,
+    pub nbsp: Option<char>,
+    pub eol: Option<char>,
+}
+
+impl ListChars {
+    // Parses a value like `tab:> ,trail:-,eol:$`
+    pub fn parse(value: &str) -> Result<ListChars, String> {
+        let mut listchars = ListChars { tab: None, trail: None, nbsp: None, eol: None };
+        for item in value.split(',').filter(|item| !item.is_empty()) {
+            let colon_index = item.find(':').ok_or(format!("Invalid listchars item: {}", item))?;
+            let (name, chars) = (&item[..colon_index], &item[colon_index + 1..]);
+            let chars: Vec<char> = chars.chars().collect();
+            match (name, chars.as_slice()) {
+                ("tab", &[first, fill]) => listchars.tab = Some((first, fill)),
+                ("trail", &[ch]) => listchars.trail = Some(ch),
+                ("nbsp", &[ch]) => listchars.nbsp = Some(ch),
+                ("eol", &[ch]) => listchars.eol = Some(ch),
+                _ => return Err(format!("Invalid listchars item: {}", item)),
+            }
+        }
+        Ok(listchars)
+    }
+}
+
+impl Settings {
+    pub fn new() -> Self {
+        Settings {
+            global: OPTIONS.iter().map(|option| (option.name, option.default_value())).collect(),
+            local: HashMap::new(),
+        }
+    }
+
+    // The value in effect for the current buffer
+    pub fn get(&self, option: &OptionDef) -> &OptionValue {
+        self.local.get(option.name)
+                  .or_else(|| self.global.get(option.name))
+                  .expect("Every option has a global value")
+    }
+
+    // Stores a value which has already been checked. Like Vim, `:set` on a buffer
+    // option also replaces the buffer's local value, and `:setlocal` on a global
+    // option sets the global value.
+    fn store(&mut self, option: &'static OptionDef, value: OptionValue, local: bool) {
+        if local && option.scope == Scope::Buffer {
+            self.local.insert(option.name, value);
+        } else {
+            self.local.remove(option.name);
+            self.global.insert(option.name, value);
+        }
+    }
+
+    // Whether the current buffer has a value of its own for the option
+    pub fn is_local(&self, name: &str) -> bool {
+        self.local.contains_key(name)
+    }
+
+    pub fn set(&mut self, name: &str, value: OptionValue, local: bool) -> Result<(), String> {
+        let option = find_option(name).ok_or(format!("Unknown option: {}", name))?;
+        option.check(&value)?;
+        self.store(option, value, local);
+        Ok(())
+    }
+
+    // Applies a single `:set` argument: `opt`, `noopt`, `invopt`, `opt!`, `opt&`,
+    // `opt=value` or `opt?`. Queries return the text to show the user.
+    pub fn apply(&mut self, arg: &str, local: bool) -> Result<Option<String>, String> {
+        let unknown = |name: &str| format!("Unknown option: {}", name);
+
+        if let Some(eq_index) = arg.find('=') {
+            let (name, value) = (&arg[..eq_index], &arg[eq_index + 1..]);
+            let option = find_option(name).ok_or(unknown(name))?;
+            let value = option.parse(value)?;
+            self.store(option, value, local);
+            return Ok(None);
+        }
+
+        if arg.ends_with('?') {
+            let name = &arg[..arg.len() - 1];
+            let option = find_option(name).ok_or(unknown(name))?;
+            return Ok(Some(self.describe(option)));
+        }
+
+        if arg.ends_with('&') {
+            let name = &arg[..arg.len() - 1];
+            let option = find_option(name).ok_or(unknown(name))?;
+            self.store(option, option.default_value(), local);
+            return Ok(None);
+        }
+
+        let toggled = if arg.ends_with('!') {
+            Some(&arg[..arg.len() - 1])
+        } else if arg.starts_with("inv") {
+            Some(&arg[3..])
+        } else {
+            None
+        };
+        if let Some(name) = toggled {
+            let option = find_option(name).filter(|option| option.is_bool()).ok_or(unknown(name))?;
+            let value = *self.get(option) == OptionValue::Bool(true);
+            self.store(option, OptionValue::Bool(!value), local);
+            return Ok(None);
+        }
+
+        // A bare option name switches a bool on, and shows the value of anything else
+        if let Some(option) = find_option(arg) {
+            if option.is_bool() {
+                self.store(option, OptionValue::Bool(true), local);
+                return Ok(None);
+            }
+            return Ok(Some(self.describe(option)));
+        }
+
+        if arg.starts_with("no") {
+            if let Some(option) = find_option(&arg[2..]).filter(|option| option.is_bool()) {
+                self.store(option, OptionValue::Bool(false), local);
+                return Ok(None);
+            }
+        }
+        Err(unknown(arg))
+    }
+
+    // Formats an option the way `:set opt?` shows it, e.g. `nowrap` or `tabstop=4`
+    pub fn describe(&self, option: &OptionDef) -> String {
+        match *self.get(option) {
+            OptionValue::Bool(true) => option.name.to_string(),
+            OptionValue::Bool(false) => format!("no{}", option.name),
+            ref value => format!("{}={}", option.name, value),
+        }
+    }
+
+    // Descriptions of the options which aren't at their defaults, or all of them
+    pub fn describe_options(&self, include_defaults: bool) -> Vec<String> {
+        OPTIONS.iter()
+               .filter(|option| include_defaults || *self.get(option) != option.default_value())
+               .map(|option| self.describe(option))
+               .collect()
+    }
+
+    fn bool_value(&self, name: &str) -> bool {
+        match *self.get(find_option(name).unwrap()) {
+            OptionValue::Bool(value) => value,
+            _ => unreachable!(),
+        }
+    }
+
+    fn int_value(&self, name: &str) -> usize {
+        match *self.get(find_option(name).unwrap()) {
+            OptionValue::Int(value) => value,
+            _ => unreachable!(),
+        }
+    }
+
+    fn str_value(&self, name: &str) -> &str {
+        match *self.get(find_option(name).unwrap()) {
+            OptionValue::Str(ref value) => value,
+            _ => unreachable!(),
+        }
+    }
+
+    fn list_value(&self, name: &str) -> &[String] {
+        match *self.get(find_option(name).unwrap()) {
+            OptionValue::List(ref items) => items,
+            _ => unreachable!(),
+        }
+    }
+
+    // Break long lines over several display rows
+    pub fn wrap(&self) -> bool {
+        self.bool_value("wrap")
+    }
+
+    // When wrapping, break at word boundaries
+    pub fn linebreak(&self) -> bool {
+        self.bool_value("linebreak")
+    }
+
+    // Shown at the start of each continuation row
+    pub fn showbreak(&self) -> &str {
+        self.str_value("showbreak")
+    }
+
+    // Show line numbers in the gutter
+    pub fn number(&self) -> bool {
+        self.bool_value("number")
+    }
+
+    // Number lines relative to the cursor; hybrid when combined with `number`
+    pub fn relativenumber(&self) -> bool {
+        self.bool_value("relativenumber")
+    }
+
+    // Minimum width of the line numbers, including the space after them
+    pub fn numberwidth(&self) -> usize {
+        self.int_value("numberwidth")
+    }
+
+    // Shade the line the cursor is on
+    pub fn cursorline(&self) -> bool {
+        self.bool_value("cursorline")
+    }
+
+    // Screen columns (from 1) to draw as rulers
+    pub fn colorcolumn(&self) -> Vec<usize> {
+        self.list_value("colorcolumn").iter()
+                                      .filter_map(|column| column.parse::<usize>().ok())
+                                      .collect()
+    }
+
+    // Show whitespace using `listchars`
+    pub fn list(&self) -> bool {
+        self.bool_value("list")
+    }
+
+    pub fn listchars(&self) -> ListChars {
+        ListChars::parse(&self.list_value("listchars").join(",")).expect("listchars is validated when set")
+    }
+
+    // Display width of a tab
+    pub fn tabstop(&self) -> usize {
+        self.int_value("tabstop")
+    }
+
+    // Columns per level of indent for >> and <<, where 0 means use tabstop
+    pub fn effective_shiftwidth(&self) -> usize {
+        match self.int_value("shiftwidth") {
+            0 => self.tabstop(),
+            shiftwidth => shiftwidth,
+        }
+    }
+
+    // Columns Tab and Backspace move by in Insert mode, 0 means off
+    pub fn softtabstop(&self) -> usize {
+        self.int_value("softtabstop")
+    }
+
+    // Insert spaces instead of tabs
+    pub fn expandtab(&self) -> bool {
+        self.bool_value("expandtab")
+    }
+
+    // The keys `<leader>` stands for in key mappings
+    pub fn mapleader(&self) -> &str {
+        self.str_value("mapleader")
+    }
+
+    // Whether a key sequence which might continue is given up waiting on
+    pub fn timeout(&self) -> bool {
+        self.bool_value("timeout")
+    }
+
+    // How long to wait for the rest of a key sequence, in milliseconds
+    pub fn timeoutlen(&self) -> usize {
+        self.int_value("timeoutlen")
+    }
+
+    // `auto` to pick whichever clipboard tool suits the session, or one of
+    // `wl-copy`, `xclip`, `xsel` or `osc52`
+    pub fn clipboardtool(&self) -> &str {
+        self.str_value("clipboardtool")
+    }
+
+    // Line endings to save with: `unix`, `dos` or `mac`
+    pub fn fileformat(&self) -> &str {
+        self.str_value("fileformat")
+    }
+
+    pub fn fileencoding(&self) -> &str {
+        self.str_value("fileencoding")
+    }
+
+    // Start the file with a byte order mark
+    pub fn bomb(&self) -> bool {
+        self.bool_value("bomb")
+    }
+
+    // Make sure the file ends with a newline when saving
+    pub fn fixendofline(&self) -> bool {
+        self.bool_value("fixendofline")
+    }
+
+    pub fn trimtrailingwhitespace(&self) -> bool {
+        self.bool_value("trimtrailingwhitespace")
+    }
+
+    // Seconds between saves of a changed buffer, 0 meaning never
+    pub fn autosave(&self) -> usize {
+        self.int_value("autosave")
+    }
+}
@@ -0,0 +1,100 @@
+// Splitting of logical lines into display rows, shared by the renderer and the
+// display-row motions so that both agree on where each row starts. Rows are
+// measured in display columns, which differ from char indices once tabs are
+// expanded.
+
+// The display column each char of `line` starts at, with tabs expanded up to the
+// next multiple of `tabstop`. There's one more entry than there are chars: the
+// column just after the last char, i.e. the display width of the line.
+pub fn char_columns(line: &[char], tabstop: usize) -> Vec<usize> {
+    let tabstop = tabstop.max(1);
+    let mut columns = Vec::with_capacity(line.len() + 1);
+    let mut column = 0;
+    for &ch in line {
+        columns.push(column);
+        column += if ch == '\t' { tabstop - column % tabstop } else { 1 };
+    }
+    columns.push(column);
+    columns
+}
+
+// The index of the char occupying display `column`, clamped to the last char.
+pub fn char_at_column(columns: &[usize], column: usize) -> usize {
+    let char_count = columns.len() - 1;
+    (0..char_count).rev()
+                   .find(|&index| columns[index] <= column)
+                   .unwrap_or(0)
+}
+
+// The whitespace which fills display columns [from_column, to_column): tabs up to
+// the last tab stop that fits followed by spaces, or only spaces with `expandtab`.
+pub fn whitespace_between(from_column: usize, to_column: usize, tabstop: usize, expandtab: bool) -> String {
+    let tabstop = tabstop.max(1);
+    let mut whitespace = String::new();
+    let mut column = from_column;
+    if !expandtab {
+        while column < to_column && (column / tabstop + 1) * tabstop <= to_column {
+            whitespace.push('\t');
+            column = (column / tabstop + 1) * tabstop;
+        }
+    }
+    while column < to_column {
+        whitespace.push(' ');
+        column += 1;
+    }
+    whitespace
+}
+
+// The line with each tab replaced by the spaces it's displayed as.
+pub fn expand_tabs(line: &[char], columns: &[usize]) -> Vec<char> {
+    let mut expanded = vec![];
+    for (index, &ch) in line.iter().enumerate() {
+        if ch == '\t' {
+            expanded.extend((columns[index]..columns[index + 1]).map(|_| ' '));
+        } else {
+            expanded.push(ch);
+        }
+    }
+    expanded
+}
+
+// Returns the [start, end) column ranges of `line` (excluding its newline, and
+// with tabs already expanded) shown on each display row. Continuation rows lose
+// `continuation_indent` columns to the showbreak marker. When `at_word_boundary`
+// is set, rows are broken after the last whitespace that fits rather than in the
+// middle of a word.
+pub fn wrap_line(line: &[char], width: usize, continuation_indent: usize, at_word_boundary: bool) -> Vec<(usize, usize)> {
+    let mut rows = vec![];
+    let mut start = 0;
+
+    while start < line.len() {
+        let row_width = if rows.is_empty() {
+            width
+        } else {
+            width.saturating_sub(continuation_indent)
+        }.max(1);
+
+        let mut end = (start + row_width).min(line.len());
+        if at_word_boundary && end < line.len() && !line[end].is_whitespace() {
+            let last_space = line[start..end].iter().rposition(|ch| ch.is_whitespace());
+            if let Some(offset) = last_space {
+                end = start + offset + 1;
+            }
+        }
+        rows.push((start, end));
+        start = end;
+    }
+
+    if rows.is_empty() {
+        rows.push((0, 0));
+    }
+    rows
+}
+
+// The index of the row containing display `column`. Positions past the end of
+// the line (i.e. the newline) belong to the last row.
+pub fn row_containing(rows: &[(usize, usize)], column: usize) -> usize {
+    rows.iter()
+        .position(|&(start, end)| column >= start && column < end)
+        .unwrap_or(rows.len() - 1)
+}
@@ -1,23 +1,28 @@
 extern crate syntect;
 extern crate termion;
+extern crate signal_hook;
+extern crate toml;
 
 mod data;
 mod view;
 mod controller; 
 
-use std::fs::File;
+use std::path::Path;
+use std::process;
 use std::time::Duration;
 use std::io::{stdin, stdout};
 
 use termion::screen::*;
 use termion::raw::IntoRawMode;
 
-use syntect::easy::HighlightLines;
 use syntect::highlighting::{Theme, ThemeSet, Style};
 use syntect::parsing::SyntaxSet;
 
 use data::editor_state::{StateApi, EditorState, Mode};
 use data::piece_table::PieceTable;
+use data::io::read_file;
+use data::config::load_config;
+use data::editorconfig;
 use controller::events::InputModeMultiplexer;
 
 
@@ -26,14 +31,12 @@ fn main() {
     
     let file_name = "test_file.py";
 
-    let mut file = File::open(file_name)
-                        .expect("Unable to open file");
-
+    // The file is read once the options are known, as `fileencoding` decides how
     let mut state = EditorState::new(
         String::from(file_name),
         Mode::Navigate,
         0,
-        PieceTable::new(&mut file),
+        PieceTable::from_text(String::new()),
         vec![]
     );
 
@@ -41,10 +44,45 @@ fn main() {
     let syntax_set = SyntaxSet::load_defaults_newlines();
     let theme_set = ThemeSet::load_defaults();
 
-    let theme = theme_set.themes.get("Solarized (dark)").unwrap();
+    let syntax = syntax_set.find_syntax_for_file(file_name)
+                           .ok()
+                           .and_then(|syntax| syntax)
+                           .unwrap_or_else(|| syntax_set.find_syntax_plain_text());
 
-    let syntax = syntax_set.find_syntax_by_extension("py").unwrap();
-    let mut highlighter = HighlightLines::new(syntax, theme);
-    
-    controller::events::event_loop(&mut highlighter, &mut state);
+    // Configuration. Anything wrong with it is reported once the editor is up.
+    let mut config = load_config();
+    config.apply_options(&mut state.settings, &syntax.name.to_lowercase());
+    // .editorconfig files describe the project, so they win over personal preferences
+    match editorconfig::properties_for(Path::new(file_name)) {
+        Ok(properties) => config.errors.extend(editorconfig::apply_properties(&properties, &mut state.settings)),
+        Err(error) => config.errors.push(format!(".editorconfig: {}", error)),
+    }
+    if let Err(error) = read_file(&mut state) {
+        config.errors.push(error);
+    }
+    let leader = state.settings.mapleader().to_string();
+    config.apply_key_mappings(&mut state.keymap, &leader);
+
+    let default_theme = theme_set.themes.get("Solarized (dark)").unwrap().clone();
+    let theme = match config.theme.clone() {
+        None => default_theme,
+        Some(name) => match theme_set.themes.get(&name) {
+            Some(theme) => theme.clone(),
+            None => ThemeSet::get_theme(&name).unwrap_or_else(|_| {
+                config.errors.push(format!("config.toml: unknown theme {}", name));
+                default_theme
+            }),
+        },
+    };
+
+    match config.errors.len() {
+        0 => (),
+        1 => { state.set_status_message(config.errors[0].clone()); },
+        count => { state.set_status_message(format!("{} (and {} more errors)", config.errors[0], count - 1)); },
+    }
+
+    if let Err(error) = controller::events::event_loop(syntax, &theme, &mut state) {
+        eprintln!("Unable to draw to the terminal: {}", error);
+        process::exit(1);
+    }
 }
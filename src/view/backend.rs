@@ -0,0 +1,327 @@
+use std::io::{self, Write, stdout};
+use std::panic;
+
+use termion::{clear, cursor, screen, style};
+use termion::terminal_size;
+
+use view::screen::*;
+
+// Abstracts the device the editor is drawn on. The Renderer only ever talks to a
+// Backend, which means the whole view can be driven without a TTY.
+// Drawing fails only when the terminal can't be written to.
+pub trait Backend {
+    // Size of the drawable area as (width, height) in cells
+    fn size(&self) -> io::Result<(usize, usize)>;
+    fn clear(&mut self) -> io::Result<()>;
+    fn begin_frame(&mut self) -> io::Result<()>;
+    fn draw_runs(&mut self, runs: &[Run]) -> io::Result<()>;
+    // Screen coordinates, indexed from 0
+    fn set_cursor(&mut self, x: usize, y: usize) -> io::Result<()>;
+    fn end_frame(&mut self) -> io::Result<()>;
+    // Escape sequences meant for the terminal rather than the screen, like OSC 52
+    fn write_sequence(&mut self, sequence: &str) -> io::Result<()>;
+}
+
+pub struct TermionBackend<W: Write> {
+    out: W,
+    synchronized_update: bool,
+}
+
+impl<W: Write> TermionBackend<W> {
+    pub fn new(out: W) -> Self {
+        TermionBackend {
+            out,
+            synchronized_update: supports_synchronized_update(),
+        }
+    }
+}
+
+impl<W: Write> Backend for TermionBackend<W> {
+
+    fn size(&self) -> io::Result<(usize, usize)> {
+        let (width, height) = terminal_size()?;
+        Ok((width as usize, height as usize))
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        write!(self.out, "{}", clear::All)
+    }
+
+    fn begin_frame(&mut self) -> io::Result<()> {
+        if self.synchronized_update {
+            write!(self.out, "{}", BEGIN_SYNCHRONIZED_UPDATE)?;
+        }
+        write!(self.out, "{}", cursor::Hide)
+    }
+
+    fn draw_runs(&mut self, runs: &[Run]) -> io::Result<()> {
+        write_runs(&mut self.out, runs)
+    }
+
+    fn set_cursor(&mut self, x: usize, y: usize) -> io::Result<()> {
+        write!(self.out, "{}", cursor::Goto(x as u16 + 1, y as u16 + 1))
+    }
+
+    fn end_frame(&mut self) -> io::Result<()> {
+        write!(self.out, "{}", cursor::Show)?;
+        if self.synchronized_update {
+            write!(self.out, "{}", END_SYNCHRONIZED_UPDATE)?;
+        }
+        self.out.flush()
+    }
+
+    fn write_sequence(&mut self, sequence: &str) -> io::Result<()> {
+        write!(self.out, "{}", sequence)
+    }
+}
+
+// There's no one to tell if the terminal can't be restored on the way out
+impl<W: Write> Drop for TermionBackend<W> {
+    fn drop(&mut self) {
+        let _ = write!(self.out, "{}", cursor::Show).and_then(|_| self.out.flush());
+    }
+}
+
+// The default panic hook prints its message straight away, which would be lost on
+// the alternate screen. Switch back to the main screen and show the cursor first;
+// unwinding then drops the raw terminal, which restores cooked mode.
+pub fn restore_terminal_on_panic() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let mut out = stdout();
+        let _ = write!(out, "{}{}{}", screen::ToMainScreen, cursor::Show, style::Reset).and_then(|_| out.flush());
+        default_hook(info);
+    }));
+}
+
+// A virtual terminal which keeps the drawn cells in memory, so tests can make
+// assertions about exactly what would have appeared on screen.
+#[cfg(test)]
+pub struct MemoryBackend {
+    screen: ScreenBuffer,
+    cursor: (usize, usize),
+    frames_drawn: usize,
+    sequences: Vec<String>,
+}
+
+#[cfg(test)]
+impl MemoryBackend {
+    pub fn new(width: usize, height: usize) -> Self {
+        MemoryBackend {
+            screen: ScreenBuffer::new(width, height),
+            cursor: (0, 0),
+            frames_drawn: 0,
+            sequences: vec![],
+        }
+    }
+
+    // Simulates the terminal being resized. Like a real terminal, the contents
+    // are lost and need to be redrawn.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.screen = ScreenBuffer::new(width, height);
+    }
+
+    pub fn screen(&self) -> &ScreenBuffer {
+        &self.screen
+    }
+
+    pub fn cursor(&self) -> (usize, usize) {
+        self.cursor
+    }
+
+    pub fn frames_drawn(&self) -> usize {
+        self.frames_drawn
+    }
+
+    // The escape sequences written for the terminal itself, in order
+    pub fn sequences(&self) -> &[String] {
+        &self.sequences
+    }
+
+    pub fn line(&self, y: usize) -> String {
+        self.screen.row_text(y)
+    }
+
+    // The whole screen as text, one row per line with trailing spaces removed,
+    // suitable for comparing against a snapshot.
+    pub fn snapshot(&self) -> String {
+        (0..self.screen.height()).map(|y| self.line(y).trim_right().to_string())
+                                 .collect::<Vec<String>>()
+                                 .join("\n")
+    }
+}
+
+#[cfg(test)]
+impl Backend for MemoryBackend {
+
+    fn size(&self) -> io::Result<(usize, usize)> {
+        Ok((self.screen.width(), self.screen.height()))
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        let (width, height) = self.size()?;
+        self.screen = ScreenBuffer::new(width, height);
+        Ok(())
+    }
+
+    fn begin_frame(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn draw_runs(&mut self, runs: &[Run]) -> io::Result<()> {
+        for run in runs {
+            for (offset, cell) in run.cells.iter().enumerate() {
+                self.screen.set(run.x + offset, run.y, *cell);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_cursor(&mut self, x: usize, y: usize) -> io::Result<()> {
+        self.cursor = (x, y);
+        Ok(())
+    }
+
+    fn end_frame(&mut self) -> io::Result<()> {
+        self.frames_drawn += 1;
+        Ok(())
+    }
+
+    fn write_sequence(&mut self, sequence: &str) -> io::Result<()> {
+        self.sequences.push(sequence.to_string());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use syntect::highlighting::ThemeSet;
+    use syntect::parsing::SyntaxSet;
+
+    use data::editor_state::{EditorState, Mode};
+    use data::piece_table::PieceTable;
+    use view::terminal::Renderer;
+    use super::{Backend, MemoryBackend};
+
+    // A renderer which draws the text plain, without any highlighting
+    fn renderer() -> Renderer {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let theme_set = ThemeSet::load_defaults();
+        Renderer::new(syntax_set.find_syntax_plain_text(), &theme_set.themes["base16-ocean.dark"])
+    }
+
+    fn render(backend: &mut MemoryBackend, renderer: &mut Renderer, state: &mut EditorState) {
+        renderer.render(backend, state).unwrap();
+    }
+
+    fn state(text: &str) -> EditorState {
+        EditorState::new(String::from("test.txt"), Mode::Navigate, 0, PieceTable::from_text(text.to_string()), vec![])
+    }
+
+    #[test]
+    fn draws_the_text_and_cursor() {
+        let mut backend = MemoryBackend::new(20, 4);
+        let mut state = state("abc\ndef\n");
+        state.cursor_index = 5;
+        render(&mut backend, &mut renderer(), &mut state);
+        assert_eq!(backend.snapshot(), "  1  abc\n  2  def\n\nEditing: test.txt |");
+        assert_eq!(backend.cursor(), (6, 1));
+        assert_eq!(backend.frames_drawn(), 1);
+    }
+
+    #[test]
+    fn only_what_changed_is_redrawn() {
+        let mut backend = MemoryBackend::new(20, 4);
+        let mut renderer = renderer();
+        let mut state = state("abc\ndef\n");
+        render(&mut backend, &mut renderer, &mut state);
+        // Cells written over behind the renderer's back stay as they are, as the
+        // frame drawn on top of them is no different from the last
+        backend.clear().unwrap();
+        state.cursor_index = 4;
+        render(&mut backend, &mut renderer, &mut state);
+        assert_eq!(backend.snapshot(), "\n\n\n");
+        assert_eq!(backend.cursor(), (5, 1));
+
+        renderer.clear_screen(&mut backend).unwrap();
+        render(&mut backend, &mut renderer, &mut state);
+        assert_eq!(backend.line(1), "  2  def            ");
+        assert_eq!(backend.frames_drawn(), 3);
+    }
+
+    #[test]
+    fn a_resized_screen_is_drawn_again() {
+        let mut backend = MemoryBackend::new(20, 4);
+        let mut renderer = renderer();
+        let mut state = state("one\ntwo\nthree\n");
+        render(&mut backend, &mut renderer, &mut state);
+        backend.resize(12, 3);
+        render(&mut backend, &mut renderer, &mut state);
+        assert_eq!(backend.screen().width(), 12);
+        assert_eq!(backend.snapshot(), "  1  one\n  2  two\nEditing: tes");
+    }
+
+    #[test]
+    fn change_markers_without_line_numbers() {
+        let mut backend = MemoryBackend::new(20, 4);
+        let mut renderer = renderer();
+        let mut state = state("abc\ndef\n");
+        state.settings.apply("nonumber", false).unwrap();
+        render(&mut backend, &mut renderer, &mut state);
+        assert_eq!(backend.line(0), format!("{:20}", "abc"));
+        state.replace_text(0, 3, "xyz");
+        render(&mut backend, &mut renderer, &mut state);
+        assert_eq!(backend.snapshot(), "~xyz\n def\n\nEditing: test.txt |");
+    }
+
+    #[test]
+    fn terminal_sequences_are_written_once() {
+        let mut backend = MemoryBackend::new(20, 4);
+        let mut renderer = renderer();
+        let mut state = state("abc\n");
+        state.terminal_sequences.push(String::from("\x1b]52;c;YWJj\x07"));
+        render(&mut backend, &mut renderer, &mut state);
+        render(&mut backend, &mut renderer, &mut state);
+        assert_eq!(backend.sequences(), ["\x1b]52;c;YWJj\x07"]);
+    }
+
+    #[test]
+    fn lines_scrolled_past_are_still_highlighted() {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let theme_set = ThemeSet::load_defaults();
+        let python = || Renderer::new(syntax_set.find_syntax_by_extension("py").unwrap(), &theme_set.themes["Solarized (dark)"]);
+        let text = "\"\"\"doc\na\nb\nc\nd\ne\nf\nstill doc\n\"\"\"\n";
+        let colours = |backend: &MemoryBackend, y: usize| -> Vec<_> {
+            backend.screen().diff(None)[y].cells.iter().map(|cell| cell.style.fg).collect()
+        };
+        let unscrolled = |state: &mut EditorState| {
+            let mut backend = MemoryBackend::new(20, 12);
+            state.viewport.top_line = 0;
+            render(&mut backend, &mut python(), state);
+            colours(&backend, 7)
+        };
+
+        // The line stays inside the docstring however far down the screen starts,
+        // and on every frame drawn
+        let mut state = state(text);
+        let in_docstring = unscrolled(&mut state);
+        let mut backend = MemoryBackend::new(20, 3);
+        let mut renderer = python();
+        state.cursor_index = text.find("still").unwrap();
+        for _ in 0..2 {
+            renderer.invalidate();
+            render(&mut backend, &mut renderer, &mut state);
+            assert!(state.viewport.top_line > 1);
+            assert_eq!(colours(&backend, 7 - state.viewport.top_line), in_docstring);
+        }
+
+        // Ending the docstring above the screen leaves the line outside it
+        state.replace_text(text.find('b').unwrap(), 1, "\"\"\"");
+        let outside = unscrolled(&mut state);
+        assert!(outside != in_docstring);
+        state.cursor_index = state.get_text().find("still").unwrap();
+        render(&mut backend, &mut renderer, &mut state);
+        assert!(state.viewport.top_line > 1);
+        assert_eq!(colours(&backend, 7 - state.viewport.top_line), outside);
+    }
+}
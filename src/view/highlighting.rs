@@ -0,0 +1,50 @@
+use syntect::highlighting::{Highlighter, HighlightState, HighlightIterator, Style};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxDefinition};
+
+// Where syntax highlighting had got to at the start of each line. Each line is
+// highlighted from the state the one before it left, so keeping them means a frame
+// only highlights the lines on screen, along with any above them which haven't
+// been yet. The states are kept with the lines they were worked out from, and
+// those after a line which has been edited since are thrown away.
+pub struct HighlightCache {
+    line_states: Vec<(ParseState, HighlightState)>,  // The state at the start of each line, one more than there are lines
+    lines: Vec<String>,  // The lines highlighted so far
+}
+
+impl HighlightCache {
+    pub fn new(syntax: &SyntaxDefinition, highlighter: &Highlighter) -> Self {
+        HighlightCache {
+            line_states: vec![(ParseState::new(syntax), HighlightState::new(highlighter, ScopeStack::new()))],
+            lines: vec![],
+        }
+    }
+
+    // Throws away the states which follow the first line to differ from those given
+    pub fn forget_edited(&mut self, lines: &[String]) {
+        let unchanged = self.lines.iter().zip(lines.iter()).take_while(|&(old, new)| old == new).count();
+        self.lines.truncate(unchanged);
+        self.line_states.truncate(unchanged + 1);
+    }
+
+    // Highlights line `y`, after any lines before it which haven't been yet
+    pub fn highlight<'b>(&mut self, highlighter: &Highlighter, lines: &'b [String], y: usize) -> Vec<(Style, &'b str)> {
+        while self.lines.len() < y {
+            let next = self.lines.len();
+            self.highlight_from_state(highlighter, &lines[next], next);
+        }
+        self.highlight_from_state(highlighter, &lines[y], y)
+    }
+
+    // Highlights the line from the state it starts in, keeping the state the next
+    // line starts in if it isn't known yet
+    fn highlight_from_state<'b>(&mut self, highlighter: &Highlighter, line: &'b str, y: usize) -> Vec<(Style, &'b str)> {
+        let (mut parse_state, mut highlight_state) = self.line_states[y].clone();
+        let ops = parse_state.parse_line(line);
+        let ranges = HighlightIterator::new(&mut highlight_state, &ops[..], line, highlighter).collect();
+        if y == self.lines.len() {
+            self.lines.push(line.to_string());
+            self.line_states.push((parse_state, highlight_state));
+        }
+        ranges
+    }
+}
@@ -1,2 +1,5 @@
+pub mod backend;
+pub mod highlighting;
 pub mod sandbox;
+pub mod screen;
 pub mod terminal;
\ No newline at end of file
@@ -0,0 +1,204 @@
+use std::io::{self, Write};
+
+use termion::{style, cursor, color};
+
+// An in-memory grid of styled cells. Each frame is drawn into a fresh
+// ScreenBuffer, which is then compared against the previous frame so that
+// only the cells which actually changed are sent to the terminal.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct CellStyle {
+    pub fg: Option<Rgb>,  // None means the terminal's default colour
+    pub bg: Option<Rgb>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+impl CellStyle {
+    pub fn new(fg: Rgb, bg: Rgb) -> Self {
+        CellStyle { fg: Some(fg), bg: Some(bg), ..CellStyle::default() }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: char,
+    pub style: CellStyle,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell { ch: ' ', style: CellStyle::default() }
+    }
+}
+
+// A horizontal run of changed cells, starting at (x, y), indexed from 0.
+#[derive(Debug, PartialEq)]
+pub struct Run {
+    pub x: usize,
+    pub y: usize,
+    pub cells: Vec<Cell>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScreenBuffer {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+}
+
+impl ScreenBuffer {
+
+    pub fn new(width: usize, height: usize) -> Self {
+        ScreenBuffer {
+            width,
+            height,
+            cells: vec![Cell::default(); width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut Cell> {
+        if x < self.width && y < self.height {
+            self.cells.get_mut(y * self.width + x)
+        } else {
+            None
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, cell: Cell) {
+        if x < self.width && y < self.height {
+            self.cells[y * self.width + x] = cell;
+        }
+    }
+
+    // Writes the string starting at (x, y), clipping at the right edge of the
+    // buffer. Returns the column following the last character written.
+    pub fn put_str(&mut self, x: usize, y: usize, text: &str, style: CellStyle) -> usize {
+        let mut col = x;
+        for ch in text.chars() {
+            if col >= self.width {
+                break;
+            }
+            self.set(col, y, Cell { ch, style });
+            col += 1;
+        }
+        col
+    }
+
+    // Fills the cells of row y from column x to the right edge.
+    pub fn fill_row(&mut self, x: usize, y: usize, cell: Cell) {
+        for col in x..self.width {
+            self.set(col, y, cell);
+        }
+    }
+
+    #[cfg(test)]
+    pub fn row_text(&self, y: usize) -> String {
+        self.cells[y * self.width..(y + 1) * self.width].iter()
+                                                      .map(|cell| cell.ch)
+                                                      .collect()
+    }
+
+    // Returns the runs of cells which differ between this buffer and the previous
+    // frame. If there was no previous frame, or it was a different size, every
+    // cell is considered changed.
+    pub fn diff(&self, previous: Option<&ScreenBuffer>) -> Vec<Run> {
+        let previous = previous.filter(|prev| prev.width == self.width && prev.height == self.height);
+        let mut runs = vec![];
+
+        for y in 0..self.height {
+            let mut current_run: Option<Run> = None;
+            for x in 0..self.width {
+                let cell = self.cells[y * self.width + x];
+                let changed = match previous {
+                    Some(prev) => prev.cells[y * self.width + x] != cell,
+                    None => true,
+                };
+
+                if changed {
+                    current_run.get_or_insert_with(|| Run { x, y, cells: vec![] })
+                               .cells.push(cell);
+                } else if let Some(run) = current_run.take() {
+                    runs.push(run);
+                }
+            }
+            if let Some(run) = current_run.take() {
+                runs.push(run);
+            }
+        }
+        runs
+    }
+}
+
+// Synchronized output (DEC private mode 2026) asks the terminal to hold off
+// painting until the whole frame has arrived. Terminals which don't understand
+// the mode ignore it, but the Linux console and dumb terminals are skipped anyway.
+pub const BEGIN_SYNCHRONIZED_UPDATE: &str = "\x1b[?2026h";
+pub const END_SYNCHRONIZED_UPDATE: &str = "\x1b[?2026l";
+
+pub fn supports_synchronized_update() -> bool {
+    match ::std::env::var("TERM") {
+        Ok(ref term) if term == "linux" || term == "dumb" => false,
+        _ => true,
+    }
+}
+
+// Emits the given runs, only moving the cursor when a run doesn't start where
+// the previous one ended, and only changing style when it differs from the last
+// cell written.
+pub fn write_runs<W: Write>(out: &mut W, runs: &[Run]) -> io::Result<()> {
+    let mut cursor_at: Option<(usize, usize)> = None;
+    let mut current_style: Option<CellStyle> = None;
+
+    for run in runs {
+        if cursor_at != Some((run.x, run.y)) {
+            write!(out, "{}", cursor::Goto(run.x as u16 + 1, run.y as u16 + 1))?;
+        }
+
+        for cell in run.cells.iter() {
+            if current_style != Some(cell.style) {
+                write_style(out, &cell.style)?;
+                current_style = Some(cell.style);
+            }
+            write!(out, "{}", cell.ch)?;
+        }
+        cursor_at = Some((run.x + run.cells.len(), run.y));
+    }
+
+    if current_style.is_some() {
+        write!(out, "{}", style::Reset)?;
+    }
+    Ok(())
+}
+
+fn write_style<W: Write>(out: &mut W, cell_style: &CellStyle) -> io::Result<()> {
+    write!(out, "{}", style::Reset)?;
+    if cell_style.bold {
+        write!(out, "{}", style::Bold)?;
+    }
+    if cell_style.italic {
+        write!(out, "{}", style::Italic)?;
+    }
+    if cell_style.underline {
+        write!(out, "{}", style::Underline)?;
+    }
+    if let Some(Rgb(r, g, b)) = cell_style.fg {
+        write!(out, "{}", color::Fg(color::Rgb(r, g, b)))?;
+    }
+    if let Some(Rgb(r, g, b)) = cell_style.bg {
+        write!(out, "{}", color::Bg(color::Rgb(r, g, b)))?;
+    }
+    Ok(())
+}
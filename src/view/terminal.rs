@@ -1,83 +1,435 @@
 use std::cmp;
+use std::io;
 
-use std::io::Write;
-
-use syntect::easy::HighlightLines;
-use syntect::highlighting::Style;
-use syntect::util::as_24_bit_terminal_escaped;
-
-use termion::{clear, style, cursor, color};
-use termion::terminal_size;
+use syntect::highlighting::{Highlighter, Style, Theme, Color};
+use syntect::parsing::SyntaxDefinition;
 
 use data::editor_state::{StateApi, EditorState};
+use data::diff::LineChange;
+use data::piece_table::Source;
+use data::settings::ListChars;
+use data::wrap::row_containing;
+use view::backend::Backend;
+use view::highlighting::HighlightCache;
+use view::screen::*;
 
-const GUTTER_WIDTH: usize = 4;
+// Columns between the digits of the line numbers and the text: a space, then the
+// change markers
 const GUTTER_RIGHT_MARGIN: usize = 2;
 
+const GUTTER_FG: Rgb = Rgb(95, 110, 109);
+const GUTTER_BG: Rgb = Rgb(8, 31, 40);
+
+const ADDED_FG: Rgb = Rgb(133, 153, 0);
+const MODIFIED_FG: Rgb = Rgb(181, 137, 0);
+const DELETED_FG: Rgb = Rgb(220, 50, 47);
+const ADD_BUFFER_TINT: Rgb = Rgb(20, 60, 40);
+const COLOR_COLUMN_BG: Rgb = GUTTER_BG;
+
+// Colours taken from the syntax highlighting theme for the parts of the UI which
+// aren't syntax highlighted.
+#[derive(Clone, Copy, Debug)]
+pub struct ThemeColours {
+    pub line_highlight: Rgb,
+    pub invisibles: Rgb,
+    pub selection: Rgb,
+}
+
+impl ThemeColours {
+    pub fn from_theme(theme: &Theme) -> Self {
+        let to_rgb = |colour: Color| Rgb(colour.r, colour.g, colour.b);
+        let defaults = ThemeColours::default();
+        ThemeColours {
+            line_highlight: theme.settings.line_highlight.map(&to_rgb).unwrap_or(defaults.line_highlight),
+            invisibles: theme.settings.guide.map(&to_rgb).unwrap_or(defaults.invisibles),
+            selection: theme.settings.selection.map(&to_rgb).unwrap_or(defaults.selection),
+        }
+    }
+}
+
+impl Default for ThemeColours {
+    fn default() -> Self {
+        ThemeColours {
+            line_highlight: Rgb(7, 54, 66),
+            invisibles: GUTTER_FG,
+            selection: Rgb(38, 79, 120),
+        }
+    }
+}
+
 
 // TODO: Encapsulate all editor state into an easily renderable struct
 // Pass state via this object instead of a Vec<&str>
 // The file is represented internally as a piece table, but presented
 // to the terminal client as a vector of string slices.
 
-pub fn clear_screen<W: Write>(out: &mut W) {
-    write!(out, "{}", clear::All);
+// Holds on to the last frame sent to the backend, so that each call to render
+// only needs to emit the cells that changed since, and to where syntax
+// highlighting had got to on each line.
+pub struct Renderer {
+    previous_frame: Option<ScreenBuffer>,
+    colours: ThemeColours,
+    theme: Theme,
+    highlight_cache: HighlightCache,
 }
 
-pub fn render<W: Write>(out: &mut W, highlighter: &mut HighlightLines, state: &EditorState) {
-    let (width, height) = terminal_size().unwrap();
-    draw_gutter_and_lines(out, highlighter, state);
-    draw_status_line(out, height as usize, width as usize, state);
-    draw_cursor(out, state);
-    out.flush();
+impl Renderer {
+    pub fn new(syntax: &SyntaxDefinition, theme: &Theme) -> Self {
+        Renderer {
+            previous_frame: None,
+            colours: ThemeColours::from_theme(theme),
+            theme: theme.clone(),
+            highlight_cache: HighlightCache::new(syntax, &Highlighter::new(theme)),
+        }
+    }
+
+    pub fn clear_screen<B: Backend>(&mut self, backend: &mut B) -> io::Result<()> {
+        self.invalidate();
+        backend.clear()
+    }
+
+    // Forces the next render to redraw every cell, e.g. after the screen was cleared.
+    pub fn invalidate(&mut self) {
+        self.previous_frame = None;
+    }
+
+    pub fn render<B: Backend>(&mut self, backend: &mut B, state: &mut EditorState) -> io::Result<()> {
+        let (width, height) = backend.size()?;
+        let mut frame = ScreenBuffer::new(width, height);
+
+        let gutter = Gutter::for_state(state);
+
+        // The bottom row of the terminal is reserved for the status line
+        state.scroll_to_cursor(width.saturating_sub(gutter.text_start_column()), height.saturating_sub(1));
+
+        let highlighter = Highlighter::new(&self.theme);
+        let (cursor_x, cursor_y) = draw_gutter_and_lines(&mut frame, &highlighter, &mut self.highlight_cache, state, &gutter, &self.colours);
+        draw_panel(&mut frame, state);
+        draw_status_line(&mut frame, state);
+
+        let runs = frame.diff(self.previous_frame.as_ref());
+        for sequence in state.terminal_sequences.drain(..) {
+            backend.write_sequence(&sequence)?;
+        }
+        backend.begin_frame()?;
+        backend.draw_runs(&runs)?;
+        backend.set_cursor(cursor_x, cursor_y)?;
+        backend.end_frame()?;
+
+        self.previous_frame = Some(frame);
+        Ok(())
+    }
 }
 
-fn draw_gutter_and_lines<W: Write>(out: &mut W, highlighter: &mut HighlightLines, state: &EditorState) {
+// Draws the visible lines, returning the screen position of the cursor.
+fn draw_gutter_and_lines(
+    frame: &mut ScreenBuffer,
+    highlighter: &Highlighter,
+    highlight_cache: &mut HighlightCache,
+    state: &EditorState,
+    gutter: &Gutter,
+    colours: &ThemeColours
+) -> (usize, usize) {
     let lines = state.get_editor_lines();
-    let last_visible_line_index = cmp::min(lines.len(), terminal_size().unwrap().1 as usize);
-    let visible_lines = lines[..last_visible_line_index].into_iter();
-
-    write!(out, "{}", cursor::Hide);
-
-    for (y, line) in visible_lines.enumerate() {
-        draw_gutter_for_line_number(out, y + 1);
-        let ranges: Vec<(Style, &str)> = highlighter.highlight(line);
-        let line = as_24_bit_terminal_escaped(&ranges[..], false);
-        write!(out, "{}", line);
-    }
-    write!(out, "{}{}", clear::AfterCursor, cursor::Show);
-}
-
-fn draw_gutter_for_line_number<W: Write>(out: &mut W, line_number: usize) {
-    let total_gutter_offset = GUTTER_WIDTH + GUTTER_RIGHT_MARGIN;
-    write!(
-        out,
-        "{}{}{}{}{}{}{}",
-        cursor::Goto(1, line_number as u16),
-        clear::CurrentLine,
-        color::Fg(color::Rgb(95, 110, 109)),
-        color::Bg(color::Rgb(8, 31, 40)),
-        format!("{:>width$} ", line_number, width=GUTTER_WIDTH - 1),
-        style::Reset,
-        cursor::Goto(total_gutter_offset as u16, line_number as u16)
-    );
+    let settings = &state.settings;
+    let (wrap, list, listchars, colorcolumn) = (settings.wrap(), settings.list(), settings.listchars(), settings.colorcolumn());
+    let text_rows = state.viewport.text_height;
+    let cursor_pos = state.get_cursor_position();
+    let cursor_column = state.get_cursor_column();
+    let showbreak_style = CellStyle { fg: Some(GUTTER_FG), ..CellStyle::default() };
+    let invisibles_style = CellStyle { fg: Some(colours.invisibles), ..CellStyle::default() };
+
+    let text_start_column = gutter.text_start_column();
+    let line_sources = if state.tint_added_text { state.get_line_sources() } else { vec![] };
+    let selected_spans = state.selected_spans();
+    let mut cursor_screen_pos = (text_start_column, 0);
+    highlight_cache.forget_edited(&lines);
+    let mut y = 0;
+    for (line_index, line) in lines.iter().enumerate().skip(state.viewport.top_line) {
+        if y >= text_rows {
+            break;
+        }
+
+        let mut cells = styled_cells(&highlight_cache.highlight(highlighter, &lines, line_index));
+        if let Some(sources) = line_sources.get(line_index) {
+            tint_added_cells(&mut cells, sources);
+        }
+        if list {
+            show_whitespace(&mut cells, &listchars, invisibles_style);
+        }
+        let selected = selected_spans.iter().find(|span| span.line == line_index);
+        if let Some(span) = selected {
+            select_cells(&mut cells, span.start, span.end, colours.selection);
+        }
+        let selects_line_break = selected.map_or(false, |span| span.end > cells.len());
+        let tab_chars = if list { listchars.tab } else { None };
+        let cells = expand_tab_cells(&cells, &state.line_columns(line), tab_chars, invisibles_style);
+
+        let rows = state.display_rows(line);
+        let cursor_row = if line_index == cursor_pos.y { Some(row_containing(&rows, cursor_column)) } else { None };
+
+        for (row_index, &(start, end)) in rows.iter().enumerate() {
+            if y >= text_rows {
+                break;
+            }
+
+            // Only the first display row of a line is numbered
+            let mut x = text_start_column;
+            if row_index == 0 {
+                gutter.draw(frame, y, Some(line_index), cursor_pos.y);
+            } else {
+                gutter.draw(frame, y, None, cursor_pos.y);
+                x = frame.put_str(x, y, settings.showbreak(), showbreak_style);
+            }
+
+            let start = if wrap { start } else { cmp::min(start + state.viewport.left_column, end) };
+            if cursor_row == Some(row_index) {
+                cursor_screen_pos = (x + cursor_column - start, y);
+            }
+            for cell in cells[start..end].iter() {
+                frame.set(x, y, *cell);
+                x += 1;
+            }
+            if list && row_index == rows.len() - 1 {
+                if let Some(eol) = listchars.eol {
+                    frame.set(x, y, Cell { ch: eol, style: invisibles_style });
+                }
+            }
+            // A selected line break shows as one selected cell past the end of the line
+            if selects_line_break && row_index == rows.len() - 1 {
+                if let Some(cell) = frame.get_mut(x, y) {
+                    cell.style.bg = Some(colours.selection);
+                }
+            }
+
+            // Cursor line and colour columns are layered beneath whatever background
+            // the text already has
+            if settings.cursorline() && line_index == cursor_pos.y {
+                for col in text_start_column..frame.width() {
+                    set_default_bg(frame, col, y, colours.line_highlight);
+                }
+            }
+            let scrolled_by = if wrap { 0 } else { state.viewport.left_column };
+            for &column in colorcolumn.iter().filter(|&&column| column > scrolled_by) {
+                if let Some(cell) = frame.get_mut(text_start_column + column - scrolled_by - 1, y) {
+                    cell.style.bg = Some(COLOR_COLUMN_BG);
+                }
+            }
+            y += 1;
+        }
+    }
+    cursor_screen_pos
 }
 
-fn draw_status_line<W: Write>(out: &mut W, term_height: usize, term_width: usize, state: &EditorState) {
+// Flattens syntect's highlighted ranges into one styled cell per character,
+// leaving out the trailing newline.
+fn styled_cells(ranges: &[(Style, &str)]) -> Vec<Cell> {
+    let mut cells = vec![];
+    for &(ref style, text) in ranges.iter() {
+        let fg = style.foreground;
+        let cell_style = CellStyle {
+            fg: Some(Rgb(fg.r, fg.g, fg.b)),
+            ..CellStyle::default()
+        };
+        cells.extend(text.chars().filter(|&ch| ch != '\n').map(|ch| Cell { ch, style: cell_style }));
+    }
+    cells
+}
+
+fn set_default_bg(frame: &mut ScreenBuffer, x: usize, y: usize, bg: Rgb) {
+    if let Some(cell) = frame.get_mut(x, y) {
+        if cell.style.bg.is_none() {
+            cell.style.bg = Some(bg);
+        }
+    }
+}
+
+// Gives the selected chars the selection background, in place of any they had
+// while keeping their syntax colours.
+fn select_cells(cells: &mut [Cell], start: usize, end: usize, bg: Rgb) {
+    let end = cmp::min(end, cells.len());
+    for cell in cells[cmp::min(start, end)..end].iter_mut() {
+        cell.style.bg = Some(bg);
+    }
+}
+
+// Replaces trailing spaces and non-breaking spaces with their `listchars`. Tabs
+// are dealt with when they're expanded.
+fn show_whitespace(cells: &mut [Cell], listchars: &ListChars, style: CellStyle) {
+    let trailing_from = cells.iter().rposition(|cell| cell.ch != ' ').map_or(0, |index| index + 1);
+    for (index, cell) in cells.iter_mut().enumerate() {
+        let replacement = match cell.ch {
+            '\u{a0}' => listchars.nbsp,
+            ' ' if index >= trailing_from => listchars.trail,
+            _ => None,
+        };
+        if let Some(ch) = replacement {
+            *cell = Cell { ch, style: CellStyle { bg: cell.style.bg, ..style } };
+        }
+    }
+}
+
+// Expands each tab to the cells it covers on screen, which are either blank or,
+// with `:set list`, drawn using the tab `listchars`.
+fn expand_tab_cells(cells: &[Cell], columns: &[usize], tab_chars: Option<(char, char)>, style: CellStyle) -> Vec<Cell> {
+    let mut expanded = Vec::with_capacity(cells.len());
+    for (index, cell) in cells.iter().enumerate() {
+        if cell.ch != '\t' {
+            expanded.push(*cell);
+            continue;
+        }
+
+        let width = columns[index + 1] - columns[index];
+        for offset in 0..width {
+            expanded.push(match tab_chars {
+                Some((first, fill)) => Cell {
+                    ch: if offset == 0 { first } else { fill },
+                    style: CellStyle { bg: cell.style.bg, ..style }
+                },
+                None => Cell { ch: ' ', style: cell.style },
+            });
+        }
+    }
+    expanded
+}
+
+// Gives chars which were typed this session (i.e. that live in the add buffer) a
+// different background.
+fn tint_added_cells(cells: &mut [Cell], sources: &[Source]) {
+    for (cell, source) in cells.iter_mut().zip(sources.iter()) {
+        if *source == Source::Add {
+            cell.style.bg = Some(ADD_BUFFER_TINT);
+        }
+    }
+}
+
+// How line numbers are shown, based on the `number` and `relativenumber` options.
+#[derive(PartialEq)]
+enum LineNumbers {
+    Hidden,
+    Absolute,
+    Relative,
+    Hybrid,  // Absolute on the cursor line, relative everywhere else
+}
+
+struct Gutter {
+    line_numbers: LineNumbers,
+    digits: usize,
+    changes: Vec<Option<LineChange>>,  // Shown in the margin between the numbers and the text
+    markers: bool,  // Whether any line has a change marker, which needs a column even without numbers
+}
+
+impl Gutter {
+    fn for_state(state: &EditorState) -> Self {
+        let line_numbers = match (state.settings.number(), state.settings.relativenumber()) {
+            (false, false) => LineNumbers::Hidden,
+            (true, false) => LineNumbers::Absolute,
+            (false, true) => LineNumbers::Relative,
+            (true, true) => LineNumbers::Hybrid,
+        };
+        let line_count = state.get_editor_lines().len();
+        let changes = state.get_line_changes();
+        Gutter {
+            line_numbers,
+            // Room for `numberwidth` less the space after the number, growing as the
+            // file gets longer
+            digits: cmp::max(line_count.to_string().len(), state.settings.numberwidth().saturating_sub(1)),
+            markers: changes.iter().any(Option::is_some),
+            changes,
+        }
+    }
+
+    // Width of the numbered part of the gutter, including the space after the number.
+    fn width(&self) -> usize {
+        if self.line_numbers == LineNumbers::Hidden { 0 } else { self.digits + 1 }
+    }
+
+    // Column (indexed from 0) at which the text of each line starts
+    fn text_start_column(&self) -> usize {
+        if self.width() == 0 && !self.markers { 0 } else { self.width() + GUTTER_RIGHT_MARGIN - 1 }
+    }
+
+    // Draws the gutter for row y. Continuation rows of wrapped lines pass None
+    // as the line index and get an empty gutter.
+    fn draw(&self, frame: &mut ScreenBuffer, y: usize, line_index: Option<usize>, cursor_line: usize) {
+        if self.line_numbers != LineNumbers::Hidden {
+            self.draw_line_number(frame, y, line_index, cursor_line);
+        }
+
+        let change = line_index.and_then(|line_index| self.changes.get(line_index).cloned()).and_then(|change| change);
+        if let Some(change) = change {
+            let (marker, fg) = match change {
+                LineChange::Added => ('+', ADDED_FG),
+                LineChange::Modified => ('~', MODIFIED_FG),
+                LineChange::DeletedBelow => ('_', DELETED_FG),
+                LineChange::DeletedAbove => ('‾', DELETED_FG),
+            };
+            frame.set(self.width(), y, Cell { ch: marker, style: CellStyle { fg: Some(fg), ..CellStyle::default() } });
+        }
+    }
+
+    fn draw_line_number(&self, frame: &mut ScreenBuffer, y: usize, line_index: Option<usize>, cursor_line: usize) {
+        let label = match line_index {
+            None => format!("{:>width$} ", "", width=self.digits),
+            Some(line_index) => {
+                let distance = if line_index > cursor_line { line_index - cursor_line } else { cursor_line - line_index };
+                match self.line_numbers {
+                    LineNumbers::Relative => format!("{:>width$} ", distance, width=self.digits),
+                    LineNumbers::Hybrid if distance == 0 => format!("{:<width$} ", line_index + 1, width=self.digits),
+                    LineNumbers::Hybrid => format!("{:>width$} ", distance, width=self.digits),
+                    _ => format!("{:>width$} ", line_index + 1, width=self.digits),
+                }
+            }
+        };
+        frame.put_str(0, y, &label, CellStyle::new(GUTTER_FG, GUTTER_BG));
+    }
+}
+
+// Draws the output panel, if open, over the bottom of the text area.
+fn draw_panel(frame: &mut ScreenBuffer, state: &EditorState) {
+    let panel_lines = match state.get_panel() {
+        Some(lines) => lines,
+        None => return,
+    };
+
+    let text_rows = frame.height().saturating_sub(1);
+    let rows = cmp::min(panel_lines.len(), text_rows);
+    let panel_style = CellStyle { fg: Some(GUTTER_FG), ..CellStyle::default() };
+    let top = text_rows - rows;
+    for (offset, line) in panel_lines[..rows].iter().enumerate() {
+        frame.fill_row(0, top + offset, Cell { ch: ' ', style: panel_style });
+        let style = match line.chars().next() {
+            Some('+') => CellStyle { fg: Some(ADDED_FG), ..panel_style },
+            Some('-') => CellStyle { fg: Some(DELETED_FG), ..panel_style },
+            _ => panel_style,
+        };
+        frame.put_str(0, top + offset, line, style);
+    }
+}
+
+fn draw_status_line(frame: &mut ScreenBuffer, state: &EditorState) {
     //  Editing: {{file_name}} | Mode: Navigate                 Ln 66, Col 68 | Python
 
     let mode_input_buf: String = state.get_mode_input_buffer().into_iter().collect();
-    let mode_text = format!("{} ({})", state.get_mode(), mode_input_buf);
-    let left_side = format!(
-        "Editing: {file_name} | Mode: {mode} | Modifier: {state}",
-        file_name=state.get_active_file_name(),
-        mode=mode_text,
-        state=state.expression_state
-    );
+    let mut mode_text = format!("{} ({})", state.get_mode(), mode_input_buf);
+    if let Some((name, _)) = state.recording {
+        mode_text.push_str(&format!(" recording @{}", name));
+    }
+    // A status message temporarily takes the place of the left hand side
+    let left_side = match state.get_status_message() {
+        Some((_, message)) => message.to_string(),
+        None => format!(
+            "Editing: {file_name} | Mode: {mode} | Modifier: {state}",
+            file_name=state.get_active_file_name(),
+            mode=mode_text,
+            state=state.expression_state
+        ),
+    };
 
     let cursor_pos = state.get_cursor_position();
     let right_side = format!(
-        "Ln {ln}, Col {col}, Idx {idx} | {file_type}",
+        "{pending:<10} Ln {ln}, Col {col}, Idx {idx} | {file_type}",
+        pending=state.pending_keys,
         ln=cursor_pos.y + 1,
         col=cursor_pos.x + 1,
         idx=state.cursor_index,
@@ -85,27 +437,16 @@ fn draw_status_line<W: Write>(out: &mut W, term_height: usize, term_width: usize
     );
 
     // Make the right hand side take up the remaining width of the terminal
-    let cols_remaining_after_left = term_width - left_side.len();
-    write!(
-        out,
-        "{goto_bottom}{clear_line}{fg}{bg}{lhs}{rhs:>pad$}{reset}",
-        goto_bottom=cursor::Goto(1, term_height as u16),
-        clear_line=clear::CurrentLine,
-        fg=color::Fg(color::Rgb(95, 110, 109)),
-        bg=color::Bg(color::Rgb(8, 31, 40)),
-        lhs=left_side,
-        rhs=right_side,
-        pad=cols_remaining_after_left,
-        reset=style::Reset,
-    );
-}
+    let term_width = frame.width();
+    let y = frame.height().saturating_sub(1);
+    let status_style = CellStyle::new(GUTTER_FG, GUTTER_BG);
+    let cols_remaining_after_left = term_width.saturating_sub(left_side.len());
 
-fn draw_cursor<W: Write>(out: &mut W, state: &EditorState) {
-    let cursor_position = state.get_cursor_position();
-    let total_gutter_offset = GUTTER_WIDTH + GUTTER_RIGHT_MARGIN;
-    write!(
-        out,
-        "{}",
-        cursor::Goto((cursor_position.x + total_gutter_offset) as u16, cursor_position.y as u16 + 1),
+    frame.fill_row(0, y, Cell { ch: ' ', style: status_style });
+    frame.put_str(
+        0,
+        y,
+        &format!("{lhs}{rhs:>pad$}", lhs=left_side, rhs=right_side, pad=cols_remaining_after_left),
+        status_style
     );
 }